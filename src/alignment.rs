@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use audio::*;
+use fft::*;
+use math::*;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CorrelationMethod {
+    Standard,
+    /// Generalized cross-correlation with phase transform, whitens the cross spectrum
+    /// for a sharp peak on reverberant or coloured signals
+    Phat,
+}
+
+/// Cross-correlation over lags `-max_lag..max_lag + 1`, `values[max_lag]` is lag zero.
+/// A positive lag means `signal` is late compared to `reference`.
+pub struct CrossCorrelation {
+    pub values: Vec<f64>,
+    pub max_lag: usize,
+}
+
+pub struct LagEstimate {
+    /// Delay of the signal in samples with sub-sample precision
+    pub lag: f64,
+    pub seconds: f64,
+    /// Pearson correlation of the overlapping samples at the integer lag
+    pub coefficient: f64,
+}
+
+impl CrossCorrelation {
+    pub fn new(
+        reference: &[f64],
+        signal: &[f64],
+        method: CorrelationMethod,
+        max_lag: usize,
+    ) -> CrossCorrelation {
+        let length = next_power_of_two(reference.len() + signal.len());
+        let a = real_fft(reference, length);
+        let b = real_fft(signal, length);
+
+        let mut spectrum = Vec::with_capacity(length);
+        for k in 0..length {
+            let cross = a[k].conj() * b[k];
+            spectrum.push(match method {
+                CorrelationMethod::Standard => cross,
+                CorrelationMethod::Phat => cross.scale(1.0 / (cross.norm() + 1.0e-12)),
+            });
+        }
+        let circular = real_ifft(&spectrum);
+
+        // Empty or single sample input leaves only lag zero
+        let max_lag = min_u32(max_lag as u32, (length / 2).saturating_sub(1) as u32) as usize;
+        let mut values = Vec::with_capacity(2 * max_lag + 1);
+        for i in 0..2 * max_lag + 1 {
+            let lag = i as isize - max_lag as isize;
+            let index = if lag < 0 {
+                (length as isize + lag) as usize
+            } else {
+                lag as usize
+            };
+            values.push(circular[index]);
+        }
+
+        CrossCorrelation {
+            values: values,
+            max_lag: max_lag,
+        }
+    }
+
+    /// Lag of the largest value refined by a parabola through its neighbours
+    pub fn peak(&self) -> f64 {
+        let mut best = 0;
+        for i in 1..self.values.len() {
+            if self.values[i] > self.values[best] {
+                best = i;
+            }
+        }
+
+        let mut offset = 0.0;
+        if best > 0 && best + 1 < self.values.len() {
+            let (a, b, c) = (
+                self.values[best - 1],
+                self.values[best],
+                self.values[best + 1],
+            );
+            let denominator = a - 2.0 * b + c;
+            if abs_f64(denominator) > 1.0e-12 {
+                offset = clamp_f64(-0.5, 0.5 * (a - c) / denominator, 0.5);
+            }
+        }
+        best as f64 - self.max_lag as f64 + offset
+    }
+}
+
+/// Pearson correlation of `reference[n]` against `signal[n + lag]` where both exist
+pub fn correlation_coefficient(reference: &[f64], signal: &[f64], lag: isize) -> f64 {
+    let start = max_i32(0, -lag as i32) as isize;
+    let end = min_i32(reference.len() as i32, signal.len() as i32 - lag as i32) as isize;
+    if end - start < 2 {
+        return 0.0;
+    }
+
+    let count = (end - start) as f64;
+    let mut mean_a = 0.0;
+    let mut mean_b = 0.0;
+    for n in start..end {
+        mean_a += reference[n as usize];
+        mean_b += signal[(n + lag) as usize];
+    }
+    mean_a /= count;
+    mean_b /= count;
+
+    let mut cross = 0.0;
+    let mut energy_a = 0.0;
+    let mut energy_b = 0.0;
+    for n in start..end {
+        let a = reference[n as usize] - mean_a;
+        let b = signal[(n + lag) as usize] - mean_b;
+        cross += a * b;
+        energy_a += a * a;
+        energy_b += b * b;
+    }
+    if energy_a > 0.0 && energy_b > 0.0 {
+        cross / (energy_a * energy_b).sqrt()
+    } else {
+        0.0
+    }
+}
+
+/// Delay of `signal` compared to `reference`, searched within `max_lag` samples
+pub fn estimate_lag(
+    reference: &Waveform,
+    signal: &Waveform,
+    method: CorrelationMethod,
+    max_lag: usize,
+) -> LagEstimate {
+    let correlation = CrossCorrelation::new(&reference.samples, &signal.samples, method, max_lag);
+    let lag = correlation.peak();
+    let coefficient = correlation_coefficient(
+        &reference.samples,
+        &signal.samples,
+        round_f64_i32(lag) as isize,
+    );
+
+    LagEstimate {
+        lag: lag,
+        seconds: lag / reference.sample_rate,
+        coefficient: coefficient,
+    }
+}
+
+/// Moves `signal` earlier by `lag` samples (rounded) and trims or zero pads it to the
+/// length of `reference`, so both start together.
+pub fn align_to(reference: &Waveform, signal: &Waveform, lag: f64) -> Waveform {
+    let shift = round_f64_i32(lag) as isize;
+    let mut samples = Vec::with_capacity(reference.sample_count);
+    for n in 0..reference.sample_count as isize {
+        let index = n + shift;
+        samples.push(if index >= 0 && (index as usize) < signal.sample_count {
+            signal.samples[index as usize]
+        } else {
+            0.0
+        });
+    }
+    Waveform::from_samples(samples, signal.sample_rate)
+}
+
+/// Estimates the delay within `max_lag` and returns the aligned signal with the estimate
+pub fn align(
+    reference: &Waveform,
+    signal: &Waveform,
+    method: CorrelationMethod,
+    max_lag: usize,
+) -> (Waveform, LagEstimate) {
+    let estimate = estimate_lag(reference, signal, method, max_lag);
+    (align_to(reference, signal, estimate.lag), estimate)
+}
@@ -0,0 +1,682 @@
+#![allow(dead_code)]
+
+use audio::*;
+use canvas::*;
+use fft::*;
+use filter::hann_window;
+use math::*;
+use render::*;
+use tools::*;
+
+/// H1 estimate of response over stimulus, one value per FFT bin up to Nyquist
+pub struct TransferFunction {
+    pub frequencies: Vec<f64>,
+    pub magnitude_db: Vec<f64>,
+    /// Wrapped to -PI..PI
+    pub phase: Vec<f64>,
+    /// 0..1, how much of the response is linearly explained by the stimulus
+    pub coherence: Vec<f64>,
+    /// Seconds, from the unwrapped phase
+    pub group_delay: Vec<f64>,
+}
+
+pub struct TransferAnalyzer {
+    /// Welch segment length, a power of two
+    pub frame_size: usize,
+}
+
+pub struct DistortionAnalyzer {
+    /// Highest harmonic counted for THD
+    pub harmonics: usize,
+    /// Fundamental in Hz, None picks the largest spectral peak
+    pub fundamental: Option<f64>,
+    /// Analysis length cap, a power of two
+    pub frame_size: usize,
+}
+
+pub struct DistortionResult {
+    pub fundamental: f64,
+    pub fundamental_db: f64,
+    /// Levels of harmonics 2, 3, ... relative to the fundamental
+    pub harmonics_db: Vec<f64>,
+    /// Ratio of harmonic to fundamental amplitude
+    pub thd: f64,
+    pub thd_db: f64,
+    /// Ratio of everything but the fundamental to the fundamental amplitude
+    pub thd_n: f64,
+    pub thd_n_db: f64,
+    pub sinad_db: f64,
+}
+
+impl DistortionResult {
+    /// Result of a response too short to measure, every level is NaN
+    pub fn unmeasured() -> DistortionResult {
+        DistortionResult {
+            fundamental: f64::NAN,
+            fundamental_db: f64::NAN,
+            harmonics_db: Vec::new(),
+            thd: f64::NAN,
+            thd_db: f64::NAN,
+            thd_n: f64::NAN,
+            thd_n_db: f64::NAN,
+            sinad_db: f64::NAN,
+        }
+    }
+}
+
+/// Logarithmic sine sweep from `start_frequency` to `end_frequency`
+pub fn exponential_sweep(
+    start_frequency: f64,
+    end_frequency: f64,
+    duration: f64,
+    sample_rate: f64,
+) -> Waveform {
+    let count = (duration * sample_rate) as usize;
+    let rate = (end_frequency / start_frequency).ln();
+    let scale = 2.0 * PI * start_frequency * duration / rate;
+    let mut samples = Vec::with_capacity(count);
+    for i in 0..count {
+        let t = i as f64 / sample_rate;
+        samples.push((scale * ((t / duration * rate).exp() - 1.0)).sin());
+    }
+    Waveform::from_samples(samples, sample_rate)
+}
+
+/// Time reversed `sweep` with its amplitude falling 6 dB per octave, the sweep spends time
+/// in proportion to 1 / f and the envelope evens that out. Convolving the sweep with it
+/// gives an impulse over the swept band.
+pub fn inverse_sweep(sweep: &Waveform, start_frequency: f64, end_frequency: f64) -> Waveform {
+    let count = sweep.sample_count;
+    let duration = count as f64 / sweep.sample_rate;
+    let rate = (end_frequency / start_frequency).ln();
+    let mut samples = Vec::with_capacity(count);
+    for i in 0..count {
+        let n = count - 1 - i;
+        let t = n as f64 / sweep.sample_rate;
+        // Instantaneous frequency of the sweep over the end frequency
+        let envelope = (rate * (t / duration - 1.0)).exp();
+        samples.push(sweep.samples[n] * envelope);
+    }
+    Waveform::from_samples(samples, sweep.sample_rate)
+}
+
+/// Sine steps of `step_duration` seconds each, one per frequency
+pub fn stepped_sine(frequencies: &[f64], step_duration: f64, sample_rate: f64) -> Waveform {
+    let step = (step_duration * sample_rate) as usize;
+    let mut samples = Vec::with_capacity(step * frequencies.len());
+    for frequency in frequencies.iter() {
+        for i in 0..step {
+            samples.push((2.0 * PI * frequency * i as f64 / sample_rate).sin());
+        }
+    }
+    Waveform::from_samples(samples, sample_rate)
+}
+
+/// 4 term Blackman-Harris, sidelobes below -92 dB
+fn blackman_harris(n: usize, length: usize) -> f64 {
+    let x = 2.0 * PI * n as f64 / length as f64;
+    0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos() - 0.01168 * (3.0 * x).cos()
+}
+
+fn largest_power_of_two(count: usize) -> usize {
+    let mut result = 1;
+    while result * 2 <= count {
+        result *= 2;
+    }
+    result
+}
+
+/// 1 over the inner half of `extent`, a cosine taper to 0 over the outer half
+fn flat_taper(offset: f64, extent: f64) -> f64 {
+    let x = abs_f64(offset) / extent;
+    if x <= 0.5 {
+        1.0
+    } else if x >= 1.0 {
+        0.0
+    } else {
+        0.5 * (1.0 + (PI * (2.0 * x - 1.0)).cos())
+    }
+}
+
+fn to_db(ratio: f64) -> f64 {
+    if ratio > 0.0 {
+        volume_to_db(ratio)
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+impl TransferAnalyzer {
+    pub fn new() -> TransferAnalyzer {
+        TransferAnalyzer { frame_size: 8192 }
+    }
+
+    /// Averages cross and auto spectra over half overlapping Hann segments. The response
+    /// should be aligned to the stimulus within a fraction of `frame_size`.
+    pub fn analyze(&self, stimulus: &Waveform, response: &Waveform) -> TransferFunction {
+        let size = self.frame_size;
+        let bins = size / 2 + 1;
+        let hop = size / 2;
+        let count = min_u32(stimulus.sample_count as u32, response.sample_count as u32) as usize;
+
+        let mut auto_x = vec![0.0; bins];
+        let mut auto_y = vec![0.0; bins];
+        let mut cross = vec![Complex::ZERO; bins];
+
+        let mut start = 0;
+        while start + size <= max_u32(count as u32, size as u32) as usize {
+            let segment = |samples: &[f64]| {
+                let mut data = vec![Complex::ZERO; size];
+                for n in 0..size {
+                    if start + n < count {
+                        data[n].re = samples[start + n] * hann_window(n, size);
+                    }
+                }
+                fft(&mut data, false);
+                data
+            };
+            let x = segment(&stimulus.samples);
+            let y = segment(&response.samples);
+            for k in 0..bins {
+                auto_x[k] += x[k].norm_sq();
+                auto_y[k] += y[k].norm_sq();
+                cross[k] = cross[k] + x[k].conj() * y[k];
+            }
+            start += hop;
+        }
+
+        let mut result = TransferFunction {
+            frequencies: Vec::with_capacity(bins),
+            magnitude_db: Vec::with_capacity(bins),
+            phase: Vec::with_capacity(bins),
+            coherence: Vec::with_capacity(bins),
+            group_delay: Vec::with_capacity(bins),
+        };
+
+        let floor = 1.0e-20;
+        let mut unwrapped = Vec::with_capacity(bins);
+        for k in 0..bins {
+            let h = cross[k].scale(1.0 / (auto_x[k] + floor));
+            let phase = h.arg();
+            result
+                .frequencies
+                .push(k as f64 * stimulus.sample_rate / size as f64);
+            result.magnitude_db.push(to_db(h.norm()));
+            result.phase.push(phase);
+            result.coherence.push(clamp01_f64(
+                cross[k].norm_sq() / (auto_x[k] * auto_y[k] + floor),
+            ));
+
+            let value = if k == 0 {
+                phase
+            } else {
+                let previous: f64 = unwrapped[k - 1];
+                let mut delta = phase - result.phase[k - 1];
+                while delta > PI {
+                    delta -= 2.0 * PI;
+                }
+                while delta < -PI {
+                    delta += 2.0 * PI;
+                }
+                previous + delta
+            };
+            unwrapped.push(value);
+        }
+
+        let bin_omega = 2.0 * PI * stimulus.sample_rate / size as f64;
+        for k in 0..bins {
+            let (low, high) = if k == 0 {
+                (0, 1)
+            } else if k + 1 == bins {
+                (k - 1, k)
+            } else {
+                (k - 1, k + 1)
+            };
+            let delay = -(unwrapped[high] - unwrapped[low]) / ((high - low) as f64 * bin_omega);
+            result.group_delay.push(delay);
+        }
+        result
+    }
+}
+
+impl TransferFunction {
+    pub fn magnitude_image(&self, width: i32, height: i32, color: Color) -> Image {
+        Image::log_frequency_plot(
+            width,
+            height,
+            &self.frequencies,
+            &self.magnitude_db,
+            (-60.0, 12.0),
+            6.0,
+            color,
+        )
+    }
+
+    /// Phase in degrees
+    pub fn phase_image(&self, width: i32, height: i32, color: Color) -> Image {
+        let degrees: Vec<f64> = self.phase.iter().map(|p| p * 180.0 / PI).collect();
+        Image::log_frequency_plot(
+            width,
+            height,
+            &self.frequencies,
+            &degrees,
+            (-180.0, 180.0),
+            45.0,
+            color,
+        )
+    }
+
+    pub fn coherence_image(&self, width: i32, height: i32, color: Color) -> Image {
+        Image::log_frequency_plot(
+            width,
+            height,
+            &self.frequencies,
+            &self.coherence,
+            (0.0, 1.0),
+            0.1,
+            color,
+        )
+    }
+
+    /// Group delay in milliseconds within `range_ms` around zero
+    pub fn group_delay_image(&self, width: i32, height: i32, range_ms: f64, color: Color) -> Image {
+        let milliseconds: Vec<f64> = self.group_delay.iter().map(|d| d * 1000.0).collect();
+        Image::log_frequency_plot(
+            width,
+            height,
+            &self.frequencies,
+            &milliseconds,
+            (-range_ms, range_ms),
+            range_ms / 4.0,
+            color,
+        )
+    }
+}
+
+impl DistortionAnalyzer {
+    pub fn new() -> DistortionAnalyzer {
+        DistortionAnalyzer {
+            harmonics: 10,
+            fundamental: None,
+            frame_size: 65536,
+        }
+    }
+
+    /// Measures a steady sine response
+    pub fn analyze(&self, response: &[f64], sample_rate: f64) -> DistortionResult {
+        let size = largest_power_of_two(min_u32(
+            max_u32(response.len() as u32, 2),
+            self.frame_size as u32,
+        ) as usize);
+        let bins = size / 2 + 1;
+        // Main lobe of the window is 4 bins each side, a bit more covers leakage.
+        let lobe = 5;
+        // Nothing past the lobe around DC to take a fundamental from
+        if bins <= lobe + 1 {
+            return DistortionResult::unmeasured();
+        }
+        let mut data = vec![Complex::ZERO; size];
+        for n in 0..min_u32(size as u32, response.len() as u32) as usize {
+            data[n].re = response[n] * blackman_harris(n, size);
+        }
+        fft(&mut data, false);
+        let power: Vec<f64> = (0..bins).map(|k| data[k].norm_sq()).collect();
+        let bin_width = sample_rate / size as f64;
+
+        let band_power = |center: usize| {
+            let low = if center > lobe { center - lobe } else { 0 };
+            let high = min_u32((center + lobe + 1) as u32, bins as u32) as usize;
+            power[low..high].iter().sum::<f64>()
+        };
+        let peak_near = |frequency: f64| {
+            let center = round_f64_u32(frequency / bin_width) as usize;
+            let low = if center > 2 { center - 2 } else { 0 };
+            let high = min_u32((center + 3) as u32, bins as u32) as usize;
+            let mut best = min_u32(center as u32, (bins - 1) as u32) as usize;
+            for k in low..high {
+                if power[k] > power[best] {
+                    best = k;
+                }
+            }
+            best
+        };
+
+        let fundamental_bin = match self.fundamental {
+            Some(frequency) => peak_near(frequency),
+            None => {
+                let mut best = lobe + 1;
+                for k in lobe + 1..bins {
+                    if power[k] > power[best] {
+                        best = k;
+                    }
+                }
+                best
+            }
+        };
+
+        let mut fundamental = fundamental_bin as f64 * bin_width;
+        if fundamental_bin > 0 && fundamental_bin + 1 < bins {
+            let (a, b, c) = (
+                power[fundamental_bin - 1].ln(),
+                power[fundamental_bin].ln(),
+                power[fundamental_bin + 1].ln(),
+            );
+            let denominator = a - 2.0 * b + c;
+            if abs_f64(denominator) > 1.0e-12 {
+                fundamental += 0.5 * (a - c) / denominator * bin_width;
+            }
+        }
+
+        let fundamental_power = band_power(fundamental_bin);
+        let total_power: f64 = power[lobe + 1..].iter().sum();
+
+        let mut harmonic_power = 0.0;
+        let mut harmonics_db = Vec::new();
+        for harmonic in 2..self.harmonics + 1 {
+            let frequency = fundamental * harmonic as f64;
+            if frequency >= sample_rate / 2.0 - lobe as f64 * bin_width {
+                break;
+            }
+            let value = band_power(peak_near(frequency));
+            harmonic_power += value;
+            harmonics_db.push(10.0 * (value / fundamental_power).log10());
+        }
+
+        let noise_power = max_f64(0.0, total_power - fundamental_power);
+        let thd = (harmonic_power / fundamental_power).sqrt();
+        let thd_n = (noise_power / fundamental_power).sqrt();
+
+        // Energy of the window, the lobe power of a sine is size * energy * amplitude^2 / 4.
+        let mut window_energy = 0.0;
+        for n in 0..size {
+            let w = blackman_harris(n, size);
+            window_energy += w * w;
+        }
+        let amplitude = 2.0 * (fundamental_power / (size as f64 * window_energy)).sqrt();
+
+        DistortionResult {
+            fundamental: fundamental,
+            fundamental_db: to_db(amplitude),
+            harmonics_db: harmonics_db,
+            thd: thd,
+            thd_db: to_db(thd),
+            thd_n: thd_n,
+            thd_n_db: to_db(thd_n),
+            sinad_db: -to_db(thd_n),
+        }
+    }
+
+    /// One result per step of a stepped sine response, the first quarter of each step is
+    /// skipped so transients settle.
+    pub fn analyze_steps(&self, response: &Waveform, step_duration: f64) -> Vec<DistortionResult> {
+        let step = (step_duration * response.sample_rate) as usize;
+        let mut result = Vec::new();
+        if step == 0 {
+            return result;
+        }
+        let mut start = 0;
+        while start + step <= response.sample_count {
+            let settled = start + step / 4;
+            result.push(self.analyze(
+                &response.samples[settled..start + step],
+                response.sample_rate,
+            ));
+            start += step;
+        }
+        result
+    }
+
+    /// Farina's swept sine method. `response` to `sweep`, an `exponential_sweep` from
+    /// `start_frequency` to `end_frequency`, is deconvolved with the inverse sweep, which
+    /// puts the impulse response of every harmonic ahead of the linear one. Each is windowed
+    /// out and one result is given per entry of `frequencies`, reading the spectrum of
+    /// harmonic k at k times the frequency. A sweep does not separate noise from the
+    /// harmonics, THD+N and SINAD are those of the harmonics alone.
+    pub fn analyze_sweep(
+        &self,
+        sweep: &Waveform,
+        response: &Waveform,
+        start_frequency: f64,
+        end_frequency: f64,
+        frequencies: &[f64],
+    ) -> Vec<DistortionResult> {
+        let count = sweep.sample_count;
+        let sample_rate = sweep.sample_rate;
+        if count < 2 || response.sample_count == 0 || end_frequency <= start_frequency {
+            return frequencies
+                .iter()
+                .map(|_| DistortionResult::unmeasured())
+                .collect();
+        }
+
+        let inverse = inverse_sweep(sweep, start_frequency, end_frequency);
+        let length = next_power_of_two(response.sample_count + count);
+        let stimulus_spectrum = real_fft(&sweep.samples, length);
+        let inverse_spectrum = real_fft(&inverse.samples, length);
+        let response_spectrum = real_fft(&response.samples, length);
+
+        // Gain of the sweep through its inverse over the octaves around the band center
+        let bin_width = sample_rate / length as f64;
+        let center = max_u32(
+            round_f64_u32((start_frequency * end_frequency).sqrt() / bin_width),
+            1,
+        ) as usize;
+        let high = min_u32((center * 2 + 1) as u32, (length / 2) as u32) as usize;
+        let mut gain = 0.0;
+        for k in center / 2..high {
+            gain += (stimulus_spectrum[k] * inverse_spectrum[k]).norm();
+        }
+        gain /= (high - center / 2) as f64;
+
+        let spectrum: Vec<Complex> = (0..length)
+            .map(|k| (response_spectrum[k] * inverse_spectrum[k]).scale(1.0 / gain))
+            .collect();
+        let impulse = real_ifft(&spectrum);
+
+        // The linear response arrives at the end of the sweep, harmonic k earlier by the
+        // time the sweep takes to rise by a factor of k.
+        let rise = count as f64 / (end_frequency / start_frequency).ln();
+        let arrival = |harmonic: usize| (count - 1) as f64 - rise * (harmonic as f64).ln();
+
+        // Magnitude spectrum of every harmonic's windowed impulse response, 1 is linear
+        let mut harmonic_spectra: Vec<Vec<f64>> = Vec::new();
+        for harmonic in 1..self.harmonics + 1 {
+            let before = (arrival(harmonic) - arrival(harmonic + 1)) / 2.0;
+            let after = if harmonic > 1 {
+                (arrival(harmonic - 1) - arrival(harmonic)) / 2.0
+            } else {
+                before
+            };
+            if before < 1.0 {
+                break;
+            }
+            let size = next_power_of_two((before + after) as usize + 1);
+            let peak = round_f64_i32(arrival(harmonic)) as isize;
+            let mut data = vec![Complex::ZERO; size];
+            for offset in -(before as isize)..after as isize + 1 {
+                let index = (peak + offset) % length as isize;
+                let index = if index < 0 {
+                    index + length as isize
+                } else {
+                    index
+                } as usize;
+                let extent = if offset < 0 { before } else { after };
+                let position = (offset + size as isize) as usize % size;
+                data[position].re = impulse[index] * flat_taper(offset as f64, extent);
+            }
+            fft(&mut data, false);
+            harmonic_spectra.push((0..size / 2 + 1).map(|k| data[k].norm()).collect());
+        }
+
+        let bin_of = |spectrum: &Vec<f64>, frequency: f64| {
+            let size = (spectrum.len() - 1) * 2;
+            let bin = round_f64_u32(frequency / sample_rate * size as f64) as usize;
+            min_u32(bin as u32, (spectrum.len() - 1) as u32) as usize
+        };
+
+        let mut result = Vec::with_capacity(frequencies.len());
+        for &frequency in frequencies.iter() {
+            if harmonic_spectra.len() == 0
+                || frequency < start_frequency
+                || frequency > end_frequency
+            {
+                result.push(DistortionResult::unmeasured());
+                continue;
+            }
+            let linear = &harmonic_spectra[0];
+            let fundamental = linear[bin_of(linear, frequency)];
+
+            let mut harmonic_power = 0.0;
+            let mut harmonics_db = Vec::new();
+            for (index, spectrum) in harmonic_spectra.iter().enumerate().skip(1) {
+                let harmonic_frequency = frequency * (index + 1) as f64;
+                if harmonic_frequency >= sample_rate / 2.0 {
+                    break;
+                }
+                let value = spectrum[bin_of(spectrum, harmonic_frequency)];
+                harmonic_power += value * value;
+                harmonics_db.push(to_db(value / fundamental));
+            }
+
+            let thd = harmonic_power.sqrt() / fundamental;
+            result.push(DistortionResult {
+                fundamental: frequency,
+                fundamental_db: to_db(fundamental),
+                harmonics_db: harmonics_db,
+                thd: thd,
+                thd_db: to_db(thd),
+                thd_n: thd,
+                thd_n_db: to_db(thd),
+                sinad_db: -to_db(thd),
+            });
+        }
+        result
+    }
+}
+
+/// THD+N of every step against its fundamental, on the same axes as the transfer plots
+pub fn thd_image(results: &[DistortionResult], width: i32, height: i32, color: Color) -> Image {
+    let frequencies: Vec<f64> = results.iter().map(|r| r.fundamental).collect();
+    let values: Vec<f64> = results.iter().map(|r| r.thd_n_db).collect();
+    Image::log_frequency_plot(
+        width,
+        height,
+        &frequencies,
+        &values,
+        (-120.0, 0.0),
+        10.0,
+        color,
+    )
+}
+
+impl Image {
+    /// Plots `values` against `frequencies` on a 20 Hz..20 kHz log axis. Vertical grid lines
+    /// at every decade and its multiples, horizontal ones every `grid_step`.
+    pub fn log_frequency_plot(
+        width: i32,
+        height: i32,
+        frequencies: &[f64],
+        values: &[f64],
+        range: (f64, f64),
+        grid_step: f64,
+        color: Color,
+    ) -> Image {
+        let mut image = Image::new(width, height);
+        plot_log_frequency(&mut image, frequencies, values, range, grid_step, color);
+        image
+    }
+}
+
+fn frequency_label(frequency: f64) -> String {
+    if frequency >= 1000.0 {
+        format!("{0}k", frequency / 1000.0)
+    } else {
+        format!("{0}", frequency)
+    }
+}
+
+/// See `Image::log_frequency_plot`, decades are labelled along the bottom edge
+pub fn plot_log_frequency(
+    canvas: &mut Canvas,
+    frequencies: &[f64],
+    values: &[f64],
+    range: (f64, f64),
+    grid_step: f64,
+    color: Color,
+) {
+    let size = canvas.size();
+    let (width, height) = (size.x as i32, size.y as i32);
+    let (min_value, max_value) = range;
+    let (min_frequency, max_frequency) = (20.0f64, 20000.0f64);
+    let decades = (max_frequency / min_frequency).log10();
+    let (r, g, b, _) = color.separate();
+    let major_color = Color::from_rgba(r, g, b, 80);
+    let minor_color = Color::from_rgba(r, g, b, 32);
+    let label_color = Color::from_rgba(r, g, b, 160);
+    let label_size = 8.0;
+
+    let to_x = |frequency: f64| (frequency / min_frequency).log10() / decades * (width - 1) as f64;
+    let to_y = |value: f64| {
+        let t = (value - min_value) / (max_value - min_value);
+        (height - 1) as f64 * (1.0 - clamp01_f64(t))
+    };
+
+    let mut decade = 10.0;
+    while decade < max_frequency {
+        for multiple in 1..10 {
+            let frequency = decade * multiple as f64;
+            if frequency < min_frequency || frequency > max_frequency {
+                continue;
+            }
+            let x = to_x(frequency);
+            let grid_color = if multiple == 1 {
+                major_color
+            } else {
+                minor_color
+            };
+            canvas.line(
+                &Vector2::new(x, 0.0),
+                &Vector2::new(x, (height - 1) as f64),
+                grid_color,
+            );
+            if multiple == 1 {
+                canvas.text(
+                    &Vector2::new(x + 2.0, height as f64 - label_size - 1.0),
+                    &frequency_label(frequency),
+                    label_size,
+                    label_color,
+                );
+            }
+        }
+        decade *= 10.0;
+    }
+
+    if grid_step > 0.0 {
+        let mut value = (min_value / grid_step).ceil() * grid_step;
+        while value <= max_value {
+            let y = to_y(value);
+            let grid_color = if abs_f64(value) < grid_step * 0.5 {
+                major_color
+            } else {
+                minor_color
+            };
+            canvas.line(
+                &Vector2::new(0.0, y),
+                &Vector2::new((width - 1) as f64, y),
+                grid_color,
+            );
+            value += grid_step;
+        }
+    }
+
+    let mut points: Vec<Vector2> = Vec::new();
+    for (frequency, value) in frequencies.iter().zip(values.iter()) {
+        if *frequency < min_frequency || *frequency > max_frequency || !value.is_finite() {
+            canvas.polyline(&points, color);
+            points.clear();
+            continue;
+        }
+        points.push(Vector2::new(to_x(*frequency), to_y(*value)));
+    }
+    canvas.polyline(&points, color);
+}
@@ -3,6 +3,16 @@
 use math::*;
 use random::*;
 use std::f64;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+/// Half open range of sample indices
+#[derive(Clone, Copy, PartialEq)]
+pub struct SampleRange {
+    pub start: usize,
+    pub end: usize,
+}
 
 pub struct Waveform {
     pub samples: Box<[f64]>,
@@ -12,7 +22,42 @@ pub struct Waveform {
     pub points: Box<[Vector2]>,
 }
 
+impl SampleRange {
+    pub fn new(start: usize, end: usize) -> SampleRange {
+        SampleRange {
+            start: start,
+            end: end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        if self.end > self.start {
+            self.end - self.start
+        } else {
+            0
+        }
+    }
+}
+
 impl Waveform {
+    pub fn from_samples(samples: Vec<f64>, sample_rate: f64) -> Waveform {
+        let sample_count = samples.len();
+        let mut pts: Vec<Vector2> = Vec::with_capacity(sample_count);
+
+        for i in 0..sample_count {
+            let x = i as f64 / sample_count as f64;
+            let y = samples[i];
+            pts.push(Vector2::new(x, y));
+        }
+
+        Waveform {
+            samples: samples.into_boxed_slice(),
+            sample_count: sample_count,
+            sample_rate: sample_rate,
+            points: pts.into_boxed_slice(),
+        }
+    }
+
     pub fn noise(sample_count: usize, sample_rate: f64) -> Waveform {
         let mut data = vec![0.0; sample_count].into_boxed_slice();
         let mut pts: Vec<Vector2> = Vec::with_capacity(sample_count);
@@ -73,4 +118,54 @@ impl Waveform {
             points: pts.into_boxed_slice(),
         }
     }
+
+    /// Writes the samples as a mono 16 bit PCM WAV file
+    pub fn write_wav(&self, path: &str) -> io::Result<()> {
+        let data_size = (self.sample_count * 2) as u32;
+        let sample_rate = round_f64_u32(self.sample_rate);
+        let mut data: Vec<u8> = Vec::with_capacity(44 + data_size as usize);
+
+        data.extend_from_slice(b"RIFF");
+        push_u32(&mut data, 36 + data_size);
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(b"fmt ");
+        push_u32(&mut data, 16);
+        push_u16(&mut data, 1);
+        push_u16(&mut data, 1);
+        push_u32(&mut data, sample_rate);
+        push_u32(&mut data, sample_rate * 2);
+        push_u16(&mut data, 2);
+        push_u16(&mut data, 16);
+        data.extend_from_slice(b"data");
+        push_u32(&mut data, data_size);
+
+        for i in 0..self.sample_count {
+            let value = round_f64_i32(clamp_f64(-1.0, self.samples[i], 1.0) * 32767.0);
+            push_u16(&mut data, value as i16 as u16);
+        }
+
+        let mut file = File::create(path)?;
+        file.write_all(&data)
+    }
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.push(value as u8);
+    data.push((value >> 8) as u8);
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    push_u16(data, value as u16);
+    push_u16(data, (value >> 16) as u16);
+}
+
+pub fn rms(samples: &[f64]) -> f64 {
+    if samples.len() == 0 {
+        return 0.0;
+    }
+    let mut sum = 0.0;
+    for value in samples.iter() {
+        sum += value * value;
+    }
+    square_root(sum / samples.len() as f64)
 }
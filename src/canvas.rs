@@ -0,0 +1,549 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use font::*;
+use math::*;
+use raster::*;
+use render::*;
+
+thread_local!(static BUILTIN_FONT: Font = Font::builtin());
+
+/// Drawing backend for the plots. Coordinates are pixels from the top left corner and
+/// lines are one pixel wide. The raster lines stop one pixel short of their end point.
+pub trait Canvas {
+    fn size(&self) -> Vector2;
+    fn line(&mut self, start: &Vector2, end: &Vector2, color: Color);
+    /// Filled axis aligned rectangle
+    fn rect(&mut self, position: &Vector2, size: &Vector2, color: Color);
+    /// `position` is the top left corner of the first character. Every backend lays the
+    /// text out in the metrics of the built in font so the layouts match, whatever `size`.
+    fn text(&mut self, position: &Vector2, text: &str, size: f64, color: Color);
+    /// Filled with non-zero winding, the last point connects back to the first.
+    /// Unlike lines the points are corners of the covered area, not pixel centers.
+    fn polygon(&mut self, points: &[Vector2], color: Color);
+
+    fn polyline(&mut self, points: &[Vector2], color: Color) {
+        for i in 1..points.len() {
+            self.line(&points[i - 1], &points[i], color);
+        }
+    }
+}
+
+impl Canvas for Image {
+    fn size(&self) -> Vector2 {
+        Vector2::new(self.width as f64, self.height as f64)
+    }
+
+    fn line(&mut self, start: &Vector2, end: &Vector2, color: Color) {
+        self.draw_line(start, end, color);
+    }
+
+    fn rect(&mut self, position: &Vector2, size: &Vector2, color: Color) {
+        self.draw_rect(position, size, color);
+    }
+
+    /// Always the built in font, whatever the size
+    fn text(&mut self, position: &Vector2, text: &str, _size: f64, color: Color) {
+        BUILTIN_FONT.with(|font| self.draw_text(font, text, position, color));
+    }
+
+    fn polygon(&mut self, points: &[Vector2], color: Color) {
+        self.fill_polygon(points, FillRule::NonZero, color);
+    }
+}
+
+/// Antialiased lines on an image. Lines go through the centres of the pixels at their
+/// end points so the plots line up with the ones drawn on `Image` directly.
+pub struct SmoothCanvas<'a> {
+    pub image: &'a mut Image,
+    pub stroke: Stroke,
+}
+
+impl<'a> SmoothCanvas<'a> {
+    pub fn new(image: &'a mut Image, stroke: Stroke) -> SmoothCanvas<'a> {
+        SmoothCanvas {
+            image: image,
+            stroke: stroke,
+        }
+    }
+
+    fn center(point: &Vector2) -> Vector2 {
+        Vector2::new(point.x + 0.5, point.y + 0.5)
+    }
+}
+
+impl<'a> Canvas for SmoothCanvas<'a> {
+    fn size(&self) -> Vector2 {
+        self.image.size()
+    }
+
+    fn line(&mut self, start: &Vector2, end: &Vector2, color: Color) {
+        let points = [SmoothCanvas::center(start), SmoothCanvas::center(end)];
+        if self.stroke.width == 1.0 && self.stroke.cap == LineCap::Butt {
+            self.image.draw_line_aa(&points[0], &points[1], color);
+        } else {
+            self.image.draw_stroke(&points, &self.stroke, color);
+        }
+    }
+
+    fn rect(&mut self, position: &Vector2, size: &Vector2, color: Color) {
+        self.image.draw_rect(position, size, color);
+    }
+
+    fn text(&mut self, position: &Vector2, text: &str, size: f64, color: Color) {
+        self.image.text(position, text, size, color);
+    }
+
+    fn polygon(&mut self, points: &[Vector2], color: Color) {
+        self.image.fill_polygon(points, FillRule::NonZero, color);
+    }
+
+    /// Blended once, overlapping segments do not darken where they meet
+    fn polyline(&mut self, points: &[Vector2], color: Color) {
+        let centers: Vec<Vector2> = points.iter().map(SmoothCanvas::center).collect();
+        self.image.draw_stroke(&centers, &self.stroke, color);
+    }
+}
+
+/// Draws into another canvas shifted by `offset`, for placing a plot inside a larger view
+pub struct Translated<'a> {
+    pub canvas: &'a mut Canvas,
+    pub offset: Vector2,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl<'a> Translated<'a> {
+    pub fn new(canvas: &'a mut Canvas, offset: Vector2, width: f64, height: f64) -> Translated<'a> {
+        Translated {
+            canvas: canvas,
+            offset: offset,
+            width: width,
+            height: height,
+        }
+    }
+
+    fn shift(&self, point: &Vector2) -> Vector2 {
+        Vector2::new(point.x + self.offset.x, point.y + self.offset.y)
+    }
+}
+
+impl<'a> Canvas for Translated<'a> {
+    fn size(&self) -> Vector2 {
+        Vector2::new(self.width, self.height)
+    }
+
+    fn line(&mut self, start: &Vector2, end: &Vector2, color: Color) {
+        let (start, end) = (self.shift(start), self.shift(end));
+        self.canvas.line(&start, &end, color);
+    }
+
+    fn rect(&mut self, position: &Vector2, size: &Vector2, color: Color) {
+        let position = self.shift(position);
+        self.canvas.rect(&position, size, color);
+    }
+
+    fn text(&mut self, position: &Vector2, text: &str, size: f64, color: Color) {
+        let position = self.shift(position);
+        self.canvas.text(&position, text, size, color);
+    }
+
+    fn polygon(&mut self, points: &[Vector2], color: Color) {
+        let shifted: Vec<Vector2> = points.iter().map(|point| self.shift(point)).collect();
+        self.canvas.polygon(&shifted, color);
+    }
+
+    fn polyline(&mut self, points: &[Vector2], color: Color) {
+        let shifted: Vec<Vector2> = points.iter().map(|point| self.shift(point)).collect();
+        self.canvas.polyline(&shifted, color);
+    }
+}
+
+/// Ascent, line height and width of `text` set in the built in font
+fn builtin_metrics(text: &str) -> (f64, f64, f64) {
+    BUILTIN_FONT.with(|font| {
+        (
+            font.ascent as f64,
+            font.line_height() as f64,
+            font.line_width(text) as f64,
+        )
+    })
+}
+
+fn format_number(value: f64) -> String {
+    let text = format!("{0:.2}", value);
+    let text = text.trim_right_matches('0').trim_right_matches('.');
+    if text == "-0" {
+        String::from("0")
+    } else {
+        String::from(text)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Collects the drawing as SVG elements
+pub struct SvgCanvas {
+    pub width: f64,
+    pub height: f64,
+    pub elements: Vec<String>,
+}
+
+impl SvgCanvas {
+    pub fn new(width: f64, height: f64) -> SvgCanvas {
+        SvgCanvas {
+            width: width,
+            height: height,
+            elements: Vec::new(),
+        }
+    }
+
+    fn paint(kind: &str, color: Color) -> String {
+        let (r, g, b, a) = color.separate();
+        let mut paint = format!("{0}=\"#{1:02x}{2:02x}{3:02x}\"", kind, r, g, b);
+        if a < 255 {
+            paint += &format!(
+                " {0}-opacity=\"{1}\"",
+                kind,
+                format_number(a as f64 / 255.0)
+            );
+        }
+        paint
+    }
+
+    pub fn to_svg(&self) -> String {
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+             viewBox=\"0 0 {0} {1}\">\n",
+            format_number(self.width),
+            format_number(self.height)
+        );
+        svg += "<g stroke-width=\"1\" stroke-linecap=\"butt\" fill=\"none\">\n";
+        for element in self.elements.iter() {
+            svg += element;
+            svg.push('\n');
+        }
+        svg += "</g>\n</svg>\n";
+        svg
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_svg().as_bytes())
+    }
+}
+
+// Strokes go through pixel centers so they cover the same pixels as the raster lines.
+impl Canvas for SvgCanvas {
+    fn size(&self) -> Vector2 {
+        Vector2::new(self.width, self.height)
+    }
+
+    fn line(&mut self, start: &Vector2, end: &Vector2, color: Color) {
+        let element = format!(
+            "<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{3}\" {4}/>",
+            format_number(start.x + 0.5),
+            format_number(start.y + 0.5),
+            format_number(end.x + 0.5),
+            format_number(end.y + 0.5),
+            SvgCanvas::paint("stroke", color)
+        );
+        self.elements.push(element);
+    }
+
+    fn rect(&mut self, position: &Vector2, size: &Vector2, color: Color) {
+        let element = format!(
+            "<rect x=\"{0}\" y=\"{1}\" width=\"{2}\" height=\"{3}\" {4}/>",
+            format_number(position.x),
+            format_number(position.y),
+            format_number(size.x),
+            format_number(size.y),
+            SvgCanvas::paint("fill", color)
+        );
+        self.elements.push(element);
+    }
+
+    fn text(&mut self, position: &Vector2, text: &str, _size: f64, color: Color) {
+        let (ascent, line_height, width) = builtin_metrics(text);
+        if width <= 0.0 {
+            return;
+        }
+        let element = format!(
+            "<text x=\"{0}\" y=\"{1}\" font-family=\"monospace\" font-size=\"{2}\" \
+             textLength=\"{3}\" lengthAdjust=\"spacingAndGlyphs\" {4}>{5}</text>",
+            format_number(position.x),
+            format_number(position.y + ascent),
+            format_number(line_height),
+            format_number(width),
+            SvgCanvas::paint("fill", color),
+            escape_xml(text)
+        );
+        self.elements.push(element);
+    }
+
+    fn polygon(&mut self, points: &[Vector2], color: Color) {
+        if points.len() < 3 {
+            return;
+        }
+        let coordinates: Vec<String> = points
+            .iter()
+            .map(|point| format!("{0},{1}", format_number(point.x), format_number(point.y)))
+            .collect();
+        let element = format!(
+            "<polygon points=\"{0}\" {1}/>",
+            coordinates.join(" "),
+            SvgCanvas::paint("fill", color)
+        );
+        self.elements.push(element);
+    }
+
+    fn polyline(&mut self, points: &[Vector2], color: Color) {
+        if points.len() < 2 {
+            return;
+        }
+        let coordinates: Vec<String> = points
+            .iter()
+            .map(|point| {
+                format!(
+                    "{0},{1}",
+                    format_number(point.x + 0.5),
+                    format_number(point.y + 0.5)
+                )
+            })
+            .collect();
+        let element = format!(
+            "<polyline points=\"{0}\" stroke-linejoin=\"miter\" {1}/>",
+            coordinates.join(" "),
+            SvgCanvas::paint("stroke", color)
+        );
+        self.elements.push(element);
+    }
+}
+
+/// Single page PDF with the drawing in one content stream. Text uses the built in
+/// Courier font, transparency one graphics state per alpha value.
+pub struct PdfCanvas {
+    pub width: f64,
+    pub height: f64,
+    pub content: String,
+    pub alphas: Vec<u32>,
+    stroke: Option<u32>,
+    fill: Option<u32>,
+    alpha: Option<u32>,
+}
+
+impl PdfCanvas {
+    pub fn new(width: f64, height: f64) -> PdfCanvas {
+        PdfCanvas {
+            width: width,
+            height: height,
+            content: String::from("1 w 0 J 0 j\n"),
+            alphas: Vec::new(),
+            stroke: None,
+            fill: None,
+            alpha: None,
+        }
+    }
+
+    fn point(&self, point: &Vector2) -> String {
+        format!(
+            "{0} {1}",
+            format_number(point.x + 0.5),
+            format_number(self.height - point.y - 0.5)
+        )
+    }
+
+    fn set_alpha(&mut self, alpha: u32) {
+        if self.alpha == Some(alpha) {
+            return;
+        }
+        let index = match self.alphas.iter().position(|known| *known == alpha) {
+            Some(index) => index,
+            None => {
+                self.alphas.push(alpha);
+                self.alphas.len() - 1
+            }
+        };
+        self.content += &format!("/A{0} gs\n", index);
+        self.alpha = Some(alpha);
+    }
+
+    fn set_color(&mut self, color: Color, stroke: bool) {
+        let (r, g, b, a) = color.separate();
+        self.set_alpha(a);
+        let rgb = (r << 16) | (g << 8) | b;
+        let current = if stroke { self.stroke } else { self.fill };
+        if current == Some(rgb) {
+            return;
+        }
+        self.content += &format!(
+            "{0} {1} {2} {3}\n",
+            format_number(r as f64 / 255.0),
+            format_number(g as f64 / 255.0),
+            format_number(b as f64 / 255.0),
+            if stroke { "RG" } else { "rg" }
+        );
+        if stroke {
+            self.stroke = Some(rgb);
+        } else {
+            self.fill = Some(rgb);
+        }
+    }
+
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let font_id = 4;
+        let content_id = 5;
+        let first_state_id = 6;
+
+        let states: Vec<String> = (0..self.alphas.len())
+            .map(|index| format!("/A{0} {1} 0 R", index, first_state_id + index))
+            .collect();
+
+        let mut objects = vec![
+            String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+            String::from("<< /Type /Pages /Kids [3 0 R] /Count 1 >>"),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {0} {1}] \
+                 /Resources << /Font << /F1 {2} 0 R >> /ExtGState << {3} >> >> \
+                 /Contents {4} 0 R >>",
+                format_number(self.width),
+                format_number(self.height),
+                font_id,
+                states.join(" "),
+                content_id
+            ),
+            String::from("<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>"),
+            format!(
+                "<< /Length {0} >>\nstream\n{1}endstream",
+                self.content.len(),
+                self.content
+            ),
+        ];
+        for alpha in self.alphas.iter() {
+            let opacity = format_number(*alpha as f64 / 255.0);
+            objects.push(format!("<< /Type /ExtGState /CA {0} /ca {0} >>", opacity));
+        }
+
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf += &format!("{0} 0 obj\n{1}\nendobj\n", index + 1, object);
+        }
+        let xref_offset = pdf.len();
+        pdf += &format!("xref\n0 {0}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets.iter() {
+            pdf += &format!("{0:010} 00000 n \n", offset);
+        }
+        pdf += &format!(
+            "trailer\n<< /Size {0} /Root 1 0 R >>\nstartxref\n{1}\n%%EOF\n",
+            objects.len() + 1,
+            xref_offset
+        );
+        pdf.into_bytes()
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(&self.to_pdf())
+    }
+}
+
+impl Canvas for PdfCanvas {
+    fn size(&self) -> Vector2 {
+        Vector2::new(self.width, self.height)
+    }
+
+    fn line(&mut self, start: &Vector2, end: &Vector2, color: Color) {
+        self.polyline(&[*start, *end], color);
+    }
+
+    fn rect(&mut self, position: &Vector2, size: &Vector2, color: Color) {
+        self.set_color(color, false);
+        self.content += &format!(
+            "{0} {1} {2} {3} re f\n",
+            format_number(position.x),
+            format_number(self.height - position.y - size.y),
+            format_number(size.x),
+            format_number(size.y)
+        );
+    }
+
+    fn text(&mut self, position: &Vector2, text: &str, _size: f64, color: Color) {
+        let (ascent, line_height, width) = builtin_metrics(text);
+        if width <= 0.0 {
+            return;
+        }
+        self.set_color(color, false);
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '(' | ')' | '\\' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                ' '...'~' => escaped.push(c),
+                _ => escaped.push('?'),
+            }
+        }
+        // Courier advances 0.6 of the font size, scaled to the width of the raster text
+        let scale = width / (0.6 * line_height * text.chars().count() as f64) * 100.0;
+        self.content += &format!(
+            "BT /F1 {0} Tf {1} Tz {2} {3} Td ({4}) Tj ET\n",
+            format_number(line_height),
+            format_number(scale),
+            format_number(position.x),
+            format_number(self.height - position.y - ascent),
+            escaped
+        );
+    }
+
+    fn polygon(&mut self, points: &[Vector2], color: Color) {
+        if points.len() < 3 {
+            return;
+        }
+        self.set_color(color, false);
+        let height = self.height;
+        let corner = |point: &Vector2| {
+            format!(
+                "{0} {1}",
+                format_number(point.x),
+                format_number(height - point.y)
+            )
+        };
+        let mut path = format!("{0} m", corner(&points[0]));
+        for point in points[1..].iter() {
+            path += &format!(" {0} l", corner(point));
+        }
+        self.content += &path;
+        self.content += " h f\n";
+    }
+
+    fn polyline(&mut self, points: &[Vector2], color: Color) {
+        if points.len() < 2 {
+            return;
+        }
+        self.set_color(color, true);
+        let mut path = format!("{0} m", self.point(&points[0]));
+        for point in points[1..].iter() {
+            path += &format!(" {0} l", self.point(point));
+        }
+        self.content += &path;
+        self.content += " S\n";
+    }
+}
@@ -0,0 +1,277 @@
+#![allow(dead_code)]
+
+use audio::*;
+use fft::*;
+use filter::*;
+use math::*;
+
+/// One pole high-pass, y = x - x[n-1] + R * y[n-1]
+pub struct DcBlocker {
+    pub coefficient: f64,
+    last_input: f64,
+    last_output: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MainsFrequency {
+    Auto,
+    Hz50,
+    Hz60,
+}
+
+pub struct HumRemover {
+    pub mains: MainsFrequency,
+    /// Number of notches including the fundamental
+    pub harmonics: usize,
+    /// Q of the fundamental notch, harmonics keep the same bandwidth in Hz
+    pub q: f64,
+    /// Follow drift of the fundamental instead of using the nominal frequency
+    pub tracking: bool,
+    /// Largest drift from the nominal frequency the tracker looks for, in Hz
+    pub tracking_range: f64,
+    /// Length of the blocks the fundamental is re-estimated for, in seconds
+    pub block_duration: f64,
+}
+
+impl DcBlocker {
+    pub fn new(sample_rate: f64, cutoff: f64) -> DcBlocker {
+        DcBlocker {
+            coefficient: clamp_f64(0.0, 1.0 - 2.0 * PI * cutoff / sample_rate, 1.0),
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.last_input = 0.0;
+        self.last_output = 0.0;
+    }
+
+    #[inline(always)]
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = input - self.last_input + self.coefficient * self.last_output;
+        self.last_input = input;
+        self.last_output = output;
+        output
+    }
+
+    pub fn process_waveform(&mut self, wave: &Waveform) -> Waveform {
+        self.reset();
+        let mut samples = Vec::with_capacity(wave.sample_count);
+        for i in 0..wave.sample_count {
+            samples.push(self.process(wave.samples[i]));
+        }
+        Waveform::from_samples(samples, wave.sample_rate)
+    }
+}
+
+/// Mean value of the samples
+pub fn dc_offset(wave: &Waveform) -> f64 {
+    if wave.sample_count == 0 {
+        return 0.0;
+    }
+    let mut sum = 0.0;
+    for i in 0..wave.sample_count {
+        sum += wave.samples[i];
+    }
+    sum / wave.sample_count as f64
+}
+
+pub fn remove_dc_offset(wave: &Waveform) -> Waveform {
+    let offset = dc_offset(wave);
+    let mut samples = Vec::with_capacity(wave.sample_count);
+    for i in 0..wave.sample_count {
+        samples.push(wave.samples[i] - offset);
+    }
+    Waveform::from_samples(samples, wave.sample_rate)
+}
+
+impl MainsFrequency {
+    pub fn nominal(&self) -> f64 {
+        match *self {
+            MainsFrequency::Hz60 => 60.0,
+            _ => 50.0,
+        }
+    }
+}
+
+impl HumRemover {
+    pub fn new() -> HumRemover {
+        HumRemover {
+            mains: MainsFrequency::Auto,
+            harmonics: 8,
+            q: 30.0,
+            tracking: true,
+            tracking_range: 1.5,
+            block_duration: 0.5,
+        }
+    }
+
+    /// Power summed over the first few harmonics of each of `fundamentals`, all of them
+    /// measured in one pass over the samples
+    fn harmonic_powers(&self, samples: &[f64], sample_rate: f64, fundamentals: &[f64]) -> Vec<f64> {
+        let count = min_u32(self.harmonics as u32, 4) as usize;
+        let mut frequencies = Vec::with_capacity(fundamentals.len() * count);
+        for fundamental in fundamentals.iter() {
+            for harmonic in 1..count + 1 {
+                frequencies.push(fundamental * harmonic as f64);
+            }
+        }
+        let powers = goertzel_powers(samples, sample_rate, &frequencies);
+
+        let mut result = vec![0.0; fundamentals.len()];
+        for (index, frequency) in frequencies.iter().enumerate() {
+            if *frequency < sample_rate / 2.0 {
+                result[index / count] += powers[index];
+            }
+        }
+        result
+    }
+
+    /// Picks 50 or 60 Hz by comparing the hum energy in the first seconds of the file
+    pub fn detect_mains(&self, wave: &Waveform) -> MainsFrequency {
+        let length = min_u32(wave.sample_count as u32, (wave.sample_rate * 4.0) as u32) as usize;
+        let segment = windowed(&wave.samples[0..length]);
+
+        let powers = self.harmonic_powers(&segment, wave.sample_rate, &[50.0, 60.0]);
+        if powers[1] > powers[0] {
+            MainsFrequency::Hz60
+        } else {
+            MainsFrequency::Hz50
+        }
+    }
+
+    /// Fundamental with the most harmonic energy within `tracking_range` of `nominal`.
+    /// A coarse scan finds the peak and a parabola through its neighbours refines it.
+    pub fn estimate_fundamental(&self, samples: &[f64], sample_rate: f64, nominal: f64) -> f64 {
+        let segment = windowed(samples);
+        // The Hann main lobe of a window of T seconds is 4 / T Hz wide, steps of an
+        // eighth of that put three points on the peak.
+        let step = 0.5 * sample_rate / max_u32(1, samples.len() as u32) as f64;
+        let steps = max_u32(1, round_f64_u32(self.tracking_range / step)) as i32;
+        let candidates: Vec<f64> = (-steps..steps + 1)
+            .map(|i| nominal + i as f64 * step)
+            .collect();
+        let powers = self.harmonic_powers(&segment, sample_rate, &candidates);
+
+        let mut best = 0;
+        for i in 1..powers.len() {
+            if powers[i] > powers[best] {
+                best = i;
+            }
+        }
+        if powers[best] <= 0.0 {
+            return nominal;
+        }
+
+        let mut frequency = nominal + (best as i32 - steps) as f64 * step;
+        if best > 0 && best + 1 < powers.len() && powers[best - 1] > 0.0 && powers[best + 1] > 0.0 {
+            // The log of the lobe is close to a parabola
+            let (a, b, c) = (
+                powers[best - 1].ln(),
+                powers[best].ln(),
+                powers[best + 1].ln(),
+            );
+            let denominator = a - 2.0 * b + c;
+            if denominator < 0.0 {
+                frequency += clamp_f64(-0.5, 0.5 * (a - c) / denominator, 0.5) * step;
+            }
+        }
+        clamp_f64(
+            nominal - self.tracking_range,
+            frequency,
+            nominal + self.tracking_range,
+        )
+    }
+
+    fn notches(&self, sample_rate: f64, fundamental: f64) -> Vec<Biquad> {
+        let mut result = Vec::with_capacity(self.harmonics);
+        for harmonic in 1..self.harmonics + 1 {
+            let frequency = fundamental * harmonic as f64;
+            // Frequencies at or above Nyquist get a pass-through section so the
+            // cascade keeps the same length while tracking.
+            if frequency < sample_rate * 0.49 {
+                let q = self.q * harmonic as f64;
+                result.push(Biquad::notch(sample_rate, frequency, q));
+            } else {
+                result.push(Biquad::identity());
+            }
+        }
+        result
+    }
+
+    /// Fundamental used for each block, as used by `process`
+    pub fn track(&self, wave: &Waveform) -> Vec<f64> {
+        let mains = match self.mains {
+            MainsFrequency::Auto => self.detect_mains(wave),
+            other => other,
+        };
+        let nominal = mains.nominal();
+        let block = max_u32(1, (self.block_duration * wave.sample_rate) as u32) as usize;
+        let block_count = (wave.sample_count + block - 1) / block;
+
+        let mut result = Vec::with_capacity(block_count);
+        if !self.tracking {
+            for _ in 0..block_count {
+                result.push(nominal);
+            }
+            return result;
+        }
+
+        // Each estimate looks at a window twice the block length for a finer frequency
+        // resolution, then gets smoothed against the previous block.
+        let mut previous = nominal;
+        for index in 0..block_count {
+            let center = index * block + block / 2;
+            let start = if center > block { center - block } else { 0 };
+            let end = min_u32((center + block) as u32, wave.sample_count as u32) as usize;
+
+            let estimate =
+                self.estimate_fundamental(&wave.samples[start..end], wave.sample_rate, nominal);
+            let fundamental = if index == 0 {
+                estimate
+            } else {
+                lerp_f64(previous, 0.5, estimate)
+            };
+            result.push(fundamental);
+            previous = fundamental;
+        }
+        result
+    }
+
+    pub fn process(&self, wave: &Waveform) -> Waveform {
+        let block = max_u32(1, (self.block_duration * wave.sample_rate) as u32) as usize;
+        let fundamentals = self.track(wave);
+
+        let mut sections: Vec<Biquad> = Vec::new();
+        let mut samples = Vec::with_capacity(wave.sample_count);
+
+        for (index, fundamental) in fundamentals.iter().enumerate() {
+            let notches = self.notches(wave.sample_rate, *fundamental);
+            if sections.len() == 0 {
+                sections = notches;
+            } else {
+                for i in 0..sections.len() {
+                    sections[i].set_coefficients(&notches[i]);
+                }
+            }
+
+            let start = index * block;
+            let end = min_u32((start + block) as u32, wave.sample_count as u32) as usize;
+            for i in start..end {
+                samples.push(process_cascade(&mut sections, wave.samples[i]));
+            }
+        }
+
+        Waveform::from_samples(samples, wave.sample_rate)
+    }
+}
+
+fn windowed(samples: &[f64]) -> Vec<f64> {
+    let length = samples.len();
+    let mut result = Vec::with_capacity(length);
+    for i in 0..length {
+        result.push(samples[i] * hann_window(i, length));
+    }
+    result
+}
@@ -0,0 +1,878 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use math::*;
+use png::*;
+use render::*;
+use tools::*;
+
+/// Largest image a decoder accepts, guards against allocating for corrupt headers
+pub const MAX_IMAGE_PIXELS: i64 = 1 << 28;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+const BITMAP_FILE_HEADER_SIZE: usize = 14;
+const BITMAP_INFO_HEADER_SIZE: u32 = 40;
+const BITMAP_V4_HEADER_SIZE: u32 = 108;
+/// 'sRGB' as the V4 header color space
+const LCS_SRGB: u32 = 0x73524742;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+    Ppm,
+    Pgm,
+    Tga,
+}
+
+/// File header and DIB header of a BMP, the masks are only meaningful for bitfield images
+pub struct BitmapHeader {
+    pub file_type: u16,
+    pub file_size: u32,
+    pub reserved1: u16,
+    pub reserved2: u16,
+    pub bitmap_offset: u32,
+    pub size: u32,
+    pub width: i32,
+    pub height: i32,
+    pub planes: u16,
+    pub bits_per_pixel: u16,
+    pub compression: u32,
+    pub size_of_bitmap: u32,
+    pub horz_resolution: i32,
+    pub vert_resolution: i32,
+    pub colors_used: u32,
+    pub colors_important: u32,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub alpha_mask: u32,
+}
+
+pub fn invalid_image(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn check_dimensions(width: i64, height: i64) -> io::Result<()> {
+    if width <= 0 || height <= 0 || width * height > MAX_IMAGE_PIXELS {
+        Err(invalid_image("Invalid image dimensions"))
+    } else {
+        Ok(())
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    read_u16(data, offset) as u32 | (read_u16(data, offset + 2) as u32) << 16
+}
+
+fn push_u16(output: &mut Vec<u8>, value: u16) {
+    output.push(value as u8);
+    output.push((value >> 8) as u8);
+}
+
+fn push_u32(output: &mut Vec<u8>, value: u32) {
+    push_u16(output, value as u16);
+    push_u16(output, (value >> 16) as u16);
+}
+
+/// Maps `pixel & mask` to 0..255, `default` when the mask is empty
+fn masked_channel(pixel: u32, mask: u32, default: u32) -> u32 {
+    let scan = find_leastsignificant_setbit(mask);
+    if !scan.found {
+        return default;
+    }
+    let maximum = (mask >> scan.index) as u64;
+    ((((pixel & mask) >> scan.index) as u64 * 255) / maximum) as u32
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = match path.rfind('.') {
+            Some(dot) => path[dot + 1..].to_lowercase(),
+            None => return None,
+        };
+        match extension.as_str() {
+            "bmp" | "dib" => Some(ImageFormat::Bmp),
+            "png" => Some(ImageFormat::Png),
+            "ppm" | "pnm" => Some(ImageFormat::Ppm),
+            "pgm" => Some(ImageFormat::Pgm),
+            "tga" => Some(ImageFormat::Tga),
+            _ => None,
+        }
+    }
+
+    /// Format from the leading bytes, TGA has no signature and is never detected
+    pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+        if data.len() >= 8 && data[..8] == PNG_SIGNATURE {
+            Some(ImageFormat::Png)
+        } else if data.len() >= 2 && &data[..2] == b"BM" {
+            Some(ImageFormat::Bmp)
+        } else if data.len() >= 2 && data[0] == b'P' {
+            match data[1] {
+                b'2' | b'5' => Some(ImageFormat::Pgm),
+                b'3' | b'6' => Some(ImageFormat::Ppm),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Pgm => "pgm",
+            ImageFormat::Tga => "tga",
+        }
+    }
+}
+
+impl BitmapHeader {
+    pub fn read(data: &[u8]) -> io::Result<BitmapHeader> {
+        if data.len() < BITMAP_FILE_HEADER_SIZE + 12 || &data[..2] != b"BM" {
+            return Err(invalid_image("Not a BMP file"));
+        }
+        let size = read_u32(data, 14);
+        if data.len() < BITMAP_FILE_HEADER_SIZE + size as usize {
+            return Err(invalid_image("Truncated BMP header"));
+        }
+
+        let info = &data[BITMAP_FILE_HEADER_SIZE..];
+        let mut header = BitmapHeader {
+            file_type: read_u16(data, 0),
+            file_size: read_u32(data, 2),
+            reserved1: read_u16(data, 6),
+            reserved2: read_u16(data, 8),
+            bitmap_offset: read_u32(data, 10),
+            size: size,
+            width: 0,
+            height: 0,
+            planes: 1,
+            bits_per_pixel: 0,
+            compression: BI_RGB,
+            size_of_bitmap: 0,
+            horz_resolution: 0,
+            vert_resolution: 0,
+            colors_used: 0,
+            colors_important: 0,
+            red_mask: 0,
+            green_mask: 0,
+            blue_mask: 0,
+            alpha_mask: 0,
+        };
+
+        if size == 12 {
+            // OS/2 core header with 16 bit dimensions
+            header.width = read_u16(info, 4) as i32;
+            header.height = read_u16(info, 6) as i16 as i32;
+            header.planes = read_u16(info, 8);
+            header.bits_per_pixel = read_u16(info, 10);
+        } else if size >= BITMAP_INFO_HEADER_SIZE {
+            header.width = read_u32(info, 4) as i32;
+            header.height = read_u32(info, 8) as i32;
+            header.planes = read_u16(info, 12);
+            header.bits_per_pixel = read_u16(info, 14);
+            header.compression = read_u32(info, 16);
+            header.size_of_bitmap = read_u32(info, 20);
+            header.horz_resolution = read_u32(info, 24) as i32;
+            header.vert_resolution = read_u32(info, 28) as i32;
+            header.colors_used = read_u32(info, 32);
+            header.colors_important = read_u32(info, 36);
+        } else {
+            return Err(invalid_image("Unsupported BMP header"));
+        }
+
+        match header.compression {
+            BI_BITFIELDS | BI_ALPHABITFIELDS => {
+                // Masks live in the larger headers or follow the 40 byte one.
+                let with_alpha = header.compression == BI_ALPHABITFIELDS || size >= 56;
+                let needed = 40 + if with_alpha { 16 } else { 12 };
+                if info.len() < needed {
+                    return Err(invalid_image("Truncated BMP masks"));
+                }
+                header.red_mask = read_u32(info, 40);
+                header.green_mask = read_u32(info, 44);
+                header.blue_mask = read_u32(info, 48);
+                if with_alpha {
+                    header.alpha_mask = read_u32(info, 52);
+                }
+            }
+            BI_RGB => match header.bits_per_pixel {
+                16 => {
+                    header.red_mask = 0x7C00;
+                    header.green_mask = 0x03E0;
+                    header.blue_mask = 0x001F;
+                }
+                24 | 32 => {
+                    header.red_mask = 0x00FF0000;
+                    header.green_mask = 0x0000FF00;
+                    header.blue_mask = 0x000000FF;
+                }
+                _ => {}
+            },
+            _ => return Err(invalid_image("Compressed BMP files are not supported")),
+        }
+        Ok(header)
+    }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        push_u16(output, self.file_type);
+        push_u32(output, self.file_size);
+        push_u16(output, self.reserved1);
+        push_u16(output, self.reserved2);
+        push_u32(output, self.bitmap_offset);
+        push_u32(output, self.size);
+        push_u32(output, self.width as u32);
+        push_u32(output, self.height as u32);
+        push_u16(output, self.planes);
+        push_u16(output, self.bits_per_pixel);
+        push_u32(output, self.compression);
+        push_u32(output, self.size_of_bitmap);
+        push_u32(output, self.horz_resolution as u32);
+        push_u32(output, self.vert_resolution as u32);
+        push_u32(output, self.colors_used);
+        push_u32(output, self.colors_important);
+        if self.size >= BITMAP_V4_HEADER_SIZE {
+            push_u32(output, self.red_mask);
+            push_u32(output, self.green_mask);
+            push_u32(output, self.blue_mask);
+            push_u32(output, self.alpha_mask);
+            push_u32(output, LCS_SRGB);
+            // Endpoints and gamma are unused for sRGB.
+            for _ in 0..12 {
+                push_u32(output, 0);
+            }
+        }
+    }
+}
+
+/// Whitespace separated header fields of PPM and PGM files, `#` comments are skipped
+struct NetpbmReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> NetpbmReader<'a> {
+    fn number(&mut self) -> io::Result<u32> {
+        loop {
+            match self.data.get(self.position) {
+                Some(&b'#') => {
+                    while self.position < self.data.len() && self.data[self.position] != b'\n' {
+                        self.position += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                _ => break,
+            }
+        }
+        let start = self.position;
+        while self.position < self.data.len() && self.data[self.position].is_ascii_digit() {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(invalid_image("Invalid PPM/PGM number"));
+        }
+        let text = String::from_utf8_lossy(&self.data[start..self.position]);
+        text.parse::<u32>()
+            .map_err(|_| invalid_image("Invalid PPM/PGM number"))
+    }
+}
+
+impl Image {
+    /// BMP with 1, 4 or 8 bit palettes, 16, 24 or 32 bits per pixel and bitfield masks,
+    /// bottom up or top down
+    pub fn decode_bmp(data: &[u8]) -> io::Result<Image> {
+        let header = BitmapHeader::read(data)?;
+        let width = header.width as i64;
+        let height = (header.height as i64).abs();
+        check_dimensions(width, height)?;
+        let (width, height) = (width as usize, height as usize);
+        let top_down = header.height < 0;
+
+        let bits = header.bits_per_pixel as usize;
+        let mut palette = Vec::new();
+        if bits == 1 || bits == 2 || bits == 4 || bits == 8 {
+            let entry_size = if header.size == 12 { 3 } else { 4 };
+            let count = if header.colors_used > 0 {
+                header.colors_used as usize
+            } else {
+                1 << bits
+            };
+            let start = BITMAP_FILE_HEADER_SIZE + header.size as usize;
+            for i in 0..count {
+                let at = start + i * entry_size;
+                if at + 3 > data.len() {
+                    break;
+                }
+                palette.push(Color::from_rgba(
+                    data[at + 2] as u32,
+                    data[at + 1] as u32,
+                    data[at] as u32,
+                    255,
+                ));
+            }
+        } else if bits != 16 && bits != 24 && bits != 32 {
+            return Err(invalid_image("Unsupported BMP bit depth"));
+        }
+
+        let stride = (width * bits + 31) / 32 * 4;
+        let offset = header.bitmap_offset as usize;
+        if offset + stride * height > data.len() {
+            return Err(invalid_image("Truncated BMP pixel data"));
+        }
+
+        let mut image = Image::new(width as i32, height as i32);
+        for row in 0..height {
+            let y = if top_down { row } else { height - 1 - row };
+            let line = &data[offset + row * stride..offset + (row + 1) * stride];
+            for x in 0..width {
+                let color = match bits {
+                    1 | 2 | 4 | 8 => {
+                        let bit = x * bits;
+                        let shift = 8 - bits - bit % 8;
+                        let index = (line[bit / 8] as usize >> shift) & ((1 << bits) - 1);
+                        match palette.get(index) {
+                            Some(color) => *color,
+                            None => return Err(invalid_image("BMP palette index out of range")),
+                        }
+                    }
+                    _ => {
+                        let at = x * bits / 8;
+                        let pixel = match bits {
+                            16 => read_u16(line, at) as u32,
+                            24 => read_u16(line, at) as u32 | (line[at + 2] as u32) << 16,
+                            _ => read_u32(line, at),
+                        };
+                        Color::from_rgba(
+                            masked_channel(pixel, header.red_mask, 0),
+                            masked_channel(pixel, header.green_mask, 0),
+                            masked_channel(pixel, header.blue_mask, 0),
+                            masked_channel(pixel, header.alpha_mask, 255),
+                        )
+                    }
+                };
+                image.color_data[y * width + x] = color;
+            }
+        }
+        Ok(image)
+    }
+
+    /// Bottom up BMP, 24 bit without alpha or 32 bit with a V4 header and ARGB masks
+    pub fn encode_bmp(&self, bits_per_pixel: u16) -> Vec<u8> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let with_alpha = bits_per_pixel == 32;
+        let bytes = if with_alpha { 4 } else { 3 };
+        let stride = (width * bytes + 3) / 4 * 4;
+        let info_size = if with_alpha {
+            BITMAP_V4_HEADER_SIZE
+        } else {
+            BITMAP_INFO_HEADER_SIZE
+        };
+        let offset = BITMAP_FILE_HEADER_SIZE as u32 + info_size;
+
+        let header = BitmapHeader {
+            file_type: 0x4D42,
+            file_size: offset + (stride * height) as u32,
+            reserved1: 0,
+            reserved2: 0,
+            bitmap_offset: offset,
+            size: info_size,
+            width: self.width,
+            height: self.height,
+            planes: 1,
+            bits_per_pixel: bytes as u16 * 8,
+            compression: if with_alpha { BI_BITFIELDS } else { BI_RGB },
+            size_of_bitmap: (stride * height) as u32,
+            // 72 DPI
+            horz_resolution: 2835,
+            vert_resolution: 2835,
+            colors_used: 0,
+            colors_important: 0,
+            red_mask: 0x00FF0000,
+            green_mask: 0x0000FF00,
+            blue_mask: 0x000000FF,
+            alpha_mask: 0xFF000000,
+        };
+
+        let mut output = Vec::with_capacity(header.file_size as usize);
+        header.write(&mut output);
+        for row in (0..height).rev() {
+            let line_start = output.len();
+            for color in self.color_data[row * width..(row + 1) * width].iter() {
+                // Little endian ARGB words are stored as B, G, R, A.
+                let (r, g, b, a) = color.separate();
+                output.push(b as u8);
+                output.push(g as u8);
+                output.push(r as u8);
+                if with_alpha {
+                    output.push(a as u8);
+                }
+            }
+            while output.len() - line_start < stride {
+                output.push(0);
+            }
+        }
+        output
+    }
+
+    /// ASCII or binary PPM (P3, P6) and PGM (P2, P5) with up to 16 bits per sample
+    pub fn decode_netpbm(data: &[u8]) -> io::Result<Image> {
+        if data.len() < 2 || data[0] != b'P' {
+            return Err(invalid_image("Not a PPM/PGM file"));
+        }
+        let (channels, binary) = match data[1] {
+            b'2' => (1, false),
+            b'3' => (3, false),
+            b'5' => (1, true),
+            b'6' => (3, true),
+            _ => return Err(invalid_image("Unsupported PPM/PGM type")),
+        };
+
+        let mut reader = NetpbmReader {
+            data: data,
+            position: 2,
+        };
+        let width = reader.number()? as i64;
+        let height = reader.number()? as i64;
+        let max_value = reader.number()?;
+        check_dimensions(width, height)?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid_image("Invalid PPM/PGM maximum value"));
+        }
+
+        let count = (width * height) as usize * channels;
+        let mut samples = Vec::with_capacity(count);
+        if binary {
+            // Exactly one whitespace byte separates the header from the samples.
+            let start = reader.position + 1;
+            let sample_bytes = if max_value > 255 { 2 } else { 1 };
+            if start + count * sample_bytes > data.len() {
+                return Err(invalid_image("Truncated PPM/PGM data"));
+            }
+            for i in 0..count {
+                let at = start + i * sample_bytes;
+                samples.push(if sample_bytes == 2 {
+                    (data[at] as u32) << 8 | data[at + 1] as u32
+                } else {
+                    data[at] as u32
+                });
+            }
+        } else {
+            for _ in 0..count {
+                samples.push(reader.number()?);
+            }
+        }
+
+        let scale = |value: u32| (value.min(max_value) * 255 + max_value / 2) / max_value;
+        let mut image = Image::new(width as i32, height as i32);
+        for (index, color) in image.color_data.iter_mut().enumerate() {
+            *color = if channels == 3 {
+                let at = index * 3;
+                Color::from_rgba(
+                    scale(samples[at]),
+                    scale(samples[at + 1]),
+                    scale(samples[at + 2]),
+                    255,
+                )
+            } else {
+                let v = scale(samples[index]);
+                Color::from_rgba(v, v, v, 255)
+            };
+        }
+        Ok(image)
+    }
+
+    /// Binary PPM, alpha is dropped
+    pub fn encode_ppm(&self) -> Vec<u8> {
+        let mut output = format!("P6\n{0} {1}\n255\n", self.width, self.height).into_bytes();
+        output.reserve(self.color_data.len() * 3);
+        for color in self.color_data.iter() {
+            let (r, g, b, _) = color.separate();
+            output.push(r as u8);
+            output.push(g as u8);
+            output.push(b as u8);
+        }
+        output
+    }
+
+    /// Binary PGM of the Rec. 601 luma, alpha is dropped
+    pub fn encode_pgm(&self) -> Vec<u8> {
+        let mut output = format!("P5\n{0} {1}\n255\n", self.width, self.height).into_bytes();
+        output.reserve(self.color_data.len());
+        for color in self.color_data.iter() {
+            let (r, g, b, _) = color.separate();
+            output.push(((299 * r + 587 * g + 114 * b + 500) / 1000) as u8);
+        }
+        output
+    }
+
+    /// Color mapped, true color and gray TGA, raw or run length encoded, any origin
+    pub fn decode_tga(data: &[u8]) -> io::Result<Image> {
+        if data.len() < 18 {
+            return Err(invalid_image("Not a TGA file"));
+        }
+        let id_length = data[0] as usize;
+        let color_map_type = data[1];
+        let image_type = data[2];
+        let map_first = read_u16(data, 3) as usize;
+        let map_length = read_u16(data, 5) as usize;
+        let map_bits = data[7] as usize;
+        let width = read_u16(data, 12) as i64;
+        let height = read_u16(data, 14) as i64;
+        let bits = data[16] as usize;
+        let descriptor = data[17];
+        check_dimensions(width, height)?;
+        let (width, height) = (width as usize, height as usize);
+
+        let rle = image_type >= 9;
+        let base_type = if rle { image_type - 8 } else { image_type };
+        let valid = match base_type {
+            1 => color_map_type == 1 && (bits == 8 || bits == 16),
+            2 => bits == 15 || bits == 16 || bits == 24 || bits == 32,
+            3 => bits == 8 || bits == 16,
+            _ => false,
+        };
+        if !valid {
+            return Err(invalid_image("Unsupported TGA type"));
+        }
+        if color_map_type == 1
+            && !(map_bits == 15 || map_bits == 16 || map_bits == 24 || map_bits == 32)
+        {
+            return Err(invalid_image("Unsupported TGA color map depth"));
+        }
+        let alpha_bits = descriptor & 0x0F;
+
+        // 15 and 16 bit pixels are ARRRRRGG GGGBBBBB, only 16 bit ones may carry alpha.
+        let decode_color = |bytes: &[u8], bits: usize| -> Color {
+            match bits {
+                8 => Color::from_rgba(bytes[0] as u32, bytes[0] as u32, bytes[0] as u32, 255),
+                15 | 16 => {
+                    let pixel = read_u16(bytes, 0) as u32;
+                    let expand = |value: u32| (value << 3) | (value >> 2);
+                    let a = if bits == 16 && alpha_bits > 0 && pixel & 0x8000 == 0 {
+                        0
+                    } else {
+                        255
+                    };
+                    Color::from_rgba(
+                        expand((pixel >> 10) & 0x1F),
+                        expand((pixel >> 5) & 0x1F),
+                        expand(pixel & 0x1F),
+                        a,
+                    )
+                }
+                24 => Color::from_rgba(bytes[2] as u32, bytes[1] as u32, bytes[0] as u32, 255),
+                _ => Color::from_rgba(
+                    bytes[2] as u32,
+                    bytes[1] as u32,
+                    bytes[0] as u32,
+                    if alpha_bits > 0 { bytes[3] as u32 } else { 255 },
+                ),
+            }
+        };
+
+        let mut offset = 18 + id_length;
+        let mut color_map = Vec::new();
+        if color_map_type == 1 {
+            let entry_bytes = (map_bits + 7) / 8;
+            if offset + map_length * entry_bytes > data.len() {
+                return Err(invalid_image("Truncated TGA color map"));
+            }
+            for i in 0..map_length {
+                let at = offset + i * entry_bytes;
+                color_map.push(decode_color(&data[at..at + entry_bytes], map_bits));
+            }
+            offset += map_length * entry_bytes;
+        }
+
+        let pixel_bytes = (bits + 7) / 8;
+        let to_color = |bytes: &[u8]| -> io::Result<Color> {
+            match base_type {
+                1 => {
+                    let index = if pixel_bytes == 2 {
+                        read_u16(bytes, 0) as usize
+                    } else {
+                        bytes[0] as usize
+                    };
+                    match index.checked_sub(map_first).and_then(|i| color_map.get(i)) {
+                        Some(color) => Ok(*color),
+                        None => Err(invalid_image("TGA color map index out of range")),
+                    }
+                }
+                // 16 bit gray carries alpha in the second byte.
+                3 if pixel_bytes == 2 => Ok(Color::from_rgba(
+                    bytes[0] as u32,
+                    bytes[0] as u32,
+                    bytes[0] as u32,
+                    bytes[1] as u32,
+                )),
+                _ => Ok(decode_color(bytes, bits)),
+            }
+        };
+
+        let count = width * height;
+        let mut pixels = Vec::with_capacity(count);
+        if rle {
+            while pixels.len() < count {
+                if offset >= data.len() {
+                    return Err(invalid_image("Truncated TGA data"));
+                }
+                let packet = data[offset];
+                offset += 1;
+                let length = (packet & 0x7F) as usize + 1;
+                if packet & 0x80 != 0 {
+                    if offset + pixel_bytes > data.len() {
+                        return Err(invalid_image("Truncated TGA data"));
+                    }
+                    let color = to_color(&data[offset..offset + pixel_bytes])?;
+                    offset += pixel_bytes;
+                    for _ in 0..length {
+                        pixels.push(color);
+                    }
+                } else {
+                    if offset + length * pixel_bytes > data.len() {
+                        return Err(invalid_image("Truncated TGA data"));
+                    }
+                    for _ in 0..length {
+                        pixels.push(to_color(&data[offset..offset + pixel_bytes])?);
+                        offset += pixel_bytes;
+                    }
+                }
+            }
+            pixels.truncate(count);
+        } else {
+            if offset + count * pixel_bytes > data.len() {
+                return Err(invalid_image("Truncated TGA data"));
+            }
+            for i in 0..count {
+                let at = offset + i * pixel_bytes;
+                pixels.push(to_color(&data[at..at + pixel_bytes])?);
+            }
+        }
+
+        let right_to_left = descriptor & 0x10 != 0;
+        let top_down = descriptor & 0x20 != 0;
+        let mut image = Image::new(width as i32, height as i32);
+        for (index, color) in pixels.iter().enumerate() {
+            let (column, row) = (index % width, index / width);
+            let x = if right_to_left {
+                width - 1 - column
+            } else {
+                column
+            };
+            let y = if top_down { row } else { height - 1 - row };
+            image.color_data[y * width + x] = *color;
+        }
+        Ok(image)
+    }
+
+    /// 32 bit top down TGA with alpha, run length encoded per row when `rle` is set
+    pub fn encode_tga(&self, rle: bool) -> Vec<u8> {
+        let width = self.width as usize;
+        let mut output = vec![0u8; 18];
+        output[2] = if rle { 10 } else { 2 };
+        output[12] = self.width as u8;
+        output[13] = (self.width >> 8) as u8;
+        output[14] = self.height as u8;
+        output[15] = (self.height >> 8) as u8;
+        output[16] = 32;
+        output[17] = 0x20 | 8;
+
+        let push_color = |output: &mut Vec<u8>, color: Color| {
+            let (r, g, b, a) = color.separate();
+            output.extend_from_slice(&[b as u8, g as u8, r as u8, a as u8]);
+        };
+
+        for row in self.color_data.chunks(max_u32(width as u32, 1) as usize) {
+            if !rle {
+                for color in row.iter() {
+                    push_color(&mut output, *color);
+                }
+                continue;
+            }
+
+            let same = |a: Color, b: Color| a.separate() == b.separate();
+            let mut i = 0;
+            while i < row.len() {
+                let mut run = 1;
+                while i + run < row.len() && run < 128 && same(row[i], row[i + run]) {
+                    run += 1;
+                }
+                if run > 1 {
+                    output.push(0x80 | (run - 1) as u8);
+                    push_color(&mut output, row[i]);
+                    i += run;
+                    continue;
+                }
+
+                // Raw packet up to the next run of two or more
+                let start = i;
+                while i < row.len()
+                    && i - start < 128
+                    && !(i + 1 < row.len() && same(row[i], row[i + 1]))
+                {
+                    i += 1;
+                }
+                if i == start {
+                    i += 1;
+                }
+                output.push((i - start - 1) as u8);
+                for color in row[start..i].iter() {
+                    push_color(&mut output, *color);
+                }
+            }
+        }
+        output
+    }
+
+    pub fn decode(data: &[u8], format: ImageFormat) -> io::Result<Image> {
+        match format {
+            ImageFormat::Bmp => Image::decode_bmp(data),
+            ImageFormat::Png => Image::decode_png(data),
+            ImageFormat::Ppm | ImageFormat::Pgm => Image::decode_netpbm(data),
+            ImageFormat::Tga => Image::decode_tga(data),
+        }
+    }
+
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Bmp => self.encode_bmp(32),
+            ImageFormat::Png => self.encode_png(),
+            ImageFormat::Ppm => self.encode_ppm(),
+            ImageFormat::Pgm => self.encode_pgm(),
+            ImageFormat::Tga => self.encode_tga(true),
+        }
+    }
+
+    /// Picks the decoder from the file contents, or the extension for TGA
+    pub fn load(path: &str) -> io::Result<Image> {
+        let data = read_file(path)?;
+        match ImageFormat::detect(&data).or(ImageFormat::from_path(path)) {
+            Some(format) => Image::decode(&data, format),
+            None => Err(invalid_image("Unknown image format")),
+        }
+    }
+
+    /// Encodes in the format the extension names
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let format = match ImageFormat::from_path(path) {
+            Some(format) => format,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Unknown image file extension",
+                ))
+            }
+        };
+        let mut file = File::create(path)?;
+        file.write_all(&self.encode(format))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gradient with translucent pixels and runs of equal ones for the RLE paths
+    fn test_image() -> Image {
+        let mut image = Image::new(7, 5);
+        for y in 0..5usize {
+            for x in 0..7 {
+                let color = if x < 3 {
+                    Color::from_rgba(200, 40, 90, 255)
+                } else {
+                    Color::from_rgba(
+                        x as u32 * 30,
+                        y as u32 * 50,
+                        255 - x as u32 * 20,
+                        60 * y as u32,
+                    )
+                };
+                image.color_data[y * 7 + x] = color;
+            }
+        }
+        image
+    }
+
+    fn assert_same(decoded: &Image, expected: &Image, with_alpha: bool) {
+        assert_eq!(decoded.width, expected.width);
+        assert_eq!(decoded.height, expected.height);
+        for (a, b) in decoded.color_data.iter().zip(expected.color_data.iter()) {
+            let (r0, g0, b0, a0) = a.separate();
+            let (r1, g1, b1, a1) = b.separate();
+            assert_eq!((r0, g0, b0), (r1, g1, b1));
+            assert_eq!(a0, if with_alpha { a1 } else { 255 });
+        }
+    }
+
+    #[test]
+    fn round_trip_every_format() {
+        let image = test_image();
+        for &(format, with_alpha) in [
+            (ImageFormat::Bmp, true),
+            (ImageFormat::Png, true),
+            (ImageFormat::Ppm, false),
+            (ImageFormat::Tga, true),
+        ]
+        .iter()
+        {
+            let data = image.encode(format);
+            assert!(ImageFormat::detect(&data) == Some(format) || format == ImageFormat::Tga);
+            assert_same(&Image::decode(&data, format).unwrap(), &image, with_alpha);
+        }
+        assert_same(
+            &Image::decode_bmp(&image.encode_bmp(24)).unwrap(),
+            &image,
+            false,
+        );
+        assert_same(
+            &Image::decode_tga(&image.encode_tga(false)).unwrap(),
+            &image,
+            true,
+        );
+    }
+
+    #[test]
+    fn gray_round_trip() {
+        let mut image = Image::new(4, 3);
+        for (i, color) in image.color_data.iter_mut().enumerate() {
+            let value = i as u32 * 20;
+            *color = Color::from_rgba(value, value, value, 255);
+        }
+        let decoded = Image::decode(&image.encode(ImageFormat::Pgm), ImageFormat::Pgm).unwrap();
+        assert_same(&decoded, &image, false);
+    }
+
+    #[test]
+    fn tga_color_map_depth_is_checked() {
+        // 1x1 color mapped image with two 8 bit map entries
+        let mut data = vec![0u8; 18];
+        data[1] = 1;
+        data[2] = 1;
+        data[5] = 2;
+        data[7] = 8;
+        data[12] = 1;
+        data[14] = 1;
+        data[16] = 8;
+        data.extend_from_slice(&[10, 20, 0]);
+        assert!(Image::decode_tga(&data).is_err());
+
+        data[7] = 24;
+        data.truncate(18);
+        data.extend_from_slice(&[10, 20, 30, 40, 50, 60, 1]);
+        let image = Image::decode_tga(&data).unwrap();
+        assert_eq!(image.color_data[0].separate(), (60, 50, 40, 255));
+    }
+}
@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use audio::*;
+use fft::*;
+use filter::hann_window;
+use math::*;
+use tools::*;
+
+/// Average magnitude spectrum of a noise-only region
+#[derive(Clone)]
+pub struct NoiseProfile {
+    pub frame_size: usize,
+    pub sample_rate: f64,
+    pub magnitudes: Vec<f64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReductionMode {
+    /// Power spectral subtraction
+    Subtraction,
+    /// Bins below the noise threshold are attenuated, the rest pass untouched
+    Gate,
+}
+
+pub struct NoiseReducer {
+    pub profile: NoiseProfile,
+    pub mode: ReductionMode,
+    /// Maximum attenuation in dB
+    pub reduction_db: f64,
+    /// Multiplier on the noise profile before subtraction or gating
+    pub sensitivity: f64,
+    /// 0.0 - 1.0, amount of time and frequency smoothing of the gain mask
+    pub musical_noise_suppression: f64,
+}
+
+impl NoiseProfile {
+    /// Captures the profile from samples `start..end` of `wave`
+    pub fn capture(wave: &Waveform, start: usize, end: usize, frame_size: usize) -> NoiseProfile {
+        let frame_size = next_power_of_two(frame_size);
+        let bins = frame_size / 2 + 1;
+        let end = min_u32(end as u32, wave.sample_count as u32) as usize;
+        let start = min_u32(start as u32, end as u32) as usize;
+        let region = &wave.samples[start..end];
+
+        let mut magnitudes = vec![0.0; bins];
+
+        if region.len() >= frame_size {
+            let hop = frame_size / 2;
+            let mut frame_count = 0;
+            let mut offset = 0;
+            while offset + frame_size <= region.len() {
+                let mut data = vec![Complex::ZERO; frame_size];
+                for n in 0..frame_size {
+                    data[n].re = region[offset + n] * hann_window(n, frame_size);
+                }
+                fft(&mut data, false);
+                for k in 0..bins {
+                    magnitudes[k] += data[k].norm_sq();
+                }
+                frame_count += 1;
+                offset += hop;
+            }
+            for k in 0..bins {
+                magnitudes[k] = square_root(magnitudes[k] / frame_count as f64);
+            }
+        } else if region.len() > 1 {
+            // Short selections are analysed as one zero padded frame, rescaled to the
+            // energy a full window would have collected.
+            let length = region.len();
+            let mut data = vec![Complex::ZERO; frame_size];
+            let mut short_energy = 0.0;
+            let mut full_energy = 0.0;
+            for n in 0..length {
+                let window = hann_window(n, length);
+                data[n].re = region[n] * window;
+                short_energy += window * window;
+            }
+            for n in 0..frame_size {
+                full_energy += square(hann_window(n, frame_size));
+            }
+            fft(&mut data, false);
+
+            let scale = square_root(full_energy / max_f64(short_energy, 1.0e-12));
+            for k in 0..bins {
+                magnitudes[k] = data[k].norm() * scale;
+            }
+        }
+
+        NoiseProfile {
+            frame_size: frame_size,
+            sample_rate: wave.sample_rate,
+            magnitudes: magnitudes,
+        }
+    }
+}
+
+impl NoiseReducer {
+    pub fn new(profile: NoiseProfile) -> NoiseReducer {
+        NoiseReducer {
+            profile: profile,
+            mode: ReductionMode::Subtraction,
+            reduction_db: 12.0,
+            sensitivity: 1.5,
+            musical_noise_suppression: 0.5,
+        }
+    }
+
+    fn bin_gain(&self, magnitude: f64, noise: f64, floor: f64) -> f64 {
+        let noise = noise * self.sensitivity;
+        match self.mode {
+            ReductionMode::Subtraction => {
+                if magnitude <= 0.0 {
+                    return floor;
+                }
+                let ratio = square(noise / magnitude);
+                max_f64(floor, square_root(max_f64(0.0, 1.0 - ratio)))
+            }
+            ReductionMode::Gate => {
+                if magnitude > noise {
+                    1.0
+                } else {
+                    floor
+                }
+            }
+        }
+    }
+
+    pub fn process(&self, wave: &Waveform) -> Waveform {
+        let frame_size = self.profile.frame_size;
+        let hop = frame_size / 4;
+        let bins = frame_size / 2 + 1;
+        let floor = db_to_volume(-abs_f64(self.reduction_db));
+
+        let suppression = clamp01_f64(self.musical_noise_suppression);
+        // Gains fall slowly and rise at once so transients are kept while isolated
+        // bins flickering above the threshold are smoothed out.
+        let release = 0.9 * suppression;
+        let spread = round_f64_u32(4.0 * suppression) as usize;
+
+        let mut frames = stft(&wave.samples, frame_size, hop);
+        let mut previous = vec![1.0; bins];
+        let mut gains = vec![1.0; bins];
+        let mut smoothed = vec![1.0; bins];
+
+        for spectrum in frames.iter_mut() {
+            for k in 0..bins {
+                let gain = self.bin_gain(spectrum[k].norm(), self.profile.magnitudes[k], floor);
+                gains[k] = if gain < previous[k] {
+                    lerp_f64(gain, release, previous[k])
+                } else {
+                    gain
+                };
+                previous[k] = gains[k];
+            }
+
+            for k in 0..bins {
+                let low = if k > spread { k - spread } else { 0 };
+                let high = min_u32((k + spread) as u32, (bins - 1) as u32) as usize;
+                let mut sum = 0.0;
+                for j in low..high + 1 {
+                    sum += gains[j];
+                }
+                smoothed[k] = sum / (high - low + 1) as f64;
+            }
+
+            for k in 0..bins {
+                spectrum[k] = spectrum[k].scale(smoothed[k]);
+                if k > 0 && k < frame_size / 2 {
+                    spectrum[frame_size - k] = spectrum[k].conj();
+                }
+            }
+        }
+
+        let samples = istft(&frames, frame_size, hop, wave.sample_count);
+        Waveform::from_samples(samples, wave.sample_rate)
+    }
+}
@@ -0,0 +1,258 @@
+#![allow(dead_code)]
+
+use audio::*;
+use filter::*;
+use math::*;
+use render::create_curve;
+
+pub enum ShaperCurve {
+    Tanh,
+    HardClip,
+    SoftClip,
+    Foldback,
+    Bitcrush(u32),
+    SampleRateReduction(f64),
+    Custom(TransferCurve),
+}
+
+/// Lookup table built from a smoothed set of control points, x and y in [-1.0,1.0]
+pub struct TransferCurve {
+    points: Vec<Vector2>,
+}
+
+pub struct Waveshaper {
+    pub curve: ShaperCurve,
+    pub drive: f64,
+    pub mix: f64,
+    pub output_gain: f64,
+    oversampler: Oversampler,
+    hold_value: f64,
+    hold_phase: f64,
+    buffer: Vec<f64>,
+    dry_delay: Vec<f64>,
+    dry_position: usize,
+}
+
+impl TransferCurve {
+    pub fn from_points(control_points: &Vec<Vector2>) -> TransferCurve {
+        // Points that are not finite cannot be placed on the curve
+        let mut sorted: Vec<Vector2> = control_points
+            .iter()
+            .filter(|point| point.x.is_finite() && point.y.is_finite())
+            .cloned()
+            .collect();
+        sorted.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+
+        let mut points = if sorted.len() > 1 {
+            create_curve(&sorted)
+        } else {
+            sorted.clone()
+        };
+
+        // Segments stop one step short of the last control point.
+        if let Some(last) = sorted.last() {
+            points.push(*last);
+        }
+
+        // Tension vectors can overshoot slightly between close control points,
+        // keep x monotonic so the lookup can use a binary search.
+        for i in 1..points.len() {
+            if points[i].x < points[i - 1].x {
+                points[i].x = points[i - 1].x;
+            }
+        }
+
+        TransferCurve { points: points }
+    }
+
+    pub fn linear() -> TransferCurve {
+        TransferCurve::from_points(&vec![Vector2::new(-1.0, -1.0), Vector2::new(1.0, 1.0)])
+    }
+
+    pub fn evaluate(&self, x: f64) -> f64 {
+        let points = &self.points;
+        let count = points.len();
+
+        if count == 0 {
+            return x;
+        }
+        if x <= points[0].x {
+            return points[0].y;
+        }
+        if x >= points[count - 1].x {
+            return points[count - 1].y;
+        }
+
+        let mut low = 0;
+        let mut high = count - 1;
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+            if points[middle].x <= x {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        let a = points[low];
+        let b = points[high];
+        let span = b.x - a.x;
+        if span <= 0.0 {
+            return b.y;
+        }
+        lerp_f64(a.y, (x - a.x) / span, b.y)
+    }
+}
+
+#[inline(always)]
+pub fn shape_tanh(x: f64) -> f64 {
+    x.tanh()
+}
+
+#[inline(always)]
+pub fn shape_hard_clip(x: f64) -> f64 {
+    clamp_f64(-1.0, x, 1.0)
+}
+
+/// Cubic soft clip, smooth at the knee and flat above |x| = 1
+#[inline(always)]
+pub fn shape_soft_clip(x: f64) -> f64 {
+    if x <= -1.0 {
+        -2.0 / 3.0
+    } else if x >= 1.0 {
+        2.0 / 3.0
+    } else {
+        x - x * x * x / 3.0
+    }
+}
+
+/// Reflects everything above the threshold back into range
+#[inline(always)]
+pub fn shape_foldback(x: f64, threshold: f64) -> f64 {
+    if threshold <= 0.0 {
+        return 0.0;
+    }
+    if x > threshold || x < -threshold {
+        let period = 4.0 * threshold;
+        let shifted = x + threshold;
+        let wrapped = shifted - period * floor_f64(shifted / period);
+        threshold - abs_f64(wrapped - 2.0 * threshold)
+    } else {
+        x
+    }
+}
+
+#[inline(always)]
+pub fn shape_bitcrush(x: f64, bits: u32) -> f64 {
+    let bits = max_u32(1, min_u32(bits, 32));
+    let steps = (1u64 << (bits - 1)) as f64;
+    round_f64(clamp_f64(-1.0, x, 1.0) * steps) / steps
+}
+
+impl Waveshaper {
+    pub fn new(curve: ShaperCurve, oversampling: Oversampling) -> Waveshaper {
+        let mut result = Waveshaper {
+            curve: curve,
+            drive: 1.0,
+            mix: 1.0,
+            output_gain: 1.0,
+            oversampler: Oversampler::new(oversampling),
+            hold_value: 0.0,
+            hold_phase: 1.0,
+            buffer: Vec::with_capacity(oversampling.factor()),
+            dry_delay: Vec::new(),
+            dry_position: 0,
+        };
+        result.reset();
+        result
+    }
+
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        self.oversampler = Oversampler::new(oversampling);
+        self.reset();
+    }
+
+    pub fn latency(&self) -> usize {
+        self.oversampler.latency()
+    }
+
+    pub fn reset(&mut self) {
+        self.oversampler.reset();
+        self.hold_value = 0.0;
+        self.hold_phase = 1.0;
+        self.dry_delay = vec![0.0; self.oversampler.latency()];
+        self.dry_position = 0;
+    }
+
+    fn shape(&mut self, x: f64) -> f64 {
+        let driven = x * self.drive;
+        match self.curve {
+            ShaperCurve::Tanh => shape_tanh(driven),
+            ShaperCurve::HardClip => shape_hard_clip(driven),
+            ShaperCurve::SoftClip => shape_soft_clip(driven) * 1.5,
+            ShaperCurve::Foldback => shape_foldback(driven, 1.0),
+            ShaperCurve::Bitcrush(bits) => shape_bitcrush(driven, bits),
+            ShaperCurve::SampleRateReduction(factor) => {
+                // Factor is relative to the base rate, scale it to the oversampled one.
+                let step = 1.0 / (max_f64(1.0, factor) * self.oversampler.factor() as f64);
+                self.hold_phase += step;
+                if self.hold_phase >= 1.0 {
+                    self.hold_phase -= 1.0;
+                    self.hold_value = driven;
+                }
+                self.hold_value
+            }
+            ShaperCurve::Custom(ref curve) => curve.evaluate(driven),
+        }
+    }
+
+    fn process_wet(&mut self, input: f64) -> f64 {
+        let mut buffer = ::std::mem::replace(&mut self.buffer, Vec::new());
+
+        self.oversampler.upsample(input, &mut buffer);
+        for i in 0..buffer.len() {
+            buffer[i] = self.shape(buffer[i]);
+        }
+        let result = self.oversampler.downsample(&buffer);
+
+        self.buffer = buffer;
+        result
+    }
+
+    /// Streaming version, the dry signal is delayed to line up with the wet one.
+    pub fn process_sample(&mut self, input: f64) -> f64 {
+        let wet = self.process_wet(input);
+
+        let dry = if self.dry_delay.len() > 0 {
+            let delayed = self.dry_delay[self.dry_position];
+            self.dry_delay[self.dry_position] = input;
+            self.dry_position = (self.dry_position + 1) % self.dry_delay.len();
+            delayed
+        } else {
+            input
+        };
+
+        lerp_f64(dry, self.mix, wet) * self.output_gain
+    }
+
+    /// Processes the whole waveform, output is aligned with the input.
+    pub fn process(&mut self, wave: &Waveform) -> Waveform {
+        self.reset();
+
+        let latency = self.latency();
+        let count = wave.sample_count;
+        let mut samples = Vec::with_capacity(count);
+
+        for i in 0..count + latency {
+            let input = if i < count { wave.samples[i] } else { 0.0 };
+            let wet = self.process_wet(input);
+
+            if i >= latency {
+                let dry = wave.samples[i - latency];
+                samples.push(lerp_f64(dry, self.mix, wet) * self.output_gain);
+            }
+        }
+
+        Waveform::from_samples(samples, wave.sample_rate)
+    }
+}
@@ -0,0 +1,324 @@
+#![allow(dead_code)]
+
+use audio::*;
+use canvas::*;
+use fft::*;
+use filter::*;
+use math::*;
+use render::*;
+use tools::*;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BandType {
+    Peak,
+    LowShelf,
+    HighShelf,
+    /// Slope in dB per octave, multiple of 6
+    HighPass(u32),
+    LowPass(u32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PhaseMode {
+    Minimum,
+    Linear,
+}
+
+#[derive(Clone, Copy)]
+pub struct EqBand {
+    pub band_type: BandType,
+    pub frequency: f64,
+    pub gain_db: f64,
+    pub q: f64,
+    pub enabled: bool,
+}
+
+pub struct Equalizer {
+    pub bands: Vec<EqBand>,
+    pub phase_mode: PhaseMode,
+    pub output_gain_db: f64,
+    /// Solo a single band through a band-pass at its frequency and Q
+    pub auto_listen: Option<usize>,
+    /// Match the output RMS to the input RMS
+    pub gain_compensation: bool,
+    /// Impulse length used in linear phase mode, power of two
+    pub fir_length: usize,
+}
+
+pub struct FrequencyResponse {
+    pub frequencies: Vec<f64>,
+    pub magnitude_db: Vec<f64>,
+    pub phase: Vec<f64>,
+}
+
+impl EqBand {
+    pub fn new(band_type: BandType, frequency: f64, gain_db: f64, q: f64) -> EqBand {
+        EqBand {
+            band_type: band_type,
+            frequency: frequency,
+            gain_db: gain_db,
+            q: q,
+            enabled: true,
+        }
+    }
+
+    pub fn sections(&self, sample_rate: f64) -> Vec<Biquad> {
+        let mut result = Vec::new();
+        if !self.enabled {
+            return result;
+        }
+
+        let frequency = self.frequency;
+        match self.band_type {
+            BandType::Peak => {
+                result.push(Biquad::peak(sample_rate, frequency, self.q, self.gain_db))
+            }
+            BandType::LowShelf => result.push(Biquad::low_shelf(
+                sample_rate,
+                frequency,
+                self.q,
+                self.gain_db,
+            )),
+            BandType::HighShelf => result.push(Biquad::high_shelf(
+                sample_rate,
+                frequency,
+                self.q,
+                self.gain_db,
+            )),
+            BandType::HighPass(slope) | BandType::LowPass(slope) => {
+                let high = match self.band_type {
+                    BandType::HighPass(_) => true,
+                    _ => false,
+                };
+                let order = max_u32(1, slope / 6);
+                let q_values = butterworth_q(order);
+
+                for (index, q) in q_values.iter().enumerate() {
+                    // A single 12 dB section takes its resonance from the band.
+                    let q = if order == 2 && index == 0 { self.q } else { *q };
+                    if high {
+                        result.push(Biquad::high_pass(sample_rate, frequency, q));
+                    } else {
+                        result.push(Biquad::low_pass(sample_rate, frequency, q));
+                    }
+                }
+                if order % 2 == 1 {
+                    if high {
+                        result.push(Biquad::first_order_high_pass(sample_rate, frequency));
+                    } else {
+                        result.push(Biquad::first_order_low_pass(sample_rate, frequency));
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Equalizer {
+    pub fn new() -> Equalizer {
+        Equalizer {
+            bands: Vec::new(),
+            phase_mode: PhaseMode::Minimum,
+            output_gain_db: 0.0,
+            auto_listen: None,
+            gain_compensation: false,
+            fir_length: 4096,
+        }
+    }
+
+    pub fn add_band(&mut self, band: EqBand) -> usize {
+        self.bands.push(band);
+        self.bands.len() - 1
+    }
+
+    /// Cascade of all enabled bands, or the auto-listen band-pass
+    pub fn sections(&self, sample_rate: f64) -> Vec<Biquad> {
+        if let Some(index) = self.auto_listen {
+            if index < self.bands.len() {
+                let band = &self.bands[index];
+                return vec![Biquad::band_pass(
+                    sample_rate,
+                    band.frequency,
+                    max_f64(band.q, 0.5),
+                )];
+            }
+        }
+
+        let mut result = Vec::new();
+        for band in self.bands.iter() {
+            result.extend(band.sections(sample_rate));
+        }
+        result
+    }
+
+    pub fn latency(&self) -> usize {
+        match self.phase_mode {
+            PhaseMode::Minimum => 0,
+            PhaseMode::Linear => next_power_of_two(self.fir_length) / 2,
+        }
+    }
+
+    /// Complex response of the whole processor at one frequency
+    pub fn response_at(&self, sample_rate: f64, frequency: f64) -> Complex {
+        let sections = self.sections(sample_rate);
+        let gain = db_to_volume(self.output_gain_db);
+        let response = cascade_response(&sections, sample_rate, frequency).scale(gain);
+
+        match self.phase_mode {
+            PhaseMode::Minimum => response,
+            // The linear phase filter only keeps the magnitude, the phase is a pure
+            // delay which is compensated when processing.
+            PhaseMode::Linear => Complex::new(response.norm(), 0.0),
+        }
+    }
+
+    /// Response at `count` log spaced points between `min_frequency` and `max_frequency`
+    pub fn response(
+        &self,
+        sample_rate: f64,
+        min_frequency: f64,
+        max_frequency: f64,
+        count: usize,
+    ) -> FrequencyResponse {
+        let mut frequencies = Vec::with_capacity(count);
+        let mut magnitude_db = Vec::with_capacity(count);
+        let mut phase = Vec::with_capacity(count);
+
+        let sections = self.sections(sample_rate);
+        let gain = db_to_volume(self.output_gain_db);
+        let ratio = max_frequency / min_frequency;
+
+        for i in 0..count {
+            let t = if count > 1 {
+                i as f64 / (count - 1) as f64
+            } else {
+                0.0
+            };
+            let frequency = min_frequency * f64::powf(ratio, t);
+            let value = cascade_response(&sections, sample_rate, frequency).scale(gain);
+
+            frequencies.push(frequency);
+            magnitude_db.push(volume_to_db(max_f64(value.norm(), 1.0e-12)));
+            phase.push(match self.phase_mode {
+                PhaseMode::Minimum => value.arg(),
+                PhaseMode::Linear => 0.0,
+            });
+        }
+
+        FrequencyResponse {
+            frequencies: frequencies,
+            magnitude_db: magnitude_db,
+            phase: phase,
+        }
+    }
+
+    /// Zero phase impulse with the magnitude of the biquad cascade, centered at `latency()`
+    pub fn linear_phase_impulse(&self, sample_rate: f64) -> Vec<f64> {
+        let length = next_power_of_two(self.fir_length);
+        let sections = self.sections(sample_rate);
+        let gain = db_to_volume(self.output_gain_db);
+
+        let mut spectrum = vec![Complex::ZERO; length];
+        for k in 0..length / 2 + 1 {
+            let frequency = k as f64 * sample_rate / length as f64;
+            let magnitude = cascade_response(&sections, sample_rate, frequency).norm() * gain;
+            spectrum[k] = Complex::new(magnitude, 0.0);
+            if k > 0 && k < length / 2 {
+                spectrum[length - k] = spectrum[k];
+            }
+        }
+
+        let impulse = real_ifft(&spectrum);
+        let half = length / 2;
+        let mut result = vec![0.0; length];
+        for i in 0..length {
+            let shifted = impulse[(i + half) % length];
+            result[i] = shifted * blackman_window(i, length);
+        }
+        result
+    }
+
+    pub fn process(&self, wave: &Waveform) -> Waveform {
+        let count = wave.sample_count;
+        if count == 0 {
+            return Waveform::from_samples(wave.samples.to_vec(), wave.sample_rate);
+        }
+        let mut samples: Vec<f64>;
+
+        match self.phase_mode {
+            PhaseMode::Minimum => {
+                let mut sections = self.sections(wave.sample_rate);
+                let gain = db_to_volume(self.output_gain_db);
+                samples = Vec::with_capacity(count);
+                for i in 0..count {
+                    samples.push(process_cascade(&mut sections, wave.samples[i]) * gain);
+                }
+            }
+            PhaseMode::Linear => {
+                let impulse = self.linear_phase_impulse(wave.sample_rate);
+                let latency = self.latency();
+                let convolved = convolve(&wave.samples, &impulse);
+                samples = convolved[latency..latency + count].to_vec();
+            }
+        }
+
+        let compensate = self.gain_compensation || self.auto_listen.is_some();
+        if compensate {
+            let input_rms = rms(&wave.samples);
+            let output_rms = rms(&samples);
+            if output_rms > 0.0 {
+                let correction = input_rms / output_rms;
+                for value in samples.iter_mut() {
+                    *value *= correction;
+                }
+            }
+        }
+
+        Waveform::from_samples(samples, wave.sample_rate)
+    }
+
+    /// Summed magnitude (and phase for minimum phase) over 20 Hz - 20 kHz on a transparent
+    /// background, meant to be drawn over the waveform layer.
+    pub fn response_image(
+        &self,
+        width: i32,
+        height: i32,
+        sample_rate: f64,
+        range_db: f64,
+        magnitude_color: Color,
+        phase_color: Color,
+    ) -> Image {
+        let mut image = Image::new(width, height);
+        self.plot_response(
+            &mut image,
+            sample_rate,
+            range_db,
+            magnitude_color,
+            phase_color,
+        );
+        image
+    }
+
+    /// `response_image` drawn into any canvas
+    pub fn plot_response(
+        &self,
+        canvas: &mut Canvas,
+        sample_rate: f64,
+        range_db: f64,
+        magnitude_color: Color,
+        phase_color: Color,
+    ) {
+        let width = canvas.size().x as i32;
+        let max_frequency = min_f64(20000.0, sample_rate / 2.0);
+        let response = self.response(sample_rate, 20.0, max_frequency, max_i32(width, 2) as usize);
+        plot_response_curve(
+            canvas,
+            &response.magnitude_db,
+            &response.phase,
+            range_db,
+            magnitude_color,
+            phase_color,
+        );
+    }
+}
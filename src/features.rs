@@ -0,0 +1,310 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use audio::*;
+use fft::*;
+use math::*;
+use pitch::NOTE_NAMES;
+use render::*;
+
+/// Triangular filters evenly spaced on the mel scale, as (bin, weight) lists
+pub struct MelFilterbank {
+    pub filters: Vec<Vec<(usize, f64)>>,
+}
+
+pub struct FeatureExtractor {
+    pub frame_size: usize,
+    pub hop: usize,
+    pub mel_bands: usize,
+    pub mfcc_count: usize,
+    /// Fraction of the spectral energy below the rolloff frequency
+    pub rolloff: f64,
+    pub min_frequency: f64,
+    pub max_frequency: f64,
+}
+
+/// One row per frame, one column per feature
+pub struct FeatureMatrix {
+    pub names: Vec<String>,
+    /// Frame centers in samples
+    pub positions: Vec<usize>,
+    pub rows: Vec<Vec<f64>>,
+}
+
+#[inline(always)]
+pub fn hz_to_mel(frequency: f64) -> f64 {
+    2595.0 * (1.0 + frequency / 700.0).log10()
+}
+
+#[inline(always)]
+pub fn mel_to_hz(mel: f64) -> f64 {
+    700.0 * (f64::powf(10.0, mel / 2595.0) - 1.0)
+}
+
+impl MelFilterbank {
+    pub fn new(
+        bands: usize,
+        frame_size: usize,
+        sample_rate: f64,
+        min_frequency: f64,
+        max_frequency: f64,
+    ) -> MelFilterbank {
+        let bins = frame_size / 2 + 1;
+        let bin_width = sample_rate / frame_size as f64;
+        let min_mel = hz_to_mel(min_frequency);
+        let max_mel = hz_to_mel(max_frequency);
+        let edges: Vec<f64> = (0..bands + 2)
+            .map(|i| mel_to_hz(min_mel + (max_mel - min_mel) * i as f64 / (bands + 1) as f64))
+            .collect();
+
+        let mut filters = Vec::with_capacity(bands);
+        for band in 0..bands {
+            let (low, center, high) = (edges[band], edges[band + 1], edges[band + 2]);
+            let mut filter = Vec::new();
+            for k in 0..bins {
+                let frequency = k as f64 * bin_width;
+                let weight = if frequency > low && frequency <= center {
+                    (frequency - low) / (center - low)
+                } else if frequency > center && frequency < high {
+                    (high - frequency) / (high - center)
+                } else {
+                    0.0
+                };
+                if weight > 0.0 {
+                    filter.push((k, weight));
+                }
+            }
+            filters.push(filter);
+        }
+        MelFilterbank { filters: filters }
+    }
+
+    pub fn apply(&self, power: &[f64]) -> Vec<f64> {
+        self.filters
+            .iter()
+            .map(|filter| filter.iter().map(|&(k, weight)| power[k] * weight).sum())
+            .collect()
+    }
+}
+
+/// Orthonormal DCT-II, the first `count` coefficients
+pub fn dct(values: &[f64], count: usize) -> Vec<f64> {
+    let length = values.len() as f64;
+    let mut result = Vec::with_capacity(count);
+    for k in 0..count {
+        let mut sum = 0.0;
+        for (n, value) in values.iter().enumerate() {
+            sum += value * (PI * k as f64 * (n as f64 + 0.5) / length).cos();
+        }
+        let scale = if k == 0 {
+            (1.0 / length).sqrt()
+        } else {
+            (2.0 / length).sqrt()
+        };
+        result.push(sum * scale);
+    }
+    result
+}
+
+impl FeatureExtractor {
+    pub fn new(sample_rate: f64) -> FeatureExtractor {
+        FeatureExtractor {
+            frame_size: 2048,
+            hop: 512,
+            mel_bands: 40,
+            mfcc_count: 13,
+            rolloff: 0.85,
+            min_frequency: 0.0,
+            max_frequency: sample_rate / 2.0,
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = ["centroid", "spread", "rolloff", "flux", "flatness"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        for i in 0..self.mfcc_count {
+            names.push(format!("mfcc{0}", i));
+        }
+        for class in NOTE_NAMES.iter() {
+            names.push(format!("chroma_{0}", class));
+        }
+        names
+    }
+
+    pub fn extract(&self, wave: &Waveform) -> FeatureMatrix {
+        let sample_rate = wave.sample_rate;
+        let bins = self.frame_size / 2 + 1;
+        let bin_width = sample_rate / self.frame_size as f64;
+        let filterbank = MelFilterbank::new(
+            self.mel_bands,
+            self.frame_size,
+            sample_rate,
+            self.min_frequency,
+            self.max_frequency,
+        );
+
+        // Pitch class of every bin, chroma only looks at A0 up to 5 kHz.
+        let pitch_class: Vec<Option<usize>> = (0..bins)
+            .map(|k| {
+                let frequency = k as f64 * bin_width;
+                if frequency < 27.5 || frequency > 5000.0 {
+                    None
+                } else {
+                    let note = round_f64_i32(69.0 + 12.0 * (frequency / 440.0).log2());
+                    Some(((note % 12 + 12) % 12) as usize)
+                }
+            })
+            .collect();
+
+        let frames = stft(&wave.samples, self.frame_size, self.hop);
+        let mut previous = vec![0.0; bins];
+        let mut positions = Vec::with_capacity(frames.len());
+        let mut rows = Vec::with_capacity(frames.len());
+
+        for (index, spectrum) in frames.iter().enumerate() {
+            let magnitude: Vec<f64> = (0..bins).map(|k| spectrum[k].norm()).collect();
+            let power: Vec<f64> = magnitude.iter().map(|m| m * m).collect();
+            let total: f64 = magnitude.iter().sum();
+            let total_power: f64 = power.iter().sum();
+
+            let mut centroid = 0.0;
+            let mut spread = 0.0;
+            if total > 0.0 {
+                for k in 0..bins {
+                    centroid += k as f64 * bin_width * magnitude[k];
+                }
+                centroid /= total;
+                for k in 0..bins {
+                    let distance = k as f64 * bin_width - centroid;
+                    spread += distance * distance * magnitude[k];
+                }
+                spread = (spread / total).sqrt();
+            }
+
+            let mut rolloff = 0.0;
+            let mut accumulated = 0.0;
+            for k in 0..bins {
+                accumulated += power[k];
+                if accumulated >= self.rolloff * total_power {
+                    rolloff = k as f64 * bin_width;
+                    break;
+                }
+            }
+
+            let mut flux = 0.0;
+            if index > 0 {
+                for k in 0..bins {
+                    let rise = max_f64(0.0, magnitude[k] - previous[k]);
+                    flux += rise * rise;
+                }
+                flux = flux.sqrt();
+            }
+            previous = magnitude;
+
+            // Geometric over arithmetic mean of the power spectrum.
+            let floor = 1.0e-12;
+            let log_mean = power.iter().map(|p| (p + floor).ln()).sum::<f64>() / bins as f64;
+            let flatness = log_mean.exp() / (total_power / bins as f64 + floor);
+
+            let log_mel: Vec<f64> = filterbank
+                .apply(&power)
+                .iter()
+                .map(|energy| (energy + 1.0e-10).ln())
+                .collect();
+            let mfcc = dct(&log_mel, self.mfcc_count);
+
+            let mut chroma = vec![0.0; 12];
+            for k in 0..bins {
+                if let Some(class) = pitch_class[k] {
+                    chroma[class] += power[k];
+                }
+            }
+            let largest = chroma.iter().cloned().fold(0.0, max_f64);
+            if largest > 0.0 {
+                for value in chroma.iter_mut() {
+                    *value /= largest;
+                }
+            }
+
+            let mut row = vec![centroid, spread, rolloff, flux, flatness];
+            row.extend_from_slice(&mfcc);
+            row.extend_from_slice(&chroma);
+            rows.push(row);
+            positions.push(index * self.hop);
+        }
+
+        FeatureMatrix {
+            names: self.names(),
+            positions: positions,
+            rows: rows,
+        }
+    }
+}
+
+impl FeatureMatrix {
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Track of one feature over all frames
+    pub fn column(&self, name: &str) -> Option<Vec<f64>> {
+        match self.column_index(name) {
+            Some(index) => Some(self.rows.iter().map(|row| row[index]).collect()),
+            None => None,
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut result = String::from("position");
+        for name in self.names.iter() {
+            result.push(',');
+            result.push_str(name);
+        }
+        result.push('\n');
+
+        for (position, row) in self.positions.iter().zip(self.rows.iter()) {
+            result.push_str(&position.to_string());
+            for value in row.iter() {
+                result.push_str(&format!(",{0}", value));
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    pub fn write_csv(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_csv().as_bytes())
+    }
+}
+
+impl Image {
+    /// Line plot of `values` scaled to fill the image between their minimum and maximum
+    pub fn line_plot(width: i32, height: i32, values: &[f64], color: Color) -> Image {
+        let mut image = Image::new(width, height);
+        let count = values.len();
+        if count < 2 {
+            return image;
+        }
+
+        let min = values.iter().cloned().fold(f64::INFINITY, min_f64);
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, max_f64);
+        let range = if max > min { max - min } else { 1.0 };
+        let step = (width - 1) as f64 / (count - 1) as f64;
+        let to_y = |value: f64| (height - 1) as f64 * (1.0 - (value - min) / range);
+
+        for i in 1..count {
+            image.draw_line(
+                &Vector2::new((i - 1) as f64 * step, to_y(values[i - 1])),
+                &Vector2::new(i as f64 * step, to_y(values[i])),
+                color,
+            );
+        }
+        image
+    }
+}
@@ -0,0 +1,268 @@
+#![allow(dead_code)]
+
+use std::ops::{Add, Mul, Sub};
+
+use filter::hann_window;
+use math::*;
+
+#[derive(Clone, Copy)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const ZERO: Complex = Complex { re: 0.0, im: 0.0 };
+    pub const ONE: Complex = Complex { re: 1.0, im: 0.0 };
+
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re: re, im: im }
+    }
+
+    pub fn from_polar(magnitude: f64, phase: f64) -> Self {
+        Complex {
+            re: magnitude * f64::cos(phase),
+            im: magnitude * f64::sin(phase),
+        }
+    }
+
+    pub fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn norm_sq(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn norm(self) -> f64 {
+        square_root(self.norm_sq())
+    }
+
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn scale(self, value: f64) -> Complex {
+        Complex::new(self.re * value, self.im * value)
+    }
+
+    pub fn div(self, other: Complex) -> Complex {
+        let denominator = other.norm_sq();
+        if denominator == 0.0 {
+            return Complex::ZERO;
+        }
+        let result = self * other.conj();
+        result.scale(1.0 / denominator)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+#[inline(always)]
+pub fn is_power_of_two(value: usize) -> bool {
+    value != 0 && (value & (value - 1)) == 0
+}
+
+#[inline(always)]
+pub fn next_power_of_two(value: usize) -> usize {
+    let mut result = 1;
+    while result < value {
+        result <<= 1;
+    }
+    result
+}
+
+/// In place radix-2 FFT, length must be a power of two.
+/// The inverse transform is scaled by 1/N.
+pub fn fft(data: &mut [Complex], inverse: bool) {
+    let length = data.len();
+    assert!(is_power_of_two(length), "FFT length must be a power of two");
+
+    let mut j = 0;
+    for i in 1..length {
+        let mut bit = length >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut size = 2;
+    while size <= length {
+        let angle = sign * 2.0 * PI / size as f64;
+        let step = Complex::from_polar(1.0, angle);
+        let half = size / 2;
+
+        let mut start = 0;
+        while start < length {
+            let mut twiddle = Complex::ONE;
+            for k in 0..half {
+                let even = data[start + k];
+                let odd = data[start + k + half] * twiddle;
+                data[start + k] = even + odd;
+                data[start + k + half] = even - odd;
+                twiddle = twiddle * step;
+            }
+            start += size;
+        }
+        size <<= 1;
+    }
+
+    if inverse {
+        let scale = 1.0 / length as f64;
+        for value in data.iter_mut() {
+            *value = value.scale(scale);
+        }
+    }
+}
+
+/// Zero pads `input` to `length` and returns the full complex spectrum.
+pub fn real_fft(input: &[f64], length: usize) -> Vec<Complex> {
+    let mut data = vec![Complex::ZERO; length];
+    let count = if input.len() < length {
+        input.len()
+    } else {
+        length
+    };
+    for i in 0..count {
+        data[i].re = input[i];
+    }
+    fft(&mut data, false);
+    data
+}
+
+/// Inverse transform keeping only the real part.
+pub fn real_ifft(spectrum: &[Complex]) -> Vec<f64> {
+    let mut data = spectrum.to_vec();
+    fft(&mut data, true);
+    data.iter().map(|value| value.re).collect()
+}
+
+/// Linear convolution of two real signals through the FFT.
+pub fn convolve(a: &[f64], b: &[f64]) -> Vec<f64> {
+    if a.len() == 0 || b.len() == 0 {
+        return Vec::new();
+    }
+    let result_length = a.len() + b.len() - 1;
+    let length = next_power_of_two(result_length);
+
+    let mut spectrum = real_fft(a, length);
+    let other = real_fft(b, length);
+    for i in 0..length {
+        spectrum[i] = spectrum[i] * other[i];
+    }
+
+    let mut result = real_ifft(&spectrum);
+    result.truncate(result_length);
+    result
+}
+
+/// Power of a single DFT bin at an arbitrary frequency.
+pub fn goertzel_power(samples: &[f64], sample_rate: f64, frequency: f64) -> f64 {
+    let w = 2.0 * PI * frequency / sample_rate;
+    let coefficient = 2.0 * f64::cos(w);
+    let mut s1 = 0.0;
+    let mut s2 = 0.0;
+    for value in samples.iter() {
+        let s0 = value + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    s1 * s1 + s2 * s2 - coefficient * s1 * s2
+}
+
+/// `goertzel_power` of every one of `frequencies` in a single pass over the samples
+pub fn goertzel_powers(samples: &[f64], sample_rate: f64, frequencies: &[f64]) -> Vec<f64> {
+    let coefficients: Vec<f64> = frequencies
+        .iter()
+        .map(|frequency| 2.0 * f64::cos(2.0 * PI * frequency / sample_rate))
+        .collect();
+    let mut s1 = vec![0.0; frequencies.len()];
+    let mut s2 = vec![0.0; frequencies.len()];
+    for value in samples.iter() {
+        for ((coefficient, s1), s2) in coefficients.iter().zip(s1.iter_mut()).zip(s2.iter_mut()) {
+            let s0 = value + coefficient * *s1 - *s2;
+            *s2 = *s1;
+            *s1 = s0;
+        }
+    }
+    (0..frequencies.len())
+        .map(|k| s1[k] * s1[k] + s2[k] * s2[k] - coefficients[k] * s1[k] * s2[k])
+        .collect()
+}
+
+/// Hann windowed frames centered at multiples of `hop`, `frame_size` must be a power of two.
+pub fn stft(samples: &[f64], frame_size: usize, hop: usize) -> Vec<Vec<Complex>> {
+    let count = samples.len();
+    let frame_count = count / hop + 1;
+    let half = frame_size / 2;
+    let mut frames = Vec::with_capacity(frame_count);
+
+    for frame in 0..frame_count {
+        let mut data = vec![Complex::ZERO; frame_size];
+        let center = (frame * hop) as isize;
+        for n in 0..frame_size {
+            let index = center + n as isize - half as isize;
+            if index >= 0 && (index as usize) < count {
+                data[n].re = samples[index as usize] * hann_window(n, frame_size);
+            }
+        }
+        fft(&mut data, false);
+        frames.push(data);
+    }
+    frames
+}
+
+/// Weighted overlap-add resynthesis of frames produced by `stft`.
+pub fn istft(frames: &[Vec<Complex>], frame_size: usize, hop: usize, length: usize) -> Vec<f64> {
+    let half = frame_size / 2;
+    let mut result = vec![0.0; length];
+    let mut weight = vec![0.0; length];
+
+    for (frame, spectrum) in frames.iter().enumerate() {
+        let data = real_ifft(spectrum);
+        let center = (frame * hop) as isize;
+        for n in 0..frame_size {
+            let index = center + n as isize - half as isize;
+            if index >= 0 && (index as usize) < length {
+                let window = hann_window(n, frame_size);
+                result[index as usize] += data[n] * window;
+                weight[index as usize] += window * window;
+            }
+        }
+    }
+
+    for i in 0..length {
+        if weight[i] > 1.0e-9 {
+            result[i] /= weight[i];
+        }
+    }
+    result
+}
@@ -0,0 +1,447 @@
+#![allow(dead_code)]
+
+use fft::*;
+use math::*;
+
+/// Linear phase FIR low-pass at a quarter of the sample rate.
+/// Every second tap except the center one is zero.
+pub struct HalfBandFilter {
+    coefficients: Box<[f64]>,
+    history: Box<[f64]>,
+    position: usize,
+}
+
+/// Transposed direct form II, coefficients normalized so a0 = 1
+#[derive(Clone, Copy)]
+pub struct Biquad {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Oversampling {
+    None,
+    X2,
+    X4,
+    X8,
+}
+
+impl Oversampling {
+    pub fn factor(&self) -> usize {
+        match *self {
+            Oversampling::None => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+            Oversampling::X8 => 8,
+        }
+    }
+
+    pub fn stages(&self) -> usize {
+        match *self {
+            Oversampling::None => 0,
+            Oversampling::X2 => 1,
+            Oversampling::X4 => 2,
+            Oversampling::X8 => 3,
+        }
+    }
+}
+
+pub struct Oversampler {
+    pub oversampling: Oversampling,
+    up: Vec<HalfBandFilter>,
+    down: Vec<HalfBandFilter>,
+    buffer: Vec<f64>,
+    scratch: Vec<f64>,
+    delay: Vec<f64>,
+    delay_position: usize,
+}
+
+#[inline(always)]
+pub fn sinc(x: f64) -> f64 {
+    if abs_f64(x) < 1.0e-12 {
+        1.0
+    } else {
+        f64::sin(PI * x) / (PI * x)
+    }
+}
+
+#[inline(always)]
+pub fn blackman_window(n: usize, length: usize) -> f64 {
+    if length <= 1 {
+        return 1.0;
+    }
+    let phase = 2.0 * PI * n as f64 / (length - 1) as f64;
+    0.42 - 0.5 * f64::cos(phase) + 0.08 * f64::cos(2.0 * phase)
+}
+
+#[inline(always)]
+pub fn hann_window(n: usize, length: usize) -> f64 {
+    if length <= 1 {
+        return 1.0;
+    }
+    0.5 - 0.5 * f64::cos(2.0 * PI * n as f64 / length as f64)
+}
+
+impl Biquad {
+    pub fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn identity() -> Biquad {
+        Biquad::new(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+    }
+
+    pub fn low_pass(sample_rate: f64, frequency: f64, q: f64) -> Biquad {
+        let (cos_w, alpha) = biquad_prewarp(sample_rate, frequency, q);
+        let b1 = 1.0 - cos_w;
+        Biquad::new(
+            b1 / 2.0,
+            b1,
+            b1 / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn high_pass(sample_rate: f64, frequency: f64, q: f64) -> Biquad {
+        let (cos_w, alpha) = biquad_prewarp(sample_rate, frequency, q);
+        let b1 = 1.0 + cos_w;
+        Biquad::new(
+            b1 / 2.0,
+            -b1,
+            b1 / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn first_order_low_pass(sample_rate: f64, frequency: f64) -> Biquad {
+        let k = f64::tan(PI * frequency / sample_rate);
+        Biquad::new(k, k, 0.0, k + 1.0, k - 1.0, 0.0)
+    }
+
+    pub fn first_order_high_pass(sample_rate: f64, frequency: f64) -> Biquad {
+        let k = f64::tan(PI * frequency / sample_rate);
+        Biquad::new(1.0, -1.0, 0.0, k + 1.0, k - 1.0, 0.0)
+    }
+
+    /// Constant 0 dB peak gain
+    pub fn band_pass(sample_rate: f64, frequency: f64, q: f64) -> Biquad {
+        let (cos_w, alpha) = biquad_prewarp(sample_rate, frequency, q);
+        Biquad::new(alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos_w, 1.0 - alpha)
+    }
+
+    pub fn notch(sample_rate: f64, frequency: f64, q: f64) -> Biquad {
+        let (cos_w, alpha) = biquad_prewarp(sample_rate, frequency, q);
+        Biquad::new(
+            1.0,
+            -2.0 * cos_w,
+            1.0,
+            1.0 + alpha,
+            -2.0 * cos_w,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn peak(sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Biquad {
+        let (cos_w, alpha) = biquad_prewarp(sample_rate, frequency, q);
+        let a = f64::powf(10.0, gain_db / 40.0);
+        Biquad::new(
+            1.0 + alpha * a,
+            -2.0 * cos_w,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos_w,
+            1.0 - alpha / a,
+        )
+    }
+
+    pub fn low_shelf(sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Biquad {
+        let (cos_w, alpha) = biquad_prewarp(sample_rate, frequency, q);
+        let a = f64::powf(10.0, gain_db / 40.0);
+        let sqrt_a = 2.0 * square_root(a) * alpha;
+        Biquad::new(
+            a * ((a + 1.0) - (a - 1.0) * cos_w + sqrt_a),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w),
+            a * ((a + 1.0) - (a - 1.0) * cos_w - sqrt_a),
+            (a + 1.0) + (a - 1.0) * cos_w + sqrt_a,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos_w),
+            (a + 1.0) + (a - 1.0) * cos_w - sqrt_a,
+        )
+    }
+
+    pub fn high_shelf(sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Biquad {
+        let (cos_w, alpha) = biquad_prewarp(sample_rate, frequency, q);
+        let a = f64::powf(10.0, gain_db / 40.0);
+        let sqrt_a = 2.0 * square_root(a) * alpha;
+        Biquad::new(
+            a * ((a + 1.0) + (a - 1.0) * cos_w + sqrt_a),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w),
+            a * ((a + 1.0) + (a - 1.0) * cos_w - sqrt_a),
+            (a + 1.0) - (a - 1.0) * cos_w + sqrt_a,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos_w),
+            (a + 1.0) - (a - 1.0) * cos_w - sqrt_a,
+        )
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    /// Takes the coefficients of `other` but keeps the running state
+    pub fn set_coefficients(&mut self, other: &Biquad) {
+        self.b0 = other.b0;
+        self.b1 = other.b1;
+        self.b2 = other.b2;
+        self.a1 = other.a1;
+        self.a2 = other.a2;
+    }
+
+    #[inline(always)]
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    /// Complex frequency response at `frequency` Hz
+    pub fn response(&self, sample_rate: f64, frequency: f64) -> Complex {
+        let w = 2.0 * PI * frequency / sample_rate;
+        let z1 = Complex::from_polar(1.0, -w);
+        let z2 = Complex::from_polar(1.0, -2.0 * w);
+
+        let numerator = Complex::new(self.b0, 0.0) + z1.scale(self.b1) + z2.scale(self.b2);
+        let denominator = Complex::ONE + z1.scale(self.a1) + z2.scale(self.a2);
+        numerator.div(denominator)
+    }
+}
+
+#[inline(always)]
+fn biquad_prewarp(sample_rate: f64, frequency: f64, q: f64) -> (f64, f64) {
+    let nyquist = sample_rate / 2.0;
+    let frequency = clamp_f64(1.0, frequency, nyquist * 0.999);
+    let w = 2.0 * PI * frequency / sample_rate;
+    let q = max_f64(q, 0.01);
+    (f64::cos(w), f64::sin(w) / (2.0 * q))
+}
+
+/// Q values of the second order sections of an n-th order Butterworth filter.
+/// Odd orders need an extra first order section.
+pub fn butterworth_q(order: u32) -> Vec<f64> {
+    let mut result = Vec::new();
+    for k in 0..order / 2 {
+        let angle = PI * (2 * k + 1) as f64 / (2 * order) as f64;
+        result.push(1.0 / (2.0 * f64::sin(angle)));
+    }
+    result
+}
+
+pub fn process_cascade(sections: &mut [Biquad], input: f64) -> f64 {
+    let mut value = input;
+    for section in sections.iter_mut() {
+        value = section.process(value);
+    }
+    value
+}
+
+pub fn cascade_response(sections: &[Biquad], sample_rate: f64, frequency: f64) -> Complex {
+    let mut result = Complex::ONE;
+    for section in sections.iter() {
+        result = result * section.response(sample_rate, frequency);
+    }
+    result
+}
+
+impl HalfBandFilter {
+    /// Number of taps is rounded up to the nearest 4k + 3.
+    pub fn new(taps: usize) -> HalfBandFilter {
+        let mut length = max_u32(taps as u32, 7) as usize;
+        while length % 4 != 3 {
+            length += 1;
+        }
+
+        let center = (length - 1) / 2;
+        let mut coefficients = vec![0.0; length].into_boxed_slice();
+        let mut sum = 0.0;
+
+        for n in 0..length {
+            let offset = n as f64 - center as f64;
+            let value = if n != center && (n as i32 - center as i32) % 2 == 0 {
+                0.0
+            } else {
+                0.5 * sinc(0.5 * offset) * blackman_window(n, length)
+            };
+            coefficients[n] = value;
+            sum += value;
+        }
+
+        for n in 0..length {
+            coefficients[n] /= sum;
+        }
+
+        HalfBandFilter {
+            coefficients: coefficients,
+            history: vec![0.0; length].into_boxed_slice(),
+            position: 0,
+        }
+    }
+
+    /// Group delay in samples at the rate the filter runs at.
+    pub fn latency(&self) -> usize {
+        (self.coefficients.len() - 1) / 2
+    }
+
+    pub fn reset(&mut self) {
+        for value in self.history.iter_mut() {
+            *value = 0.0;
+        }
+        self.position = 0;
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let length = self.history.len();
+        self.history[self.position] = input;
+
+        let mut result = 0.0;
+        let mut index = self.position;
+        for n in 0..length {
+            let coefficient = self.coefficients[n];
+            if coefficient != 0.0 {
+                result += coefficient * self.history[index];
+            }
+            index = if index == 0 { length - 1 } else { index - 1 };
+        }
+
+        self.position = (self.position + 1) % length;
+        result
+    }
+}
+
+impl Oversampler {
+    pub fn new(oversampling: Oversampling) -> Oversampler {
+        let stages = oversampling.stages();
+        let mut up = Vec::with_capacity(stages);
+        let mut down = Vec::with_capacity(stages);
+
+        // The first stage does most of the work, later stages have a lot of
+        // headroom between the passband and the image so they can be shorter.
+        for stage in 0..stages {
+            let taps = if stage == 0 { 63 } else { 31 };
+            up.push(HalfBandFilter::new(taps));
+            down.push(HalfBandFilter::new(taps));
+        }
+
+        // Each filter delays by an odd number of samples at its own rate, pad the
+        // oversampled stream so the round trip is a whole number of base samples.
+        let factor = oversampling.factor();
+        let total = filter_delay(&up, factor);
+        let padding = (factor - total % factor) % factor;
+
+        Oversampler {
+            oversampling: oversampling,
+            up: up,
+            down: down,
+            buffer: Vec::with_capacity(factor),
+            scratch: Vec::with_capacity(factor),
+            delay: vec![0.0; padding],
+            delay_position: 0,
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.oversampling.factor()
+    }
+
+    /// Round trip delay of upsampling followed by downsampling, in base rate samples.
+    pub fn latency(&self) -> usize {
+        let factor = self.factor();
+        (filter_delay(&self.up, factor) + self.delay.len()) / factor
+    }
+
+    pub fn reset(&mut self) {
+        for filter in self.up.iter_mut() {
+            filter.reset();
+        }
+        for filter in self.down.iter_mut() {
+            filter.reset();
+        }
+        for value in self.delay.iter_mut() {
+            *value = 0.0;
+        }
+        self.delay_position = 0;
+    }
+
+    /// Fills `output` with `factor()` samples at the oversampled rate.
+    pub fn upsample(&mut self, input: f64, output: &mut Vec<f64>) {
+        output.clear();
+        output.push(input);
+
+        for stage in 0..self.up.len() {
+            self.buffer.clear();
+            let filter = &mut self.up[stage];
+            for i in 0..output.len() {
+                self.buffer.push(2.0 * filter.process(output[i]));
+                self.buffer.push(2.0 * filter.process(0.0));
+            }
+            output.clear();
+            output.extend_from_slice(&self.buffer);
+        }
+    }
+
+    /// Takes `factor()` samples at the oversampled rate and returns one.
+    pub fn downsample(&mut self, input: &[f64]) -> f64 {
+        self.scratch.clear();
+        if self.delay.len() > 0 {
+            let length = self.delay.len();
+            for i in 0..input.len() {
+                self.scratch.push(self.delay[self.delay_position]);
+                self.delay[self.delay_position] = input[i];
+                self.delay_position = (self.delay_position + 1) % length;
+            }
+        } else {
+            self.scratch.extend_from_slice(input);
+        }
+
+        let stages = self.down.len();
+        for stage in (0..stages).rev() {
+            let filter = &mut self.down[stage];
+            let half = self.scratch.len() / 2;
+            for i in 0..half {
+                // Keep the even phase so the output lines up with the input samples.
+                let output = filter.process(self.scratch[2 * i]);
+                filter.process(self.scratch[2 * i + 1]);
+                self.scratch[i] = output;
+            }
+            self.scratch.truncate(half);
+        }
+
+        self.scratch[0]
+    }
+}
+
+/// Round trip delay of the up and down filters, in samples at the oversampled rate.
+fn filter_delay(filters: &Vec<HalfBandFilter>, factor: usize) -> usize {
+    let mut total = 0;
+    for stage in 0..filters.len() {
+        let rate = 2 << stage;
+        total += 2 * filters[stage].latency() * (factor / rate);
+    }
+    total
+}
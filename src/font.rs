@@ -0,0 +1,598 @@
+#![allow(dead_code)]
+
+use std::char;
+use std::collections::HashMap;
+use std::io;
+use std::str;
+
+use math::*;
+use render::*;
+use tools::*;
+use truetype::*;
+
+/// One coverage value per pixel, 255 is fully covered
+pub struct Glyph {
+    pub width: i32,
+    pub height: i32,
+    /// From the pen position on the baseline to the top left corner of the bitmap
+    pub left: i32,
+    pub top: i32,
+    pub advance: i32,
+    pub coverage: Vec<u8>,
+}
+
+pub struct Font {
+    pub ascent: i32,
+    pub descent: i32,
+    pub line_gap: i32,
+    pub glyphs: HashMap<char, Glyph>,
+    /// Added to the advance between a pair of characters
+    pub kerning: HashMap<(char, char), i32>,
+    /// Drawn for characters the font does not have
+    pub fallback: char,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+/// Printable ASCII from the space on, five columns of seven rows, bit 0 is the top row
+const BUILTIN_GLYPHS: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5F, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50],
+    [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00],
+    [0x14, 0x08, 0x3E, 0x08, 0x14],
+    [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E],
+    [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46],
+    [0x21, 0x41, 0x45, 0x4B, 0x31],
+    [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3C, 0x4A, 0x49, 0x49, 0x30],
+    [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x06, 0x49, 0x49, 0x29, 0x1E],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E],
+    [0x7F, 0x49, 0x49, 0x49, 0x36],
+    [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C],
+    [0x7F, 0x49, 0x49, 0x49, 0x41],
+    [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x49, 0x49, 0x7A],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F],
+    [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01],
+    [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F],
+    [0x7F, 0x04, 0x08, 0x10, 0x7F],
+    [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06],
+    [0x3E, 0x41, 0x51, 0x21, 0x5E],
+    [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7F, 0x01, 0x01],
+    [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F],
+    [0x3F, 0x40, 0x38, 0x40, 0x3F],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07],
+    [0x61, 0x51, 0x49, 0x45, 0x43],
+    [0x00, 0x7F, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x7F, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00],
+    [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7F, 0x48, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x08, 0x7E, 0x09, 0x01, 0x02],
+    [0x0C, 0x52, 0x52, 0x52, 0x3E],
+    [0x7F, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7D, 0x40, 0x00],
+    [0x20, 0x40, 0x44, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7F, 0x40, 0x00],
+    [0x7C, 0x04, 0x18, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7C, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7C],
+    [0x7C, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3F, 0x44, 0x40, 0x20],
+    [0x3C, 0x40, 0x40, 0x20, 0x7C],
+    [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x0C, 0x50, 0x50, 0x50, 0x3C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7F, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+pub fn invalid_font(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Glyph {
+    pub fn empty(advance: i32) -> Glyph {
+        Glyph {
+            width: 0,
+            height: 0,
+            left: 0,
+            top: 0,
+            advance: advance,
+            coverage: Vec::new(),
+        }
+    }
+
+    /// Rows of `bytes_per_row` bytes, most significant bit first
+    pub fn from_bits(
+        width: i32,
+        height: i32,
+        bytes_per_row: usize,
+        bits: &[u8],
+        left: i32,
+        top: i32,
+        advance: i32,
+    ) -> Glyph {
+        let mut coverage = vec![0u8; width as usize * height as usize];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let byte = bits.get(y * bytes_per_row + x / 8).cloned().unwrap_or(0);
+                if byte & (0x80 >> (x % 8)) != 0 {
+                    coverage[y * width as usize + x] = 255;
+                }
+            }
+        }
+        Glyph {
+            width: width,
+            height: height,
+            left: left,
+            top: top,
+            advance: advance,
+            coverage: coverage,
+        }
+    }
+}
+
+impl Font {
+    pub fn new(ascent: i32, descent: i32, line_gap: i32) -> Font {
+        Font {
+            ascent: ascent,
+            descent: descent,
+            line_gap: line_gap,
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+            fallback: '?',
+        }
+    }
+
+    /// 5x7 pixel ASCII font in a 6x9 cell
+    pub fn builtin() -> Font {
+        let mut font = Font::new(7, 1, 1);
+        for (index, columns) in BUILTIN_GLYPHS.iter().enumerate() {
+            let mut coverage = vec![0u8; 5 * 7];
+            for (x, column) in columns.iter().enumerate() {
+                for y in 0..7 {
+                    if column & (1 << y) != 0 {
+                        coverage[y * 5 + x] = 255;
+                    }
+                }
+            }
+            let glyph = Glyph {
+                width: 5,
+                height: 7,
+                left: 0,
+                top: -7,
+                advance: 6,
+                coverage: coverage,
+            };
+            font.glyphs.insert((0x20 + index as u8) as char, glyph);
+        }
+        font
+    }
+
+    /// Glyph bitmaps from a BDF file, encodings are taken as Unicode
+    pub fn from_bdf(text: &str) -> io::Result<Font> {
+        let mut font = Font::new(0, 0, 0);
+        let mut bounding_box = (0, 0, 0, 0);
+        let mut lines = text.lines();
+
+        let parse = |value: Option<&str>| -> io::Result<i32> {
+            value
+                .and_then(|value| value.parse::<i32>().ok())
+                .ok_or(invalid_font("Malformed BDF number"))
+        };
+
+        let mut encoding = -1;
+        let mut advance = 0;
+        let mut glyph_box = (0, 0, 0, 0);
+        while let Some(line) = lines.next() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("FONTBOUNDINGBOX") => {
+                    bounding_box = (
+                        parse(words.next())?,
+                        parse(words.next())?,
+                        parse(words.next())?,
+                        parse(words.next())?,
+                    );
+                }
+                Some("FONT_ASCENT") => font.ascent = parse(words.next())?,
+                Some("FONT_DESCENT") => font.descent = parse(words.next())?,
+                Some("STARTCHAR") => {
+                    encoding = -1;
+                    advance = bounding_box.0;
+                    glyph_box = bounding_box;
+                }
+                Some("ENCODING") => encoding = parse(words.next())?,
+                Some("DWIDTH") => advance = parse(words.next())?,
+                Some("BBX") => {
+                    glyph_box = (
+                        parse(words.next())?,
+                        parse(words.next())?,
+                        parse(words.next())?,
+                        parse(words.next())?,
+                    );
+                }
+                Some("BITMAP") => {
+                    let (width, height, x_offset, y_offset) = glyph_box;
+                    if width < 0 || height < 0 || width > 1024 || height > 1024 {
+                        return Err(invalid_font("BDF glyph too large"));
+                    }
+                    let bytes_per_row = (width as usize + 7) / 8;
+                    let mut bits = Vec::with_capacity(bytes_per_row * height as usize);
+                    for _ in 0..height {
+                        let row = lines.next().ok_or(invalid_font("Truncated BDF bitmap"))?;
+                        let row = row.trim();
+                        for i in 0..bytes_per_row {
+                            let byte = row
+                                .get(i * 2..i * 2 + 2)
+                                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                                .unwrap_or(0);
+                            bits.push(byte);
+                        }
+                    }
+                    let character = if encoding >= 0 {
+                        char::from_u32(encoding as u32)
+                    } else {
+                        None
+                    };
+                    if let Some(character) = character {
+                        let glyph = Glyph::from_bits(
+                            width,
+                            height,
+                            bytes_per_row,
+                            &bits,
+                            x_offset,
+                            -(y_offset + height),
+                            advance,
+                        );
+                        font.glyphs.insert(character, glyph);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if font.glyphs.is_empty() {
+            return Err(invalid_font("BDF file has no glyphs"));
+        }
+        if font.ascent == 0 && font.descent == 0 {
+            font.ascent = bounding_box.1 + bounding_box.3;
+            font.descent = -bounding_box.3;
+        }
+        Ok(font)
+    }
+
+    /// PC screen font, version 1 or 2. Without a Unicode table glyph n is character n.
+    /// The baseline is put a quarter of the height from the bottom.
+    pub fn from_psf(data: &[u8]) -> io::Result<Font> {
+        let truncated = || invalid_font("Truncated PSF file");
+        let read_u32 = |offset: usize| -> io::Result<u32> {
+            data.get(offset..offset + 4)
+                .map(|bytes| {
+                    bytes[0] as u32
+                        | (bytes[1] as u32) << 8
+                        | (bytes[2] as u32) << 16
+                        | (bytes[3] as u32) << 24
+                })
+                .ok_or(truncated())
+        };
+
+        let (glyph_count, glyph_size, width, height, header_size, has_table, version) =
+            if data.len() >= 4 && data[0] == 0x36 && data[1] == 0x04 {
+                let mode = data[2];
+                let count = if mode & 0x01 != 0 { 512 } else { 256 };
+                let size = data[3] as usize;
+                (count, size, 8, size as i32, 4, mode & 0x06 != 0, 1)
+            } else if data.len() >= 32 && data[0..4] == [0x72, 0xB5, 0x4A, 0x86] {
+                let header_size = read_u32(8)? as usize;
+                let flags = read_u32(12)?;
+                let count = read_u32(16)? as usize;
+                let size = read_u32(20)? as usize;
+                let height = read_u32(24)?;
+                let width = read_u32(28)?;
+                if width == 0 || height == 0 || width > 256 || height > 256 || count > 0x10000 {
+                    return Err(invalid_font("Unsupported PSF glyph size"));
+                }
+                (
+                    count,
+                    size,
+                    width as i32,
+                    height as i32,
+                    header_size,
+                    flags & 0x01 != 0,
+                    2,
+                )
+            } else {
+                return Err(invalid_font("Not a PSF file"));
+            };
+
+        let bytes_per_row = (width as usize + 7) / 8;
+        if glyph_size < bytes_per_row * height as usize {
+            return Err(invalid_font("PSF glyph size does not match its dimensions"));
+        }
+        let table_start = header_size + glyph_count * glyph_size;
+        if data.len() < table_start {
+            return Err(truncated());
+        }
+
+        let descent = height / 4;
+        let mut font = Font::new(height - descent, descent, 0);
+        let glyph = |index: usize| {
+            let start = header_size + index * glyph_size;
+            Glyph::from_bits(
+                width,
+                height,
+                bytes_per_row,
+                &data[start..start + glyph_size],
+                0,
+                -(height - descent),
+                width,
+            )
+        };
+
+        if !has_table {
+            for index in 0..glyph_count {
+                if let Some(character) = char::from_u32(index as u32) {
+                    font.glyphs.insert(character, glyph(index));
+                }
+            }
+            return Ok(font);
+        }
+
+        // Each glyph lists its characters, then sequences after 0xFFFE (version 1) or
+        // 0xFE (version 2) that are skipped, and ends with 0xFFFF or 0xFF.
+        let mut offset = table_start;
+        for index in 0..glyph_count {
+            let mut characters = Vec::new();
+            let mut in_sequence = false;
+            if version == 1 {
+                loop {
+                    let bytes = data.get(offset..offset + 2).ok_or(truncated())?;
+                    offset += 2;
+                    let value = bytes[0] as u32 | (bytes[1] as u32) << 8;
+                    match value {
+                        0xFFFF => break,
+                        0xFFFE => in_sequence = true,
+                        _ if !in_sequence => characters.extend(char::from_u32(value)),
+                        _ => {}
+                    }
+                }
+            } else {
+                let end = data[offset..]
+                    .iter()
+                    .position(|byte| *byte == 0xFF)
+                    .ok_or(truncated())?;
+                let entry = &data[offset..offset + end];
+                offset += end + 1;
+                let single = match entry.iter().position(|byte| *byte == 0xFE) {
+                    Some(position) => &entry[..position],
+                    None => entry,
+                };
+                let text = str::from_utf8(single).map_err(|_| invalid_font("Bad PSF table"))?;
+                characters.extend(text.chars());
+            }
+            for character in characters {
+                font.glyphs.insert(character, glyph(index));
+            }
+        }
+        Ok(font)
+    }
+
+    /// Printable Latin-1 rasterized at `pixel_size` pixels per em, with the font's
+    /// kerning pairs between them
+    pub fn from_truetype(data: &[u8], pixel_size: f64) -> io::Result<Font> {
+        let truetype = TrueType::parse(data)?;
+        let characters: String = (0x20..0x7F)
+            .chain(0xA0..0x100)
+            .filter_map(char::from_u32)
+            .collect();
+        Ok(truetype.font(pixel_size, &characters))
+    }
+
+    /// Picks the parser from the extension, `pixel_size` only applies to TrueType
+    pub fn load(path: &str, pixel_size: f64) -> io::Result<Font> {
+        let data = read_file(path)?;
+        let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "bdf" => {
+                let text = str::from_utf8(&data).map_err(|_| invalid_font("BDF is not text"))?;
+                Font::from_bdf(text)
+            }
+            "psf" | "psfu" => Font::from_psf(&data),
+            "ttf" => Font::from_truetype(&data, pixel_size),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unknown font file extension",
+            )),
+        }
+    }
+
+    pub fn line_height(&self) -> i32 {
+        self.ascent + self.descent + self.line_gap
+    }
+
+    pub fn glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs
+            .get(&character)
+            .or_else(|| self.glyphs.get(&self.fallback))
+    }
+
+    pub fn kerning(&self, left: char, right: char) -> i32 {
+        self.kerning.get(&(left, right)).cloned().unwrap_or(0)
+    }
+
+    /// Advance of one line, kerning included
+    pub fn line_width(&self, line: &str) -> i32 {
+        let mut width = 0;
+        let mut previous = None;
+        for character in line.chars() {
+            if let Some(previous) = previous {
+                width += self.kerning(previous, character);
+            }
+            width += self.glyph(character).map_or(0, |glyph| glyph.advance);
+            previous = Some(character);
+        }
+        width
+    }
+
+    /// Width of the widest line and height of all lines
+    pub fn measure(&self, text: &str) -> (i32, i32) {
+        let mut width = 0;
+        let mut lines = 0;
+        for line in text.split('\n') {
+            width = max_i32(width, self.line_width(line));
+            lines += 1;
+        }
+        (width, lines * self.line_height())
+    }
+}
+
+fn align_offset(align: Align, size: i32) -> i32 {
+    match align {
+        Align::Start => 0,
+        Align::Center => size / 2,
+        Align::End => size,
+    }
+}
+
+impl Image {
+    /// Blends the glyph coverage times the color alpha, only inside `clip`
+    pub fn draw_glyph(&mut self, glyph: &Glyph, x: i32, y: i32, clip: &Rect, color: Color) {
+        let (r, g, b, a) = color.separate();
+        let bounds = Rect::new(x, y, glyph.width, glyph.height)
+            .intersect(clip)
+            .intersect(&Rect::new(0, 0, self.width, self.height));
+
+        let width = self.width;
+        for row in bounds.y..bounds.y + bounds.height {
+            for column in bounds.x..bounds.x + bounds.width {
+                let index = ((row - y) * glyph.width + column - x) as usize;
+                let coverage = glyph.coverage[index] as u32;
+                if coverage == 0 {
+                    continue;
+                }
+                let alpha = (a * coverage + 127) / 255;
+                plot_point(
+                    column,
+                    row,
+                    width,
+                    Color::from_rgba(r, g, b, alpha),
+                    &mut self.color_data,
+                );
+            }
+        }
+    }
+
+    /// Draws `text` with the top left corner of its first line at `position`
+    pub fn draw_text(&mut self, font: &Font, text: &str, position: &Vector2, color: Color) {
+        let clip = Rect::new(0, 0, self.width, self.height);
+        self.draw_text_clipped(
+            font,
+            text,
+            position,
+            (Align::Start, Align::Start),
+            &clip,
+            color,
+        );
+    }
+
+    /// `align` places the text block horizontally and vertically relative to `position`,
+    /// every line is aligned on its own within the block
+    pub fn draw_text_aligned(
+        &mut self,
+        font: &Font,
+        text: &str,
+        position: &Vector2,
+        align: (Align, Align),
+        color: Color,
+    ) {
+        let clip = Rect::new(0, 0, self.width, self.height);
+        self.draw_text_clipped(font, text, position, align, &clip, color);
+    }
+
+    /// Like `draw_text_aligned` but nothing is drawn outside `clip`
+    pub fn draw_text_clipped(
+        &mut self,
+        font: &Font,
+        text: &str,
+        position: &Vector2,
+        align: (Align, Align),
+        clip: &Rect,
+        color: Color,
+    ) {
+        let (horizontal, vertical) = align;
+        let (block_width, block_height) = font.measure(text);
+        let left = round_f64_i32(position.x) - align_offset(horizontal, block_width);
+        let mut top = round_f64_i32(position.y) - align_offset(vertical, block_height);
+
+        for line in text.split('\n') {
+            let line_width = font.line_width(line);
+            let mut pen = left + align_offset(horizontal, block_width - line_width);
+            let baseline = top + font.ascent;
+            let mut previous = None;
+            for character in line.chars() {
+                if let Some(previous) = previous {
+                    pen += font.kerning(previous, character);
+                }
+                if let Some(glyph) = font.glyph(character) {
+                    self.draw_glyph(glyph, pen + glyph.left, baseline + glyph.top, clip, color);
+                    pen += glyph.advance;
+                }
+                previous = Some(character);
+            }
+            top += font.line_height();
+        }
+    }
+}
@@ -0,0 +1,528 @@
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use codec::*;
+use math::*;
+use platform::*;
+use render::*;
+
+/// Which presented frames are written to disk, counted from 0
+pub enum FrameCapture {
+    Nothing,
+    All,
+    Every(u64),
+    Frames(Vec<u64>),
+}
+
+/// Runs the viewer without a window: input comes from a script, frames are kept in memory
+/// and written out as requested, and the clock advances exactly one frame per
+/// `wait_for_frame` unless `real_time` is set, so runs are reproducible.
+pub struct HeadlessPlatform {
+    pub width: i32,
+    pub height: i32,
+    pub frame_rate: f64,
+    /// Quits after this many presented frames
+    pub max_frames: Option<u64>,
+    /// Sleep like a windowed platform instead of running as fast as possible
+    pub real_time: bool,
+    pub capture: FrameCapture,
+    pub frame_format: ImageFormat,
+    pub output_directory: String,
+    /// Paths of the frames written so far
+    pub written: Vec<String>,
+    pub frame: u64,
+    pub last_frame: Option<Image>,
+    /// Pending input with the frame it is delivered on, ordered by frame
+    script: VecDeque<(u64, Message)>,
+    start: Instant,
+    simulated_time: f64,
+    last_counter: f64,
+}
+
+impl FrameCapture {
+    pub fn contains(&self, frame: u64) -> bool {
+        match *self {
+            FrameCapture::Nothing => false,
+            FrameCapture::All => true,
+            FrameCapture::Every(step) => step > 0 && frame % step == 0,
+            FrameCapture::Frames(ref frames) => frames.contains(&frame),
+        }
+    }
+}
+
+impl HeadlessPlatform {
+    pub fn new(width: i32, height: i32) -> HeadlessPlatform {
+        HeadlessPlatform {
+            width: width,
+            height: height,
+            frame_rate: 60.0,
+            max_frames: None,
+            real_time: false,
+            capture: FrameCapture::Nothing,
+            frame_format: ImageFormat::Png,
+            output_directory: String::from("frames"),
+            written: Vec::new(),
+            frame: 0,
+            last_frame: None,
+            script: VecDeque::new(),
+            start: Instant::now(),
+            simulated_time: 0.0,
+            last_counter: 0.0,
+        }
+    }
+
+    /// Reads the options following `--headless`:
+    /// `--size 1280x720 --fps 60 --frames 120 --script input.txt --capture all|every:10|0,5,9
+    /// --format png|bmp|ppm|pgm|tga --output frames --real-time`
+    pub fn from_args(args: &[String]) -> Result<HeadlessPlatform, String> {
+        let mut platform = HeadlessPlatform::new(1280, 720);
+        platform.max_frames = Some(1);
+        platform.capture = FrameCapture::All;
+
+        let mut i = 0;
+        while i < args.len() {
+            let option = args[i].as_str();
+            let value = args
+                .get(i + 1)
+                .map(|value| value.as_str())
+                .ok_or(format!("{0} needs a value", option));
+            match option {
+                "--headless" => {}
+                "--real-time" => platform.real_time = true,
+                "--size" => {
+                    let size = value?;
+                    let mut parts = size.split('x');
+                    match (
+                        parts.next().and_then(|w| w.parse::<i32>().ok()),
+                        parts.next().and_then(|h| h.parse::<i32>().ok()),
+                    ) {
+                        (Some(width), Some(height)) if width > 0 && height > 0 => {
+                            platform.width = width;
+                            platform.height = height;
+                        }
+                        _ => return Err(format!("Invalid size {0}", size)),
+                    }
+                    i += 1;
+                }
+                "--fps" => {
+                    let fps = value?;
+                    match fps.parse::<f64>() {
+                        Ok(fps) if fps > 0.0 => platform.frame_rate = fps,
+                        _ => return Err(format!("Invalid frame rate {0}", fps)),
+                    }
+                    i += 1;
+                }
+                "--frames" => {
+                    let frames = value?;
+                    match frames.parse::<u64>() {
+                        Ok(frames) => platform.max_frames = Some(frames),
+                        _ => return Err(format!("Invalid frame count {0}", frames)),
+                    }
+                    i += 1;
+                }
+                "--script" => {
+                    platform.load_script(value?)?;
+                    i += 1;
+                }
+                "--capture" => {
+                    platform.capture = parse_capture(value?)?;
+                    i += 1;
+                }
+                "--format" => {
+                    let format = value?;
+                    match ImageFormat::from_path(&format!(".{0}", format)) {
+                        Some(format) => platform.frame_format = format,
+                        None => return Err(format!("Unknown image format {0}", format)),
+                    }
+                    i += 1;
+                }
+                "--output" => {
+                    platform.output_directory = String::from(value?);
+                    i += 1;
+                }
+                _ => return Err(format!("Unknown option {0}", option)),
+            }
+            i += 1;
+        }
+        Ok(platform)
+    }
+
+    /// Queues input for the current frame
+    pub fn push_message(&mut self, message: Message) {
+        let frame = self.frame;
+        self.schedule(frame, message);
+    }
+
+    /// Queues input delivered before rendering `frame`, after anything already queued for it
+    pub fn schedule(&mut self, frame: u64, message: Message) {
+        let index = self
+            .script
+            .iter()
+            .position(|&(queued, _)| queued > frame)
+            .unwrap_or(self.script.len());
+        self.script.insert(index, (frame, message));
+    }
+
+    pub fn load_script(&mut self, path: &str) -> Result<(), String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|error| format!("{0}: {1}", path, error))?;
+        for (frame, message) in parse_script(&text)? {
+            self.schedule(frame, message);
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, image: &Image) -> io::Result<()> {
+        fs::create_dir_all(&self.output_directory)?;
+        let path = format!(
+            "{0}/frame_{1:05}.{2}",
+            self.output_directory,
+            self.frame,
+            self.frame_format.extension()
+        );
+        image.save(&path)?;
+        self.written.push(path);
+        Ok(())
+    }
+}
+
+impl Platform for HeadlessPlatform {
+    /// Keeps the configured size so output does not depend on what the viewer asks for
+    fn create_window(&mut self, _title: &str, _width: i32, _height: i32) -> bool {
+        true
+    }
+
+    fn dimensions(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn poll_message(&mut self) -> Message {
+        if let Some(max_frames) = self.max_frames {
+            if self.frame >= max_frames {
+                return Message::Quit;
+            }
+        }
+        let due = match self.script.front() {
+            Some(&(frame, _)) => frame <= self.frame,
+            None => false,
+        };
+        if !due {
+            return Message::Silence;
+        }
+        match self.script.pop_front() {
+            Some((_, message)) => {
+                if let Message::Resize(width, height) = message {
+                    self.width = width;
+                    self.height = height;
+                }
+                message
+            }
+            None => Message::Silence,
+        }
+    }
+
+    fn present(&mut self, image: &Image) {
+        if self.capture.contains(self.frame) {
+            if let Err(error) = self.write_frame(image) {
+                println!("Writing frame {0} failed: {1}", self.frame, error);
+            }
+        }
+        let data = image.color_data.to_vec().into_boxed_slice();
+        self.last_frame = Some(Image::from_data(image.width, image.height, data));
+        self.frame += 1;
+    }
+
+    /// Copies only the regions into the kept frame, captures show what a window would
+    fn present_regions(&mut self, image: &Image, regions: &[Rect2i]) {
+        let mut frame = match self.last_frame.take() {
+            Some(frame) => frame,
+            None => return self.present(image),
+        };
+        if frame.width != image.width || frame.height != image.height {
+            return self.present(image);
+        }
+        let bounds = Rect2i::new(0, 0, image.width, image.height);
+        for region in regions.iter() {
+            let region = region.intersect(&bounds);
+            if region.is_empty() {
+                continue;
+            }
+            for y in region.min_y..region.max_y {
+                let from = (y * image.width + region.min_x) as usize;
+                let to = (y * image.width + region.max_x) as usize;
+                frame.color_data[from..to].copy_from_slice(&image.color_data[from..to]);
+            }
+        }
+        if self.capture.contains(self.frame) {
+            if let Err(error) = self.write_frame(&frame) {
+                println!("Writing frame {0} failed: {1}", self.frame, error);
+            }
+        }
+        self.last_frame = Some(frame);
+        self.frame += 1;
+    }
+
+    fn wall_clock(&self) -> f64 {
+        if self.real_time {
+            let elapsed = self.start.elapsed();
+            elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1.0e-9
+        } else {
+            self.simulated_time
+        }
+    }
+
+    fn refresh_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    fn wait_for_frame(&mut self, target_seconds_per_frame: f64) -> f64 {
+        if self.real_time {
+            let mut last_counter = self.last_counter;
+            let delta_time = wait_until(self, &mut last_counter, target_seconds_per_frame, true);
+            self.last_counter = last_counter;
+            delta_time
+        } else {
+            self.simulated_time += target_seconds_per_frame;
+            target_seconds_per_frame
+        }
+    }
+}
+
+fn parse_capture(text: &str) -> Result<FrameCapture, String> {
+    if text == "all" {
+        return Ok(FrameCapture::All);
+    }
+    if text == "none" {
+        return Ok(FrameCapture::Nothing);
+    }
+    if text.starts_with("every:") {
+        return match text["every:".len()..].parse::<u64>() {
+            Ok(step) if step > 0 => Ok(FrameCapture::Every(step)),
+            _ => Err(format!("Invalid capture step {0}", text)),
+        };
+    }
+    let mut frames = Vec::new();
+    for part in text.split(',') {
+        match part.trim().parse::<u64>() {
+            Ok(frame) => frames.push(frame),
+            Err(_) => return Err(format!("Invalid capture frame {0}", part)),
+        }
+    }
+    Ok(FrameCapture::Frames(frames))
+}
+
+fn parse_key(text: &str) -> Option<u32> {
+    let lower = text.to_lowercase();
+    let key = match lower.as_str() {
+        "space" => 0x20,
+        "enter" | "return" => 0x0D,
+        "escape" | "esc" => 0x1B,
+        "backspace" => 0x08,
+        "tab" => 0x09,
+        "left" => 0x25,
+        "up" => 0x26,
+        "right" => 0x27,
+        "down" => 0x28,
+        "shift" => 0x10,
+        "control" | "ctrl" => 0x11,
+        "alt" => 0x12,
+        _ => {
+            if lower.starts_with("0x") {
+                return u32::from_str_radix(&lower[2..], 16)
+                    .ok()
+                    .filter(|key| (*key as usize) < KEY_COUNT);
+            }
+            let mut chars = text.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase() as u32),
+                _ => None,
+            };
+        }
+    };
+    Some(key)
+}
+
+fn parse_button(text: &str) -> Option<MouseButton> {
+    match text {
+        "left" => Some(MouseButton::Left),
+        "right" => Some(MouseButton::Right),
+        "middle" => Some(MouseButton::Middle),
+        _ => None,
+    }
+}
+
+/// One event per line as `<frame> <event> <arguments>`, `#` starts a comment:
+///
+/// ```text
+/// 0 key_down B
+/// 1 key_up B
+/// 10 mouse_down left 200 300
+/// 11 mouse_move 400 300
+/// 12 mouse_up left 400 300
+/// 20 resize 800 600
+/// 30 quit
+/// ```
+pub fn parse_script(text: &str) -> Result<Vec<(u64, Message)>, String> {
+    let mut events = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        let error = || format!("Line {0}: cannot parse \"{1}\"", index + 1, line.trim());
+        let number = |i: usize| words.get(i).and_then(|word| word.parse::<i32>().ok());
+        let frame = words[0].parse::<u64>().map_err(|_| error())?;
+        let event = words.get(1).map(|word| *word).unwrap_or("");
+
+        let message = match (event, words.len()) {
+            ("quit", 2) => Some(Message::Quit),
+            ("key_down", 3) => parse_key(words[2]).map(Message::KeyDown),
+            ("key_up", 3) => parse_key(words[2]).map(Message::KeyUp),
+            ("mouse_down", 5) => match (parse_button(words[2]), number(3), number(4)) {
+                (Some(button), Some(x), Some(y)) => Some(Message::MouseDown(button, x, y)),
+                _ => None,
+            },
+            ("mouse_up", 5) => match (parse_button(words[2]), number(3), number(4)) {
+                (Some(button), Some(x), Some(y)) => Some(Message::MouseUp(button, x, y)),
+                _ => None,
+            },
+            ("mouse_move", 4) => match (number(2), number(3)) {
+                (Some(x), Some(y)) => Some(Message::MouseMove(x, y)),
+                _ => None,
+            },
+            ("resize", 4) => match (number(2), number(3)) {
+                (Some(width), Some(height)) if width > 0 && height > 0 => {
+                    Some(Message::Resize(width, height))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match message {
+            Some(message) => events.push((frame, message)),
+            None => return Err(error()),
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_events() {
+        let script = "# warm up\n\
+                      0 key_down b\n\
+                      1 key_up 0x1FF  # last key code\n\
+                      \n\
+                      10 mouse_down left 200 300\n\
+                      11 mouse_move 400 -5\n\
+                      12 mouse_up right 400 300\n\
+                      20 resize 800 600\n\
+                      30 quit\n";
+        let events = parse_script(script).unwrap();
+        let frames: Vec<u64> = events.iter().map(|event| event.0).collect();
+        assert_eq!(frames, vec![0, 1, 10, 11, 12, 20, 30]);
+        match events[0].1 {
+            Message::KeyDown(key) => assert_eq!(key, 'B' as u32),
+            _ => panic!("expected key_down"),
+        }
+        match events[1].1 {
+            Message::KeyUp(key) => assert_eq!(key, 0x1FF),
+            _ => panic!("expected key_up"),
+        }
+        match events[2].1 {
+            Message::MouseDown(MouseButton::Left, 200, 300) => {}
+            _ => panic!("expected mouse_down"),
+        }
+        match events[3].1 {
+            Message::MouseMove(400, -5) => {}
+            _ => panic!("expected mouse_move"),
+        }
+        match events[4].1 {
+            Message::MouseUp(MouseButton::Right, 400, 300) => {}
+            _ => panic!("expected mouse_up"),
+        }
+        match events[5].1 {
+            Message::Resize(800, 600) => {}
+            _ => panic!("expected resize"),
+        }
+        match events[6].1 {
+            Message::Quit => {}
+            _ => panic!("expected quit"),
+        }
+    }
+
+    #[test]
+    fn script_errors_name_the_line() {
+        let bad = [
+            "0 key_down 0x200",
+            "0 key_down F1",
+            "0 key_down",
+            "x quit",
+            "-1 quit",
+            "0 quit now",
+            "0 mouse_down top 1 2",
+            "0 mouse_move 1",
+            "0 resize 0 600",
+            "0 scroll 3",
+        ];
+        for line in bad.iter() {
+            let text = format!("0 quit\n{0}\n", line);
+            match parse_script(&text) {
+                Err(error) => assert!(error.starts_with("Line 2:"), "{0}", error),
+                Ok(_) => panic!("accepted {0}", line),
+            }
+        }
+    }
+
+    #[test]
+    fn capture_lists() {
+        let every = parse_capture("every:10").unwrap();
+        assert!(every.contains(0) && every.contains(20) && !every.contains(5));
+        let frames = parse_capture("0, 5,9").unwrap();
+        assert!(frames.contains(5) && frames.contains(9) && !frames.contains(1));
+        assert!(parse_capture("all").unwrap().contains(12345));
+        assert!(!parse_capture("none").unwrap().contains(0));
+        assert!(!FrameCapture::Every(0).contains(0));
+
+        assert!(parse_capture("every:0").is_err());
+        assert!(parse_capture("every:x").is_err());
+        assert!(parse_capture("1,,2").is_err());
+        assert!(parse_capture("some").is_err());
+    }
+
+    #[test]
+    fn unknown_options_are_errors() {
+        let args: Vec<String> = ["--headless", "--frame", "10"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        match HeadlessPlatform::from_args(&args) {
+            Err(error) => assert_eq!(error, "Unknown option --frame"),
+            Ok(_) => panic!("accepted --frame"),
+        }
+        let args: Vec<String> = ["--headless", "--frames", "10", "--capture", "none"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        let platform = HeadlessPlatform::from_args(&args).unwrap();
+        assert_eq!(platform.max_frames, Some(10));
+        assert!(!platform.capture.contains(0));
+    }
+}
@@ -0,0 +1,282 @@
+extern crate x11;
+
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ptr;
+use std::time::Instant;
+
+use self::x11::xlib;
+
+use math::*;
+use platform::*;
+use render::*;
+
+pub struct LinuxPlatform {
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    gc: xlib::GC,
+    delete_window: xlib::Atom,
+    width: i32,
+    height: i32,
+    /// Parts of the window were uncovered, the next present has to show everything
+    exposed: bool,
+    start: Instant,
+    last_counter: f64,
+}
+
+/// Xlib keysyms to Win32 virtual key codes, letters and digits map to uppercase ASCII
+fn translate_key(keysym: xlib::KeySym) -> u32 {
+    use self::x11::keysym::*;
+
+    let keysym = keysym as c_uint;
+    match keysym {
+        XK_a...XK_z => keysym - XK_a + 0x41,
+        XK_A...XK_Z | XK_0...XK_9 => keysym,
+        XK_space => 0x20,
+        XK_Return => 0x0D,
+        XK_Escape => 0x1B,
+        XK_BackSpace => 0x08,
+        XK_Tab => 0x09,
+        XK_Left => 0x25,
+        XK_Up => 0x26,
+        XK_Right => 0x27,
+        XK_Down => 0x28,
+        XK_Shift_L | XK_Shift_R => 0x10,
+        XK_Control_L | XK_Control_R => 0x11,
+        XK_Alt_L | XK_Alt_R => 0x12,
+        XK_F1...XK_F12 => keysym - XK_F1 + 0x70,
+        // Outside the range the keyboard state array covers.
+        _ => 0x1FF,
+    }
+}
+
+fn translate_button(button: c_uint) -> Option<MouseButton> {
+    match button {
+        xlib::Button1 => Some(MouseButton::Left),
+        xlib::Button2 => Some(MouseButton::Middle),
+        xlib::Button3 => Some(MouseButton::Right),
+        _ => None,
+    }
+}
+
+impl LinuxPlatform {
+    pub fn new() -> LinuxPlatform {
+        LinuxPlatform {
+            display: ptr::null_mut(),
+            window: 0,
+            gc: ptr::null_mut(),
+            delete_window: 0,
+            width: 0,
+            height: 0,
+            exposed: true,
+            start: Instant::now(),
+            last_counter: 0.0,
+        }
+    }
+}
+
+impl LinuxPlatform {
+    fn put_image(&self, image: &Image, regions: &[Rect2i]) {
+        unsafe {
+            let screen = xlib::XDefaultScreen(self.display);
+            let visual = xlib::XDefaultVisual(self.display, screen);
+            let depth = xlib::XDefaultDepth(self.display, screen);
+
+            // ARGB words in memory are the BGRX layout of a 24/32 bit TrueColor visual.
+            let x_image = xlib::XCreateImage(
+                self.display,
+                visual,
+                depth as c_uint,
+                xlib::ZPixmap,
+                0,
+                image.color_data.as_ptr() as *mut c_char,
+                image.width as c_uint,
+                image.height as c_uint,
+                32,
+                image.width * 4 as c_int,
+            );
+            if x_image == ptr::null_mut() {
+                return;
+            }
+
+            let bounds = Rect2i::new(0, 0, image.width, image.height);
+            for region in regions.iter() {
+                let region = region.intersect(&bounds);
+                if region.is_empty() {
+                    continue;
+                }
+                xlib::XPutImage(
+                    self.display,
+                    self.window,
+                    self.gc,
+                    x_image,
+                    region.min_x,
+                    region.min_y,
+                    region.min_x,
+                    region.min_y,
+                    region.width() as c_uint,
+                    region.height() as c_uint,
+                );
+            }
+
+            // The pixels belong to `image`, detach them before Xlib frees the header.
+            (*x_image).data = ptr::null_mut();
+            xlib::XDestroyImage(x_image);
+            xlib::XFlush(self.display);
+        }
+    }
+}
+
+impl Drop for LinuxPlatform {
+    fn drop(&mut self) {
+        if self.display != ptr::null_mut() {
+            unsafe {
+                if self.gc != ptr::null_mut() {
+                    xlib::XFreeGC(self.display, self.gc);
+                }
+                xlib::XDestroyWindow(self.display, self.window);
+                xlib::XCloseDisplay(self.display);
+            }
+        }
+    }
+}
+
+impl Platform for LinuxPlatform {
+    fn create_window(&mut self, title: &str, width: i32, height: i32) -> bool {
+        unsafe {
+            self.display = xlib::XOpenDisplay(ptr::null());
+            if self.display == ptr::null_mut() {
+                return false;
+            }
+
+            let screen = xlib::XDefaultScreen(self.display);
+            let root = xlib::XRootWindow(self.display, screen);
+            self.window = xlib::XCreateSimpleWindow(
+                self.display,
+                root,
+                0,
+                0,
+                width as c_uint,
+                height as c_uint,
+                0,
+                xlib::XBlackPixel(self.display, screen),
+                xlib::XBlackPixel(self.display, screen),
+            );
+
+            let title = CString::new(title).unwrap_or_default();
+            xlib::XStoreName(self.display, self.window, title.as_ptr() as *mut c_char);
+
+            xlib::XSelectInput(
+                self.display,
+                self.window,
+                xlib::KeyPressMask
+                    | xlib::KeyReleaseMask
+                    | xlib::ButtonPressMask
+                    | xlib::ButtonReleaseMask
+                    | xlib::PointerMotionMask
+                    | xlib::StructureNotifyMask
+                    | xlib::ExposureMask,
+            );
+
+            // Ask for a message instead of being killed when the window is closed.
+            let name = CString::new("WM_DELETE_WINDOW").unwrap();
+            self.delete_window = xlib::XInternAtom(self.display, name.as_ptr(), xlib::False);
+            let mut protocols = [self.delete_window];
+            xlib::XSetWMProtocols(self.display, self.window, protocols.as_mut_ptr(), 1);
+
+            self.gc = xlib::XCreateGC(self.display, self.window, 0, ptr::null_mut());
+            xlib::XMapWindow(self.display, self.window);
+            xlib::XFlush(self.display);
+        }
+
+        self.width = width;
+        self.height = height;
+        self.last_counter = self.wall_clock();
+        true
+    }
+
+    fn dimensions(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn poll_message(&mut self) -> Message {
+        unsafe {
+            while xlib::XPending(self.display) > 0 {
+                let mut event: xlib::XEvent = mem::zeroed();
+                xlib::XNextEvent(self.display, &mut event);
+
+                match event.get_type() {
+                    xlib::KeyPress | xlib::KeyRelease => {
+                        let mut key_event = event.key;
+                        let keysym = xlib::XLookupKeysym(&mut key_event, 0);
+                        let key = translate_key(keysym);
+                        return if event.get_type() == xlib::KeyPress {
+                            Message::KeyDown(key)
+                        } else {
+                            Message::KeyUp(key)
+                        };
+                    }
+                    xlib::ButtonPress | xlib::ButtonRelease => {
+                        let button_event = event.button;
+                        if let Some(button) = translate_button(button_event.button) {
+                            let (x, y) = (button_event.x as i32, button_event.y as i32);
+                            return if event.get_type() == xlib::ButtonPress {
+                                Message::MouseDown(button, x, y)
+                            } else {
+                                Message::MouseUp(button, x, y)
+                            };
+                        }
+                    }
+                    xlib::MotionNotify => {
+                        let motion = event.motion;
+                        return Message::MouseMove(motion.x as i32, motion.y as i32);
+                    }
+                    xlib::ConfigureNotify => {
+                        let configure = event.configure;
+                        if configure.width != self.width || configure.height != self.height {
+                            self.width = configure.width;
+                            self.height = configure.height;
+                            return Message::Resize(self.width, self.height);
+                        }
+                    }
+                    xlib::Expose => self.exposed = true,
+                    xlib::ClientMessage => {
+                        let client = event.client_message;
+                        if client.data.get_long(0) as xlib::Atom == self.delete_window {
+                            return Message::Quit;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Message::Silence
+    }
+
+    fn present(&mut self, image: &Image) {
+        let everything = Rect2i::new(0, 0, image.width, image.height);
+        self.put_image(image, &[everything]);
+        self.exposed = false;
+    }
+
+    fn present_regions(&mut self, image: &Image, regions: &[Rect2i]) {
+        if self.exposed {
+            self.present(image);
+        } else if !regions.is_empty() {
+            self.put_image(image, regions);
+        }
+    }
+
+    fn wall_clock(&self) -> f64 {
+        let elapsed = self.start.elapsed();
+        elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1.0e-9
+    }
+
+    fn wait_for_frame(&mut self, target_seconds_per_frame: f64) -> f64 {
+        let mut last_counter = self.last_counter;
+        let delta_time = wait_until(self, &mut last_counter, target_seconds_per_frame, true);
+        self.last_counter = last_counter;
+        delta_time
+    }
+}
@@ -0,0 +1,547 @@
+#![allow(dead_code)]
+
+use audio::*;
+use filter::*;
+use math::*;
+use tools::*;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+
+/// ITU-R BS.1770 loudness of a set of channels, all values in LUFS / LU / dB
+pub struct LoudnessReport {
+    pub integrated: f64,
+    pub loudness_range: f64,
+    pub momentary_max: f64,
+    pub short_term_max: f64,
+    pub true_peak: f64,
+    pub sample_peak: f64,
+    /// Momentary loudness every 100 ms
+    pub momentary: Vec<f64>,
+    /// Short-term loudness every 100 ms
+    pub short_term: Vec<f64>,
+}
+
+/// Streaming meter, feed it one frame (one sample per channel) at a time
+pub struct LoudnessMeter {
+    pub sample_rate: f64,
+    pub channel_weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    oversamplers: Vec<Oversampler>,
+    oversampled: Vec<f64>,
+    block_size: usize,
+    block_position: usize,
+    block_energy: f64,
+    /// Weighted mean square of every completed 100 ms block
+    blocks: Vec<f64>,
+    true_peak: f64,
+    sample_peak: f64,
+}
+
+/// Pre-filter (high shelf) and RLB high-pass of BS.1770 for any sample rate
+pub fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = f64::tan(PI * f0 / sample_rate);
+    let vh = f64::powf(10.0, gain / 20.0);
+    let vb = f64::powf(vh, 0.4996667741545416);
+    let shelf = Biquad::new(
+        vh + vb * k / q + k * k,
+        2.0 * (k * k - vh),
+        vh - vb * k / q + k * k,
+        1.0 + k / q + k * k,
+        2.0 * (k * k - 1.0),
+        1.0 - k / q + k * k,
+    );
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = f64::tan(PI * f0 / sample_rate);
+    let high_pass = Biquad::new(
+        1.0,
+        -2.0,
+        1.0,
+        1.0,
+        2.0 * (k * k - 1.0) / (1.0 + k / q + k * k),
+        (1.0 - k / q + k * k) / (1.0 + k / q + k * k),
+    );
+
+    [shelf, high_pass]
+}
+
+#[inline(always)]
+pub fn energy_to_loudness(energy: f64) -> f64 {
+    if energy <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    -0.691 + 10.0 * energy.log10()
+}
+
+#[inline(always)]
+pub fn loudness_to_energy(loudness: f64) -> f64 {
+    f64::powf(10.0, (loudness + 0.691) / 10.0)
+}
+
+fn amplitude_to_db(amplitude: f64) -> f64 {
+    if amplitude <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        volume_to_db(amplitude)
+    }
+}
+
+/// Mean energy of every `window` consecutive blocks, one value per block
+fn windowed_energies(blocks: &[f64], window: usize) -> Vec<f64> {
+    let mut result = Vec::new();
+    if blocks.len() < window {
+        return result;
+    }
+    let mut sum = 0.0;
+    for i in 0..blocks.len() {
+        sum += blocks[i];
+        if i >= window {
+            sum -= blocks[i - window];
+        }
+        if i + 1 >= window {
+            result.push(max_f64(sum, 0.0) / window as f64);
+        }
+    }
+    result
+}
+
+/// Two stage gated loudness of a list of block energies
+fn gated_loudness(energies: &[f64], relative_gate: f64) -> (f64, Vec<f64>) {
+    let absolute = loudness_to_energy(ABSOLUTE_GATE);
+    let above_absolute: Vec<f64> = energies.iter().cloned().filter(|e| *e > absolute).collect();
+    if above_absolute.len() == 0 {
+        return (f64::NEG_INFINITY, above_absolute);
+    }
+
+    let mean = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+    let relative = loudness_to_energy(energy_to_loudness(mean) + relative_gate);
+    let gated: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|e| *e > relative)
+        .collect();
+    if gated.len() == 0 {
+        return (f64::NEG_INFINITY, gated);
+    }
+
+    let mean = gated.iter().sum::<f64>() / gated.len() as f64;
+    (energy_to_loudness(mean), gated)
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: f64, channel_count: usize) -> LoudnessMeter {
+        let mut filters = Vec::with_capacity(channel_count);
+        let mut oversamplers = Vec::with_capacity(channel_count);
+        for _ in 0..channel_count {
+            filters.push(k_weighting(sample_rate));
+            oversamplers.push(Oversampler::new(Oversampling::X4));
+        }
+
+        LoudnessMeter {
+            sample_rate: sample_rate,
+            channel_weights: vec![1.0; channel_count],
+            filters: filters,
+            oversamplers: oversamplers,
+            oversampled: Vec::with_capacity(4),
+            block_size: round_f64_u32(sample_rate * 0.1) as usize,
+            block_position: 0,
+            block_energy: 0.0,
+            blocks: Vec::new(),
+            true_peak: 0.0,
+            sample_peak: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let channel_count = self.filters.len();
+        let weights = self.channel_weights.clone();
+        *self = LoudnessMeter::new(self.sample_rate, channel_count);
+        self.channel_weights = weights;
+    }
+
+    pub fn push(&mut self, frame: &[f64]) {
+        let mut energy = 0.0;
+        for channel in 0..self.filters.len() {
+            let input = if channel < frame.len() {
+                frame[channel]
+            } else {
+                0.0
+            };
+
+            let filters = &mut self.filters[channel];
+            let shelved = filters[0].process(input);
+            let weighted = filters[1].process(shelved);
+            energy += self.channel_weights[channel] * weighted * weighted;
+
+            self.sample_peak = max_f64(self.sample_peak, abs_f64(input));
+            self.oversamplers[channel].upsample(input, &mut self.oversampled);
+            for value in self.oversampled.iter() {
+                self.true_peak = max_f64(self.true_peak, abs_f64(*value));
+            }
+        }
+        // True peak can never be below the sample peak, the filter just smears it.
+        self.true_peak = max_f64(self.true_peak, self.sample_peak);
+
+        self.block_energy += energy;
+        self.block_position += 1;
+        if self.block_position == self.block_size {
+            self.blocks.push(self.block_energy / self.block_size as f64);
+            self.block_energy = 0.0;
+            self.block_position = 0;
+        }
+    }
+
+    /// Loudness of the last 400 ms
+    pub fn momentary(&self) -> f64 {
+        self.last_window(4)
+    }
+
+    /// Loudness of the last 3 s
+    pub fn short_term(&self) -> f64 {
+        self.last_window(30)
+    }
+
+    fn last_window(&self, window: usize) -> f64 {
+        let count = self.blocks.len();
+        if count < window {
+            return f64::NEG_INFINITY;
+        }
+        let sum: f64 = self.blocks[count - window..].iter().sum();
+        energy_to_loudness(sum / window as f64)
+    }
+
+    /// Gated loudness over 400 ms blocks with 75% overlap since the last reset
+    pub fn integrated(&self) -> f64 {
+        let energies = windowed_energies(&self.blocks, 4);
+        gated_loudness(&energies, RELATIVE_GATE).0
+    }
+
+    /// EBU Tech 3342 loudness range from the gated short-term distribution
+    pub fn loudness_range(&self) -> f64 {
+        let energies = windowed_energies(&self.blocks, 30);
+        let (_, mut gated) = gated_loudness(&energies, RANGE_RELATIVE_GATE);
+        if gated.len() < 2 {
+            return 0.0;
+        }
+        gated.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let low = gated[round_f64_u32(0.10 * (gated.len() - 1) as f64) as usize];
+        let high = gated[round_f64_u32(0.95 * (gated.len() - 1) as f64) as usize];
+        energy_to_loudness(high) - energy_to_loudness(low)
+    }
+
+    pub fn true_peak(&self) -> f64 {
+        amplitude_to_db(self.true_peak)
+    }
+
+    pub fn sample_peak(&self) -> f64 {
+        amplitude_to_db(self.sample_peak)
+    }
+
+    pub fn report(&self) -> LoudnessReport {
+        let momentary: Vec<f64> = windowed_energies(&self.blocks, 4)
+            .into_iter()
+            .map(energy_to_loudness)
+            .collect();
+        let short_term: Vec<f64> = windowed_energies(&self.blocks, 30)
+            .into_iter()
+            .map(energy_to_loudness)
+            .collect();
+
+        let momentary_max = momentary.iter().cloned().fold(f64::NEG_INFINITY, max_f64);
+        let short_term_max = short_term.iter().cloned().fold(f64::NEG_INFINITY, max_f64);
+
+        LoudnessReport {
+            integrated: self.integrated(),
+            loudness_range: self.loudness_range(),
+            momentary_max: momentary_max,
+            short_term_max: short_term_max,
+            true_peak: self.true_peak(),
+            sample_peak: self.sample_peak(),
+            momentary: momentary,
+            short_term: short_term,
+        }
+    }
+}
+
+/// Full analysis of channels sharing the same sample rate and length
+pub fn analyze_loudness(channels: &[&Waveform]) -> LoudnessReport {
+    let sample_rate = if channels.len() > 0 {
+        channels[0].sample_rate
+    } else {
+        48000.0
+    };
+    let mut meter = LoudnessMeter::new(sample_rate, channels.len());
+    let length = channels
+        .iter()
+        .map(|wave| wave.sample_count)
+        .max()
+        .unwrap_or(0);
+
+    let mut frame = vec![0.0; channels.len()];
+    for i in 0..length {
+        for (channel, wave) in channels.iter().enumerate() {
+            frame[channel] = if i < wave.sample_count {
+                wave.samples[i]
+            } else {
+                0.0
+            };
+        }
+        meter.push(&frame);
+    }
+    meter.report()
+}
+
+pub fn loudness(wave: &Waveform) -> LoudnessReport {
+    analyze_loudness(&[wave])
+}
+
+/// Gain in dB that brings the channels to `target` LUFS without the true peak
+/// going above `true_peak_ceiling` dBTP.
+pub fn normalization_gain(report: &LoudnessReport, target: f64, true_peak_ceiling: f64) -> f64 {
+    if !report.integrated.is_finite() {
+        return 0.0;
+    }
+    let gain = target - report.integrated;
+    if report.true_peak.is_finite() && report.true_peak + gain > true_peak_ceiling {
+        true_peak_ceiling - report.true_peak
+    } else {
+        gain
+    }
+}
+
+pub fn normalize_loudness(
+    channels: &[&Waveform],
+    target: f64,
+    true_peak_ceiling: f64,
+) -> Vec<Waveform> {
+    let report = analyze_loudness(channels);
+    let gain = db_to_volume(normalization_gain(&report, target, true_peak_ceiling));
+
+    channels
+        .iter()
+        .map(|wave| {
+            let samples = wave.samples.iter().map(|value| value * gain).collect();
+            Waveform::from_samples(samples, wave.sample_rate)
+        })
+        .collect()
+}
+
+/// Reference signal from EBU Tech 3341 / 3342 with the expected measurement
+pub struct LoudnessTestVector {
+    pub name: &'static str,
+    pub channels: Vec<Waveform>,
+    pub expected: f64,
+    pub tolerance_low: f64,
+    pub tolerance_high: f64,
+    pub measure: fn(&LoudnessReport) -> f64,
+}
+
+fn integrated_of(report: &LoudnessReport) -> f64 {
+    report.integrated
+}
+
+fn momentary_of(report: &LoudnessReport) -> f64 {
+    report.momentary_max
+}
+
+fn short_term_of(report: &LoudnessReport) -> f64 {
+    report.short_term_max
+}
+
+fn range_of(report: &LoudnessReport) -> f64 {
+    report.loudness_range
+}
+
+fn true_peak_of(report: &LoudnessReport) -> f64 {
+    report.true_peak
+}
+
+/// 1 kHz sine made of consecutive (level dBFS, seconds) segments
+fn tone_segments(sample_rate: f64, segments: &[(f64, f64)]) -> Waveform {
+    let mut samples = Vec::new();
+    for &(level, seconds) in segments.iter() {
+        let amplitude = db_to_volume(level);
+        let count = round_f64_u32(seconds * sample_rate) as usize;
+        for _ in 0..count {
+            let i = samples.len();
+            samples.push(amplitude * f64::sin(2.0 * PI * 1000.0 * i as f64 / sample_rate));
+        }
+    }
+    Waveform::from_samples(samples, sample_rate)
+}
+
+fn stereo_vector(
+    name: &'static str,
+    sample_rate: f64,
+    segments: &[(f64, f64)],
+    expected: f64,
+    tolerance: f64,
+    measure: fn(&LoudnessReport) -> f64,
+) -> LoudnessTestVector {
+    let left = tone_segments(sample_rate, segments);
+    let right = Waveform::from_samples(left.samples.to_vec(), sample_rate);
+    LoudnessTestVector {
+        name: name,
+        channels: vec![left, right],
+        expected: expected,
+        tolerance_low: tolerance,
+        tolerance_high: tolerance,
+        measure: measure,
+    }
+}
+
+/// Generates the tone based EBU test cases locally
+pub fn ebu_test_vectors(sample_rate: f64) -> Vec<LoudnessTestVector> {
+    let mut result = vec![
+        stereo_vector(
+            "3341-1 -23 dBFS",
+            sample_rate,
+            &[(-23.0, 20.0)],
+            -23.0,
+            0.1,
+            integrated_of,
+        ),
+        stereo_vector(
+            "3341-2 -33 dBFS",
+            sample_rate,
+            &[(-33.0, 20.0)],
+            -33.0,
+            0.1,
+            integrated_of,
+        ),
+        stereo_vector(
+            "3341-3 relative gate",
+            sample_rate,
+            &[(-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0)],
+            -23.0,
+            0.1,
+            integrated_of,
+        ),
+        stereo_vector(
+            "3341-4 absolute gate",
+            sample_rate,
+            &[
+                (-72.0, 10.0),
+                (-36.0, 10.0),
+                (-23.0, 60.0),
+                (-36.0, 10.0),
+                (-72.0, 10.0),
+            ],
+            -23.0,
+            0.1,
+            integrated_of,
+        ),
+        stereo_vector(
+            "3341-5 level changes",
+            sample_rate,
+            &[(-26.0, 20.0), (-20.0, 20.1), (-26.0, 20.0)],
+            -23.0,
+            0.1,
+            integrated_of,
+        ),
+        stereo_vector(
+            "3341 momentary",
+            sample_rate,
+            &[(-23.0, 5.0)],
+            -23.0,
+            0.1,
+            momentary_of,
+        ),
+        stereo_vector(
+            "3341 short-term",
+            sample_rate,
+            &[(-23.0, 5.0)],
+            -23.0,
+            0.1,
+            short_term_of,
+        ),
+        stereo_vector(
+            "3342-1 LRA 10",
+            sample_rate,
+            &[(-20.0, 20.0), (-30.0, 20.0)],
+            10.0,
+            1.0,
+            range_of,
+        ),
+        stereo_vector(
+            "3342-2 LRA 5",
+            sample_rate,
+            &[(-20.0, 20.0), (-15.0, 20.0)],
+            5.0,
+            1.0,
+            range_of,
+        ),
+        stereo_vector(
+            "3342-3 LRA 20",
+            sample_rate,
+            &[(-40.0, 20.0), (-20.0, 20.0)],
+            20.0,
+            1.0,
+            range_of,
+        ),
+        stereo_vector(
+            "3342-4 LRA 15",
+            sample_rate,
+            &[
+                (-50.0, 20.0),
+                (-35.0, 20.0),
+                (-20.0, 20.0),
+                (-35.0, 20.0),
+                (-50.0, 20.0),
+            ],
+            15.0,
+            1.0,
+            range_of,
+        ),
+    ];
+
+    // Quarter sample rate sine sampled 45 degrees off its peaks, the samples sit 3 dB
+    // below the real peak of -6 dBFS.
+    let count = round_f64_u32(sample_rate) as usize;
+    let amplitude = db_to_volume(-6.0);
+    let samples: Vec<f64> = (0..count)
+        .map(|i| amplitude * f64::sin(PI / 2.0 * i as f64 + PI / 4.0))
+        .collect();
+    result.push(LoudnessTestVector {
+        name: "3341 true peak fs/4 45 degrees",
+        channels: vec![Waveform::from_samples(samples, sample_rate)],
+        expected: -6.0,
+        tolerance_low: 0.4,
+        tolerance_high: 0.2,
+        measure: true_peak_of,
+    });
+
+    result
+}
+
+/// Runs every test vector, returns (name, measured, passed)
+pub fn validate_test_vectors(sample_rate: f64) -> Vec<(&'static str, f64, bool)> {
+    let mut result = Vec::new();
+    for vector in ebu_test_vectors(sample_rate).iter() {
+        let channels: Vec<&Waveform> = vector.channels.iter().collect();
+        let report = analyze_loudness(&channels);
+        let measured = (vector.measure)(&report);
+        let passed = measured >= vector.expected - vector.tolerance_low
+            && measured <= vector.expected + vector.tolerance_high;
+        result.push((vector.name, measured, passed));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ebu_test_vectors_pass() {
+        for &(name, measured, passed) in validate_test_vectors(48000.0).iter() {
+            assert!(passed, "{0} measured {1:.2}", name, measured);
+        }
+    }
+}
@@ -3,28 +3,104 @@
 
 extern crate stdsimd;
 
+#[cfg(windows)]
 extern crate gdi32;
+#[cfg(windows)]
 extern crate kernel32;
+#[cfg(windows)]
 extern crate user32;
+#[cfg(windows)]
 extern crate winapi;
+#[cfg(windows)]
 extern crate winmm;
 
-use std::mem;
+use std::env;
 
 mod math;
 mod audio;
 mod random;
 mod tools;
 mod render;
+mod scene;
+mod raster;
+mod waveview;
+mod canvas;
+mod font;
+mod truetype;
+mod zlib;
+mod png;
+mod codec;
+mod platform;
+mod headless;
+#[cfg(windows)]
 mod windows;
+#[cfg(target_os = "linux")]
+mod linux;
+mod fft;
+mod filter;
+mod distortion;
+mod eq;
+mod denoise;
+mod cleanup;
+mod restore;
+mod loudness;
+mod statistics;
+mod pitch;
+mod rhythm;
+mod silence;
+mod features;
+mod alignment;
+mod analyzer;
+mod stereo;
+mod meter;
 
 use audio::*;
+use canvas::*;
+use cleanup::*;
+use denoise::*;
+use eq::*;
+use features::*;
+use meter::*;
+use pitch::*;
 //use coresimd::vendor::*;
-use windows::*;
+use headless::*;
+use platform::*;
 use render::*;
+use restore::*;
+use rhythm::*;
+use scene::*;
+use silence::*;
+use statistics::*;
+use waveview::*;
 use math::*;
 //use random::*;
 
+const BUFFER_LENGTH: u32 = 500;
+/// Fewest samples the view zooms in to
+const MIN_VIEW_LENGTH: u32 = 16;
+const METER_WIDTH: i32 = 14;
+const NOISE_FRAME_SIZE: usize = 2048;
+/// Above this many separate changed rectangles a frame updates their bounds instead
+const MAX_DAMAGE_REGIONS: usize = 16;
+const KEY_A: u32 = 0x41;
+const KEY_B: u32 = 0x42;
+const KEY_C: u32 = 0x43;
+const KEY_D: u32 = 0x44;
+const KEY_E: u32 = 0x45;
+const KEY_F: u32 = 0x46;
+const KEY_G: u32 = 0x47;
+const KEY_H: u32 = 0x48;
+const KEY_L: u32 = 0x4C;
+const KEY_N: u32 = 0x4E;
+const KEY_P: u32 = 0x50;
+const KEY_Q: u32 = 0x51;
+const KEY_S: u32 = 0x53;
+const KEY_T: u32 = 0x54;
+const KEY_V: u32 = 0x56;
+const KEY_W: u32 = 0x57;
+const KEY_UP: u32 = 0x26;
+const KEY_DOWN: u32 = 0x28;
+
 struct ScreenPoint {
     x: i32,
     y: i32,
@@ -36,7 +112,7 @@ struct MouseControls {
 }
 
 struct KeyboardControls {
-    key: [bool; 512],
+    key: [bool; KEY_COUNT],
 }
 
 pub struct Application {
@@ -46,33 +122,62 @@ pub struct Application {
     mouse_start: ScreenPoint,
     keyboard: KeyboardControls,
     window_buffer: WindowBuffer,
-    background: Sprite,
-    sprites: Vec<Box<Sprite>>,
     wave: Waveform,
+    equalizer: Equalizer,
+    noise_profile: Option<NoiseProfile>,
+    events: Vec<SampleRange>,
+    pitch_track: Vec<PitchFrame>,
+    rhythm: Option<Rhythm>,
+    meters: Vec<Meter>,
+    meter_position: u32,
     position: u32,
+    wave_style: WaveStyle,
+    view_length: u32,
+    /// Something the scene shows changed, repaint it next frame
+    content_changed: bool,
+    /// Playback position the scene was last painted at
+    painted_position: u32,
+    /// Where the cursor, selection and markers were drawn over the scene last frame
+    overlay_regions: Vec<Rect2i>,
 }
 
-fn main() {
-    use winapi::{GWLP_USERDATA, MSG, TIMERR_NOERROR};
-    use user32::SetWindowLongPtrW;
-    use kernel32::QueryPerformanceFrequency;
-    use winmm::timeBeginPeriod;
+#[cfg(windows)]
+fn create_window_platform() -> Box<Platform> {
+    Box::new(windows::Win32Platform::new())
+}
 
-    let mut global_perf_count_frequency: i64 = 60;
+#[cfg(target_os = "linux")]
+fn create_window_platform() -> Box<Platform> {
+    Box::new(linux::LinuxPlatform::new())
+}
 
-    let query_performance_frequency =
-        unsafe { QueryPerformanceFrequency(&mut global_perf_count_frequency) };
-    if query_performance_frequency == 0 {
-        global_perf_count_frequency = 60;
+/// `--headless` renders without a window, see `HeadlessPlatform::from_args` for its options
+fn create_platform() -> Option<Box<Platform>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        match HeadlessPlatform::from_args(&args) {
+            Ok(platform) => Some(Box::new(platform)),
+            Err(error) => {
+                println!("{0}", error);
+                None
+            }
+        }
+    } else {
+        Some(create_window_platform())
     }
+}
 
-    let desired_scheduler_ms: u32 = 1;
-    let sleep_is_granular: bool =
-        unsafe { (timeBeginPeriod(desired_scheduler_ms) == TIMERR_NOERROR) };
+fn main() {
+    let mut platform = match create_platform() {
+        Some(platform) => platform,
+        None => return,
+    };
+    if platform.create_window("Shade", 1280, 720) {
+        let target_seconds_per_frame = 1.0 / platform.refresh_rate();
+        let (win_width, win_height) = platform.dimensions();
 
-    let window = unsafe { create_window(String::from("ShadeClass"), String::from("Shade")) };
-    if window != std::ptr::null_mut() {
-        let target_seconds_per_frame = 1.0 / get_monitor_refresh_rate(&window);
+        let waveform = Waveform::noise(20000, 44100.0);
+        //let waveform = Waveform::osc(400.0, 4000, 44100.0);
 
         let mut app: Box<Application> = Box::new(Application {
             is_running: true,
@@ -82,93 +187,81 @@ fn main() {
                 point: ScreenPoint { x: 0, y: 0 },
             },
             mouse_start: ScreenPoint { x: 0, y: 0 },
-            keyboard: KeyboardControls { key: [false; 512] },
-            window_buffer: unsafe { mem::zeroed() },
-            background: unsafe { mem::zeroed() },
-            sprites: Vec::new(),
-            wave: unsafe { mem::zeroed() },
+            keyboard: KeyboardControls {
+                key: [false; KEY_COUNT],
+            },
+            window_buffer: WindowBuffer {
+                image: Image::from_color(win_width, win_height, Color::from_u32(0xFFFF0000)),
+                resized: true,
+            },
+            wave: waveform,
+            equalizer: Equalizer::new(),
+            noise_profile: None,
+            events: Vec::new(),
+            pitch_track: Vec::new(),
+            rhythm: None,
+            meters: Vec::new(),
+            meter_position: 0,
             position: 0,
-        });
-
-        app.window_buffer = WindowBuffer {
-            image: Image::from_color(1900, 1200, Color::from_u32(0xFFFF0000)),
-            resized: true,
-            info: unsafe { mem::zeroed() },
-        };
-
-        unsafe { SetWindowLongPtrW(window, GWLP_USERDATA, mem::transmute(&app.window_buffer)) };
-
-        let (win_width, win_height) = get_window_dimension(window);
-        resize_dib_section(&mut app.window_buffer, win_width, win_height);
-
-        let mut msg: MSG = unsafe { mem::uninitialized() };
-        let mut last_counter = unsafe { get_wall_clock() };
-
-        app.background = Sprite {
-            image: (Image::from_color(
-                win_width,
-                win_height,
-                Color::from_u32(Colors::White as u32),
-            )),
-            position: Vector2::ORIGIN,
-            layer: LayerID::Base,
-            need_update: false,
-            children: Vec::new(),
-        };
-
-        let bg = Box::new(Sprite {
-            image: (Image::from_color(
-                win_width,
-                win_height - 100,
-                Color::from_u32(Colors::DarkGrey as u32),
-            )),
-            position: Vector2::new(0.0, 50.0),
-            layer: LayerID::Background,
-            need_update: true,
-            children: Vec::new(),
-        });
-
-        let waveform = Waveform::noise(20000, 44100.0);
-        //let waveform = Waveform::osc(400.0, 4000, 44100.0);
-
-        let wave_sprite = Box::new(Sprite {
-            image: (Image::from_color(
-                win_width,
-                win_height - 100,
+            wave_style: WaveStyle::new(
                 Color::from_u32(Colors::Amber as u32),
-            )),
-            position: Vector2::new(0.0, 50.0),
-            layer: LayerID::Wave,
-            need_update: true,
-            children: Vec::new(),
+                Color::from_u32(0xFFFFE080),
+            ),
+            view_length: BUFFER_LENGTH,
+            content_changed: true,
+            painted_position: 0,
+            overlay_regions: Vec::new(),
         });
 
-        app.sprites.push(bg);
-        app.sprites.push(wave_sprite);
+        let base = Image::from_color(win_width, win_height, Color::from_u32(Colors::Black as u32));
+        let mut scene = Scene::new(Sprite::from_image(base, Vector2::ORIGIN, LayerID::Base));
+        let layers: [(&str, LayerID, Painter<Application>); 4] = [
+            ("background", LayerID::Background, paint_background),
+            ("wave", LayerID::Wave, paint_wave),
+            ("response", LayerID::Response, paint_response),
+            ("meters", LayerID::GUI, paint_meters),
+        ];
+        for &(name, layer, painter) in layers.iter() {
+            let mut sprite = Sprite::new(Vector2::new(0.0, 50.0), 1, 1, layer);
+            sprite.name = name.to_string();
+            scene.add("", sprite, Some(painter));
+        }
 
-        app.wave = waveform;
+        app.meters = [
+            MeterType::Peak,
+            MeterType::Rms,
+            MeterType::Vu,
+            MeterType::PpmType1,
+            MeterType::PpmType2,
+            MeterType::K(14),
+        ]
+        .iter()
+        .map(|meter_type| Meter::new(*meter_type, app.wave.sample_rate))
+        .collect();
 
         while app.is_running {
             //let app_cycle_count = unsafe { _rdtsc() };
 
-            let input = process_pending_messages(&mut msg);
-            //let os_input_cycles = unsafe { _rdtsc() } - app_cycle_count;
-
-            //let mut app_input_cycles = unsafe { _rdtsc() };
-            app.process_input(input);
-            //app_input_cycles = unsafe { _rdtsc() } - app_input_cycles;
+            // Everything that arrived since the last frame is handled before drawing it.
+            loop {
+                match platform.poll_message() {
+                    Message::Silence => break,
+                    input => app.process_input(input),
+                }
+                if !app.is_running {
+                    break;
+                }
+            }
+            if !app.is_running {
+                break;
+            }
 
             // let render_cycle_count = unsafe { _rdtsc() };
-            app.update_and_render();
+            let damage = app.update_and_render(&mut scene);
 
-            app.delta_time = sleep(
-                &mut last_counter,
-                target_seconds_per_frame,
-                global_perf_count_frequency,
-                sleep_is_granular,
-            );
+            app.delta_time = platform.wait_for_frame(target_seconds_per_frame);
 
-            display_buffer_in_window(&app.window_buffer, &window);
+            platform.present_regions(&app.window_buffer.image, &damage);
 
             //let end_cycles_elapsed = unsafe { _rdtsc() };
 
@@ -183,6 +276,72 @@ fn main() {
     }
 }
 
+/// Milliseconds below the plot area, a tick every fifth of the view
+fn plot_time_axis(canvas: &mut Canvas, sample_rate: f64, start: u32, range: u32, top: f64) {
+    let width = canvas.size().x;
+    let color = Color::from_u32(0xFFAAAAAA);
+    let step = max_u32(1, range / 5);
+    let mut position = (start + step - 1) / step * step;
+    while position < start + range {
+        let x = (position - start) as f64 / range as f64 * width;
+        canvas.line(&Vector2::new(x, top), &Vector2::new(x, top + 4.0), color);
+        let label = format!("{0:.1} ms", position as f64 / sample_rate * 1000.0);
+        canvas.text(&Vector2::new(x + 3.0, top + 3.0), &label, 8.0, color);
+        position += step;
+    }
+}
+
+fn paint_background(_: &Application, sprite: &mut Sprite, size: &Vector2) {
+    let color = Color::from_u32(Colors::DarkGrey as u32);
+    sprite.image = Image::from_color(size.x as i32, size.y as i32 - 100, color);
+}
+
+fn paint_wave(app: &Application, sprite: &mut Sprite, size: &Vector2) {
+    let mut wave_image = Image::waveform(
+        size.x as i32,
+        size.y as i32 - 100,
+        &app.wave,
+        app.position,
+        app.view_length,
+        &app.wave_style,
+    );
+    wave_image.draw_pitch_contour(
+        &app.pitch_track,
+        app.position,
+        app.view_length,
+        50.0,
+        1000.0,
+        Color::from_u32(Colors::White as u32),
+    );
+    sprite.image = wave_image;
+}
+
+fn paint_response(app: &Application, sprite: &mut Sprite, size: &Vector2) {
+    sprite.visible = app.equalizer.bands.len() > 0;
+    if !sprite.visible {
+        return;
+    }
+    sprite.image = app.equalizer.response_image(
+        size.x as i32,
+        size.y as i32 - 100,
+        app.wave.sample_rate,
+        24.0,
+        Color::from_u32(Colors::White as u32),
+        Color::from_u32(0x8800AAFF),
+    );
+}
+
+fn paint_meters(app: &Application, sprite: &mut Sprite, size: &Vector2) {
+    let panel = Image::meter_panel(&app.meters, METER_WIDTH, 6, size.y as i32 - 120);
+    sprite.position = Vector2::new(size.x - (panel.width + 10) as f64, 60.0);
+    sprite.image = panel;
+}
+
+/// Pixels a line drawn from `start` to `end` can touch
+fn line_region(start: &Vector2, end: &Vector2) -> Rect2i {
+    Rect2i::covering(start, &(*end - *start)).grow(1)
+}
+
 /*
 fn print_debug(cycles: u64, target: f64, delta_time: f64, width: i32, height: i32) {
     println!(
@@ -198,65 +357,102 @@ fn print_debug(cycles: u64, target: f64, delta_time: f64, width: i32, height: i3
 */
 
 impl Application {
-    fn update_and_render(&mut self) {
-        let window_buffer = &mut self.window_buffer;
-        let width = window_buffer.image.width;
-        let height = window_buffer.image.height;
+    /// Draws the frame and returns the parts of the window that changed. Only the damaged
+    /// parts of the scene and the places the overlays were drawn last frame are composited
+    /// again.
+    fn update_and_render(&mut self, scene: &mut Scene<Application>) -> Vec<Rect2i> {
+        self.update_meters();
+
+        let selection_stats = if self.mouse.button[0] {
+            let (start, end) = self.selected_samples();
+            let range = SampleRange::new(start, end);
+            Some(WaveformStatistics::analyze(&self.wave, range))
+        } else {
+            None
+        };
 
-        window_buffer.image.clear();
+        let width = self.window_buffer.image.width;
+        let height = self.window_buffer.image.height;
+        let buffer_length = self.view_length;
+
+        let mut restore = Vec::new();
+        if self.window_buffer.resized {
+            let base = Image::from_color(width, height, Color::from_u32(Colors::Black as u32));
+            scene.root.image = base;
+            restore.push(Rect2i::new(0, 0, width, height));
+            self.window_buffer.resized = false;
+            self.content_changed = true;
+        }
+        if self.content_changed {
+            scene.invalidate();
+            self.content_changed = false;
+            self.painted_position = self.position;
+        } else if self.position != self.painted_position {
+            // Only the waveform and the meters follow the play position.
+            scene.invalidate_node("wave");
+            scene.invalidate_node("meters");
+            self.painted_position = self.position;
+        }
+        restore.extend(scene.update(&*self, &Vector2::new(width as f64, height as f64)));
+        restore.append(&mut self.overlay_regions);
+        let restore = simplify_damage(&restore, MAX_DAMAGE_REGIONS);
 
-        let sprites = &mut self.sprites;
+        let window_buffer = &mut self.window_buffer;
+        scene.render_regions(&mut window_buffer.image, &restore);
 
-        let buffer_length = 500;
+        if self.position < self.wave.sample_count as u32 - buffer_length {
+            self.position += 1;
+        }
 
-        let buffers_count = sprites.len();
-        if window_buffer.resized == true {
-            let bg = Image::from_color(width, height, Color::from_u32(Colors::Black as u32));
-            self.background.image = bg;
-            window_buffer.resized = false;
-            for i in 0..buffers_count {
-                sprites[i].need_update = true;
+        let mut overlays = Vec::new();
+        let axis_top = height as f64 - 48.0;
+        plot_time_axis(
+            &mut window_buffer.image,
+            self.wave.sample_rate,
+            self.position,
+            buffer_length,
+            axis_top,
+        );
+        overlays.push(Rect2i::new(0, axis_top as i32 - 1, width, height));
+
+        let event_color = Color::from_u32(0x66FF2020);
+        let view_start = self.position;
+        let view_end = view_start + buffer_length;
+        let scale = width as f64 / buffer_length as f64;
+        for event in self.events.iter() {
+            let event_start = max_u32(event.start as u32, view_start);
+            let event_end = min_u32(event.end as u32, view_end);
+            if event_end <= event_start {
+                continue;
             }
+            let x = (event_start - view_start) as f64 * scale;
+            let event_width = max_f64(1.0, (event_end - event_start) as f64 * scale);
+            let position = Vector2::new(x, 50.0);
+            let size = Vector2::new(event_width, height as f64 - 100.0);
+            window_buffer.image.draw_rect(&position, &size, event_color);
+            overlays.push(Rect2i::covering(&position, &size).grow(1));
         }
 
-        for i in 0..buffers_count {
-            if sprites[i].need_update {
-                if sprites[i].position.x < width as f64 && sprites[i].position.y < height as f64 {
-                    match sprites[i].layer {
-                        LayerID::Background => {
-                            let mut bg = Image::from_color(
-                                width,
-                                height - 100,
-                                Color::from_u32(Colors::DarkGrey as u32),
-                            );
-                            sprites[i].image = bg;
-                            sprites[i].need_update = true;
-                        }
-                        LayerID::Wave => {
-                            let wave_image = Image::waveform(
-                                width,
-                                height - 100,
-                                &self.wave,
-                                self.position,
-                                buffer_length,
-                                Color::from_u32(Colors::Amber as u32),
-                            );
-                            sprites[i].image = wave_image;
-                            sprites[i].need_update = true;
-                        }
-                        _ => {}
+        if let Some(ref rhythm) = self.rhythm {
+            let markers = [
+                (&rhythm.onsets, Color::from_u32(0x88FFAA00)),
+                (&rhythm.beats, Color::from_u32(0xFF00CCFF)),
+            ];
+            for &(positions, marker_color) in markers.iter() {
+                for position in positions.iter() {
+                    let position = *position as u32;
+                    if position < view_start || position >= view_end {
+                        continue;
                     }
-                    self.background.image.draw_bitmap(&sprites[i]);
+                    let x = (position - view_start) as f64 * scale;
+                    let start = Vector2::new(x, 50.0);
+                    let end = Vector2::new(x, height as f64 - 50.0);
+                    window_buffer.image.draw_line(&start, &end, marker_color);
+                    overlays.push(line_region(&start, &end));
                 }
             }
         }
 
-        if self.position < self.wave.sample_count as u32 - buffer_length {
-            self.position += 1;
-        }
-
-        window_buffer.image.draw_bitmap(&self.background);
-
         let mouse = &self.mouse.point;
         let mouse_fill_color = Color::from_u32(0x55A08563);
         let mouse_line_color = Color::from_u32(0xFF880000);
@@ -265,66 +461,370 @@ impl Application {
             let mouse_down = self.mouse.button[0];
             if mouse_down {
                 let start_x = self.mouse_start.x;
-                if mouse.x < start_x {
-                    window_buffer.image.draw_rect(
-                        &Vector2 {
-                            x: mouse.x as f64,
-                            y: 50.0,
-                        },
-                        &Vector2 {
-                            x: (start_x - mouse.x) as f64,
-                            y: height as f64 - 100.0,
-                        },
-                        mouse_fill_color,
-                    );
-                } else {
-                    window_buffer.image.draw_rect(
-                        &Vector2 {
-                            x: start_x as f64,
-                            y: 50.0,
-                        },
-                        &Vector2 {
-                            x: (mouse.x - start_x) as f64,
-                            y: height as f64 - 100.0,
-                        },
-                        mouse_fill_color,
-                    );
+                let position = Vector2::new(min_i32(mouse.x, start_x) as f64, 50.0);
+                let size = Vector2::new(abs_i32(mouse.x - start_x) as f64, height as f64 - 100.0);
+                window_buffer
+                    .image
+                    .draw_rect(&position, &size, mouse_fill_color);
+                overlays.push(Rect2i::covering(&position, &size).grow(1));
+
+                if let Some(ref stats) = selection_stats {
+                    let overlay_width = abs_i32(mouse.x - start_x);
+                    if overlay_width > 1 {
+                        let overlay = Sprite::from_image(
+                            Image::statistics_overlay(
+                                overlay_width,
+                                height - 100,
+                                stats,
+                                Color::from_u32(Colors::White as u32),
+                            ),
+                            position,
+                            LayerID::GUI,
+                        );
+                        window_buffer.image.draw_bitmap(&overlay);
+                    }
                 }
             }
-            window_buffer.image.draw_line(
-                &Vector2::new(mouse.x as f64, 50.0),
-                &Vector2::new(mouse.x as f64, height as f64 - 50.0),
-                mouse_line_color,
+            let start = Vector2::new(mouse.x as f64, 50.0);
+            let end = Vector2::new(mouse.x as f64, height as f64 - 50.0);
+            window_buffer
+                .image
+                .draw_line(&start, &end, mouse_line_color);
+            overlays.push(line_region(&start, &end));
+        }
+
+        let mut damage = restore;
+        damage.extend(overlays.iter());
+        self.overlay_regions = overlays;
+        simplify_damage(&damage, MAX_DAMAGE_REGIONS)
+    }
+
+    /// Feeds the meters everything the playback position moved over since the last frame.
+    /// Jumps restart them from a second before the new position.
+    fn update_meters(&mut self) {
+        let playhead = min_u32(self.position, self.wave.sample_count as u32);
+        let history = self.wave.sample_rate as u32;
+        let start = if playhead < self.meter_position || playhead - self.meter_position > history {
+            for meter in self.meters.iter_mut() {
+                meter.reset();
+            }
+            if playhead > history {
+                playhead - history
+            } else {
+                0
+            }
+        } else {
+            self.meter_position
+        };
+
+        let samples = &self.wave.samples[start as usize..playhead as usize];
+        for meter in self.meters.iter_mut() {
+            meter.process(samples);
+        }
+        self.meter_position = playhead;
+    }
+
+    /// Sample range under the current mouse drag
+    fn selected_samples(&self) -> (usize, usize) {
+        let width = self.window_buffer.image.width as f64;
+        let start_x = min_i32(self.mouse_start.x, self.mouse.point.x);
+        let end_x = max_i32(self.mouse_start.x, self.mouse.point.x);
+
+        let to_sample = |x: i32| {
+            let offset = clamp01_f64(x as f64 / width) * self.view_length as f64;
+            self.position as usize + round_f64_u32(offset) as usize
+        };
+        (to_sample(start_x), to_sample(end_x))
+    }
+
+    fn capture_noise_profile(&mut self) {
+        let (start, end) = self.selected_samples();
+        if end > start + 1 {
+            let profile = NoiseProfile::capture(&self.wave, start, end, NOISE_FRAME_SIZE);
+            self.noise_profile = Some(profile);
+        }
+    }
+
+    fn reduce_noise(&mut self) {
+        let result = match self.noise_profile {
+            Some(ref profile) => Some(NoiseReducer::new(profile.clone()).process(&self.wave)),
+            None => None,
+        };
+        if let Some(wave) = result {
+            self.wave = wave;
+        }
+    }
+
+    fn repair_clicks(&mut self) {
+        let detector = ClickDetector::new();
+        let events = detector.detect(&self.wave);
+        self.wave = detector.repair(&self.wave, &events);
+        self.events = events;
+    }
+
+    fn repair_clipping(&mut self) {
+        let detector = ClipDetector::new();
+        let level = detector.clip_level(&self.wave);
+        let events = detector.detect(&self.wave);
+        self.wave = Declipper::new().repair(&self.wave, &events, level);
+        self.events = events;
+    }
+
+    fn detect_pitch(&mut self) {
+        let mut detector = PitchDetector::new(self.wave.sample_rate);
+        detector.method = PitchMethod::PYin;
+        self.pitch_track = detector.detect(&self.wave);
+    }
+
+    fn track_beats(&mut self) {
+        self.rhythm = Some(Rhythm::analyze(&self.wave));
+    }
+
+    fn trim_silence(&mut self) {
+        let trimmed = SilenceDetector::new().trim(&self.wave);
+        // The view needs at least one buffer of samples.
+        if trimmed.sample_count > BUFFER_LENGTH as usize {
+            self.view_length = min_u32(self.view_length, trimmed.sample_count as u32);
+            self.wave = trimmed;
+            self.position = 0;
+            // Everything found at sample positions of the untrimmed wave
+            self.events.clear();
+            self.pitch_track.clear();
+            self.rhythm = None;
+        }
+    }
+
+    fn export_regions(&self) {
+        let regions = SilenceDetector::new().regions(&self.wave);
+        match export_regions(&self.wave, &regions, "region") {
+            Ok(paths) => println!("Exported {0} regions", paths.len()),
+            Err(error) => println!("Export failed: {0}", error),
+        }
+    }
+
+    fn export_features(&self) {
+        let features = FeatureExtractor::new(self.wave.sample_rate).extract(&self.wave);
+        if let Err(error) = features.write_csv("features.csv") {
+            println!("Feature export failed: {0}", error);
+        }
+    }
+
+    /// The window contents without the mouse selection, in the same layout as the raster layers
+    fn plot_view(&self, canvas: &mut Canvas) {
+        let size = canvas.size();
+        let (width, height) = (size.x, size.y);
+        let plot_size = Vector2::new(width, height - 100.0);
+        let plot_origin = Vector2::new(0.0, 50.0);
+
+        canvas.rect(
+            &Vector2::ORIGIN,
+            &size,
+            Color::from_u32(Colors::Black as u32),
+        );
+        canvas.rect(
+            &plot_origin,
+            &plot_size,
+            Color::from_u32(Colors::DarkGrey as u32),
+        );
+        {
+            let mut plot = Translated::new(canvas, plot_origin, plot_size.x, plot_size.y);
+            plot_wave(
+                &mut plot,
+                &self.wave,
+                self.position,
+                self.view_length,
+                &self.wave_style,
+            );
+            plot_pitch_contour(
+                &mut plot,
+                &self.pitch_track,
+                self.position,
+                self.view_length,
+                50.0,
+                1000.0,
+                Color::from_u32(Colors::White as u32),
             );
+            if self.equalizer.bands.len() > 0 {
+                self.equalizer.plot_response(
+                    &mut plot,
+                    self.wave.sample_rate,
+                    24.0,
+                    Color::from_u32(Colors::White as u32),
+                    Color::from_u32(0x8800AAFF),
+                );
+            }
+        }
+        {
+            let panel_width = meter_panel_width(self.meters.len(), METER_WIDTH, 6) as f64;
+            let panel_origin = Vector2::new(width - panel_width - 10.0, 60.0);
+            let mut panel = Translated::new(canvas, panel_origin, panel_width, height - 120.0);
+            plot_meter_panel(&mut panel, &self.meters, METER_WIDTH, 6);
+        }
+        plot_time_axis(
+            canvas,
+            self.wave.sample_rate,
+            self.position,
+            self.view_length,
+            height - 48.0,
+        );
+
+        let view_start = self.position;
+        let view_end = view_start + self.view_length;
+        let scale = width / self.view_length as f64;
+        for event in self.events.iter() {
+            let event_start = max_u32(event.start as u32, view_start);
+            let event_end = min_u32(event.end as u32, view_end);
+            if event_end > event_start {
+                canvas.rect(
+                    &Vector2::new((event_start - view_start) as f64 * scale, 50.0),
+                    &Vector2::new(
+                        max_f64(1.0, (event_end - event_start) as f64 * scale),
+                        height - 100.0,
+                    ),
+                    Color::from_u32(0x66FF2020),
+                );
+            }
+        }
+        if let Some(ref rhythm) = self.rhythm {
+            let markers = [
+                (&rhythm.onsets, Color::from_u32(0x88FFAA00)),
+                (&rhythm.beats, Color::from_u32(0xFF00CCFF)),
+            ];
+            for &(positions, marker_color) in markers.iter() {
+                for position in positions.iter() {
+                    let position = *position as u32;
+                    if position >= view_start && position < view_end {
+                        let x = (position - view_start) as f64 * scale;
+                        canvas.line(
+                            &Vector2::new(x, 50.0),
+                            &Vector2::new(x, height - 50.0),
+                            marker_color,
+                        );
+                    }
+                }
+            }
         }
     }
 
-    fn process_input(&mut self, message: windows::Message) {
+    fn export_view(&self) {
+        let image = &self.window_buffer.image;
+        let (width, height) = (image.width as f64, image.height as f64);
+
+        let mut svg = SvgCanvas::new(width, height);
+        self.plot_view(&mut svg);
+        let mut pdf = PdfCanvas::new(width, height);
+        self.plot_view(&mut pdf);
+
+        match svg.save("view.svg").and_then(|_| pdf.save("view.pdf")) {
+            Ok(_) => println!("Exported view.svg and view.pdf"),
+            Err(error) => println!("View export failed: {0}", error),
+        }
+    }
+
+    /// Puts a starting set of bands on the response layer, or clears them
+    fn toggle_equalizer(&mut self) {
+        if self.equalizer.bands.len() > 0 {
+            self.equalizer.bands.clear();
+            return;
+        }
+        let bands = [
+            EqBand::new(BandType::HighPass(12), 30.0, 0.0, 0.707),
+            EqBand::new(BandType::LowShelf, 120.0, 3.0, 0.707),
+            EqBand::new(BandType::Peak, 1000.0, -4.0, 1.4),
+            EqBand::new(BandType::HighShelf, 8000.0, 2.0, 0.707),
+        ];
+        for band in bands.iter() {
+            self.equalizer.add_band(*band);
+        }
+    }
+
+    fn toggle_quality(&mut self) {
+        self.wave_style.quality = match self.wave_style.quality {
+            Quality::Fast => Quality::AntiAliased,
+            Quality::AntiAliased => Quality::Fast,
+        };
+    }
+
+    fn toggle_amplitude_scale(&mut self) {
+        self.wave_style.scale = match self.wave_style.scale {
+            AmplitudeScale::Linear => AmplitudeScale::Decibel(-60.0),
+            AmplitudeScale::Decibel(_) => AmplitudeScale::Linear,
+        };
+    }
+
+    /// Halves or doubles the visible samples, keeping the view inside the waveform
+    fn zoom(&mut self, zoom_in: bool) {
+        let sample_count = self.wave.sample_count as u32;
+        let length = if zoom_in {
+            self.view_length / 2
+        } else {
+            self.view_length * 2
+        };
+        self.view_length = min_u32(max_u32(length, MIN_VIEW_LENGTH), sample_count);
+        self.position = min_u32(self.position, sample_count - self.view_length);
+    }
+
+    fn process_input(&mut self, message: Message) {
         match message {
-            windows::Message::Quit => self.is_running = false,
-            windows::Message::KeyDown(key) => {
+            Message::Quit => self.is_running = false,
+            Message::KeyDown(key) => {
+                if !self.keyboard.key[key as usize] {
+                    match key {
+                        KEY_A => self.toggle_amplitude_scale(),
+                        KEY_B => self.track_beats(),
+                        KEY_C => self.repair_clicks(),
+                        KEY_D => self.wave = remove_dc_offset(&self.wave),
+                        KEY_E => self.export_regions(),
+                        KEY_F => self.export_features(),
+                        KEY_G => self.toggle_equalizer(),
+                        KEY_H => self.wave = HumRemover::new().process(&self.wave),
+                        KEY_L => self.repair_clipping(),
+                        KEY_N => self.reduce_noise(),
+                        KEY_P => self.detect_pitch(),
+                        KEY_Q => self.toggle_quality(),
+                        KEY_S => self.events = SilenceDetector::new().detect(&self.wave),
+                        KEY_T => self.trim_silence(),
+                        KEY_V => self.export_view(),
+                        KEY_W => self.wave_style.mode = self.wave_style.mode.next(),
+                        KEY_UP => self.zoom(true),
+                        KEY_DOWN => self.zoom(false),
+                        _ => {}
+                    }
+                    self.content_changed = true;
+                }
                 self.keyboard.key[key as usize] = true;
             }
-            windows::Message::KeyUp(key) => {
+            Message::KeyUp(key) => {
                 self.keyboard.key[key as usize] = false;
             }
-            windows::Message::MouseDown(button, x, y) => {
+            Message::MouseDown(button, x, y) => {
                 self.mouse.button[button.as_usize()] = true;
                 self.mouse.point.x = x;
                 self.mouse.point.y = y;
                 self.mouse_start.x = x;
                 self.mouse_start.y = y;
             }
-            windows::Message::MouseUp(button, x, y) => {
+            Message::MouseUp(button, x, y) => {
+                let was_dragging = button.as_usize() == 0 && self.mouse.button[0];
                 self.mouse.button[button.as_usize()] = false;
                 self.mouse.point.x = x;
                 self.mouse.point.y = y;
+                if was_dragging {
+                    self.capture_noise_profile();
+                }
             }
 
-            windows::Message::MouseMove(x, y) => {
+            Message::MouseMove(x, y) => {
                 self.mouse.point.x = x;
                 self.mouse.point.y = y;
             }
+            Message::Resize(width, height) => {
+                // Minimizing reports an empty client area, keep the old buffer.
+                if width > 0 && height > 0 {
+                    self.window_buffer.image = Image::new(width, height);
+                    self.window_buffer.resized = true;
+                }
+            }
             _ => {}
         }
     }
@@ -92,6 +92,84 @@ impl Vector3 {
     const ORIGIN: Vector2 = Vector2 { x: 0.0, y: 0.0 };
 }
 
+/// The max edges are exclusive
+impl Rect2i {
+    pub fn new(min_x: i32, min_y: i32, max_x: i32, max_y: i32) -> Rect2i {
+        Rect2i {
+            min_x: min_x,
+            min_y: min_y,
+            max_x: max_x,
+            max_y: max_y,
+        }
+    }
+
+    /// Smallest rectangle holding the pixels touched by the area from `position` spanning `size`
+    pub fn covering(position: &Vector2, size: &Vector2) -> Rect2i {
+        let (x0, x1) = (position.x, position.x + size.x);
+        let (y0, y1) = (position.y, position.y + size.y);
+        Rect2i::new(
+            min_f64(x0, x1).floor() as i32,
+            min_f64(y0, y1).floor() as i32,
+            max_f64(x0, x1).ceil() as i32,
+            max_f64(y0, y1).ceil() as i32,
+        )
+    }
+
+    pub fn width(&self) -> i32 {
+        max_i32(0, self.max_x - self.min_x)
+    }
+
+    pub fn height(&self) -> i32 {
+        max_i32(0, self.max_y - self.min_y)
+    }
+
+    pub fn area(&self) -> i64 {
+        self.width() as i64 * self.height() as i64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_x <= self.min_x || self.max_y <= self.min_y
+    }
+
+    pub fn grow(&self, amount: i32) -> Rect2i {
+        Rect2i::new(
+            self.min_x - amount,
+            self.min_y - amount,
+            self.max_x + amount,
+            self.max_y + amount,
+        )
+    }
+
+    pub fn intersect(&self, other: &Rect2i) -> Rect2i {
+        Rect2i::new(
+            max_i32(self.min_x, other.min_x),
+            max_i32(self.min_y, other.min_y),
+            min_i32(self.max_x, other.max_x),
+            min_i32(self.max_y, other.max_y),
+        )
+    }
+
+    /// Bounding rectangle of both, an empty one does not count
+    pub fn union(&self, other: &Rect2i) -> Rect2i {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        Rect2i::new(
+            min_i32(self.min_x, other.min_x),
+            min_i32(self.min_y, other.min_y),
+            max_i32(self.max_x, other.max_x),
+            max_i32(self.max_y, other.max_y),
+        )
+    }
+
+    pub fn overlaps(&self, other: &Rect2i) -> bool {
+        !self.intersect(other).is_empty()
+    }
+}
+
 #[inline(always)]
 pub fn hadamard(a: Vector2, b: Vector2) -> Vector2 {
     Vector2::new(a.x * b.x, a.y * b.y)
@@ -306,3 +384,54 @@ pub fn fraction_part_f32(value: f32) -> f32 {
         value - floor_f32(value)
     }
 }
+
+/// Solves `matrix * x = rhs` for a square row major matrix with Gaussian elimination
+/// and partial pivoting. Returns None when the system is singular.
+pub fn solve_linear_system(matrix: &[f64], rhs: &[f64]) -> Option<Vec<f64>> {
+    let n = rhs.len();
+    if matrix.len() != n * n {
+        return None;
+    }
+
+    let mut a = matrix.to_vec();
+    let mut b = rhs.to_vec();
+
+    for column in 0..n {
+        let mut pivot = column;
+        for row in column + 1..n {
+            if abs_f64(a[row * n + column]) > abs_f64(a[pivot * n + column]) {
+                pivot = row;
+            }
+        }
+        if abs_f64(a[pivot * n + column]) < 1.0e-12 {
+            return None;
+        }
+        if pivot != column {
+            for k in 0..n {
+                a.swap(pivot * n + k, column * n + k);
+            }
+            b.swap(pivot, column);
+        }
+
+        for row in column + 1..n {
+            let factor = a[row * n + column] / a[column * n + column];
+            if factor == 0.0 {
+                continue;
+            }
+            for k in column..n {
+                a[row * n + k] -= factor * a[column * n + k];
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let mut sum = b[row];
+        for k in row + 1..n {
+            sum -= a[row * n + k] * x[k];
+        }
+        x[row] = sum / a[row * n + row];
+    }
+    Some(x)
+}
@@ -0,0 +1,295 @@
+#![allow(dead_code)]
+
+use std::f64;
+
+use canvas::*;
+use math::*;
+use render::*;
+use tools::*;
+
+pub const METER_FLOOR_DB: f64 = -120.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MeterType {
+    /// Sample peak, instant attack, 20 dB in 1.7 s fall
+    Peak,
+    /// 300 ms average power
+    Rms,
+    /// Volume unit, 300 ms rise and fall, 0 VU at -18 dBFS
+    Vu,
+    /// DIN 45406 quasi peak, 10 ms integration, 20 dB in 1.5 s fall
+    PpmType1,
+    /// BBC / EBU quasi peak, 4 dB per mark, 24 dB in 2.8 s fall, mark 4 at -18 dBFS
+    PpmType2,
+    /// Bob Katz K-system, RMS with 0 on the scale at -12, -14 or -20 dBFS
+    K(u32),
+}
+
+pub struct Meter {
+    pub meter_type: MeterType,
+    pub sample_rate: f64,
+    /// Seconds the peak hold line stays before falling
+    pub hold_time: f64,
+    /// dB per second the peak hold line falls after `hold_time`
+    pub hold_fall: f64,
+    /// Absolute sample value that lights the clip indicator
+    pub clip_level: f64,
+    pub clipped: bool,
+
+    attack: f64,
+    release: f64,
+    release_db_per_second: f64,
+    envelope: f64,
+    smoothed: f64,
+    hold: f64,
+    hold_remaining: usize,
+}
+
+impl MeterType {
+    /// Lowest and highest level shown and the reference (0 on the scale) in dBFS
+    pub fn scale(&self) -> (f64, f64, f64) {
+        match *self {
+            MeterType::Peak => (-60.0, 0.0, 0.0),
+            MeterType::Rms => (-60.0, 0.0, 0.0),
+            MeterType::Vu => (-38.0, -15.0, -18.0),
+            MeterType::PpmType1 => (-59.0, -4.0, -9.0),
+            MeterType::PpmType2 => (-34.0, -2.0, -18.0),
+            MeterType::K(k) => (-(k as f64) - 40.0, 0.0, -(k as f64)),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            MeterType::Peak => String::from("Peak"),
+            MeterType::Rms => String::from("RMS"),
+            MeterType::Vu => String::from("VU"),
+            MeterType::PpmType1 => String::from("PPM I"),
+            MeterType::PpmType2 => String::from("PPM II"),
+            MeterType::K(k) => format!("K-{0}", k),
+        }
+    }
+}
+
+#[inline(always)]
+fn one_pole(time: f64, sample_rate: f64) -> f64 {
+    if time > 0.0 {
+        1.0 - (-1.0 / (time * sample_rate)).exp()
+    } else {
+        1.0
+    }
+}
+
+impl Meter {
+    pub fn new(meter_type: MeterType, sample_rate: f64) -> Meter {
+        // Attack and release time constants, and the linear fall of quasi peak meters.
+        let (attack, release, fall) = match meter_type {
+            MeterType::Peak => (0.0, 0.0, 20.0 / 1.7),
+            MeterType::Rms | MeterType::K(_) => (0.3 / 3.0, 0.3 / 3.0, 0.0),
+            // Two cascaded poles at 45 ms reach 99% of a step in about 300 ms.
+            MeterType::Vu => (0.045, 0.045, 0.0),
+            MeterType::PpmType1 => (0.0017, 0.0, 20.0 / 1.5),
+            MeterType::PpmType2 => (0.005, 0.0, 24.0 / 2.8),
+        };
+
+        Meter {
+            meter_type: meter_type,
+            sample_rate: sample_rate,
+            hold_time: 1.5,
+            // Meters without a linear fall drop the hold at the peak meter's rate.
+            hold_fall: if fall > 0.0 { fall } else { 20.0 / 1.7 },
+            clip_level: 0.999,
+            clipped: false,
+            attack: one_pole(attack, sample_rate),
+            release: one_pole(release, sample_rate),
+            release_db_per_second: fall,
+            envelope: 0.0,
+            smoothed: 0.0,
+            hold: 0.0,
+            hold_remaining: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.clipped = false;
+        self.envelope = 0.0;
+        self.smoothed = 0.0;
+        self.hold = 0.0;
+        self.hold_remaining = 0;
+    }
+
+    pub fn push(&mut self, sample: f64) {
+        let magnitude = abs_f64(sample);
+        if magnitude >= self.clip_level {
+            self.clipped = true;
+        }
+
+        match self.meter_type {
+            MeterType::Rms | MeterType::K(_) => {
+                self.envelope += self.attack * (magnitude * magnitude - self.envelope);
+                self.smoothed = self.envelope.sqrt();
+            }
+            MeterType::Vu => {
+                self.envelope += self.attack * (magnitude - self.envelope);
+                self.smoothed += self.release * (self.envelope - self.smoothed);
+            }
+            _ => {
+                // Quasi peak: integrate up toward the input, fall at a fixed dB rate.
+                let fall = db_to_volume(-self.release_db_per_second / self.sample_rate);
+                let decayed = self.smoothed * fall;
+                let attacked = self.smoothed + self.attack * (magnitude - self.smoothed);
+                self.smoothed = if magnitude > self.smoothed {
+                    max_f64(attacked, decayed)
+                } else {
+                    decayed
+                };
+            }
+        }
+
+        let level = self.level();
+        if level >= self.hold {
+            self.hold = level;
+            self.hold_remaining = (self.hold_time * self.sample_rate) as usize;
+        } else if self.hold_remaining > 0 {
+            self.hold_remaining -= 1;
+        } else {
+            self.hold *= db_to_volume(-self.hold_fall / self.sample_rate);
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f64]) {
+        for sample in samples.iter() {
+            self.push(*sample);
+        }
+    }
+
+    /// Current reading as a linear amplitude, RMS meters read +3 dB for a full scale sine
+    pub fn level(&self) -> f64 {
+        match self.meter_type {
+            MeterType::Rms | MeterType::K(_) => self.smoothed * f64::consts::SQRT_2,
+            // An average reading meter is calibrated to read sine waves at their RMS.
+            MeterType::Vu => self.smoothed * PI / (2.0 * f64::consts::SQRT_2),
+            _ => self.smoothed,
+        }
+    }
+
+    pub fn level_db(&self) -> f64 {
+        to_meter_db(self.level())
+    }
+
+    pub fn hold_db(&self) -> f64 {
+        to_meter_db(self.hold)
+    }
+
+    /// Reading relative to the reference of the scale, VU or K-system units
+    pub fn scale_reading(&self) -> f64 {
+        let (_, _, reference) = self.meter_type.scale();
+        self.level_db() - reference
+    }
+
+    pub fn reset_clip(&mut self) {
+        self.clipped = false;
+    }
+}
+
+fn to_meter_db(level: f64) -> f64 {
+    if level > 0.0 {
+        max_f64(METER_FLOOR_DB, volume_to_db(level))
+    } else {
+        METER_FLOOR_DB
+    }
+}
+
+impl Image {
+    /// Vertical bar meter, green below the reference, amber up to 0 dBFS and red above
+    /// it, with a peak hold line and a clip light on top.
+    pub fn meter(width: i32, height: i32, meter: &Meter) -> Image {
+        let mut image = Image::new(width, height);
+        plot_meter(&mut image, meter);
+        image
+    }
+
+    /// All meters side by side with `gap` pixels between them
+    pub fn meter_panel(meters: &[Meter], meter_width: i32, gap: i32, height: i32) -> Image {
+        let width = meter_panel_width(meters.len(), meter_width, gap);
+        let mut image = Image::new(width, height);
+        plot_meter_panel(&mut image, meters, meter_width, gap);
+        image
+    }
+}
+
+pub fn meter_panel_width(count: usize, meter_width: i32, gap: i32) -> i32 {
+    let count = count as i32;
+    max_i32(1, count * meter_width + max_i32(0, count - 1) * gap)
+}
+
+/// See `Image::meter`, fills the whole canvas
+pub fn plot_meter(canvas: &mut Canvas, meter: &Meter) {
+    let size = canvas.size();
+    let width = size.x as i32;
+    canvas.rect(&Vector2::ORIGIN, &size, Color::from_u32(0xFF101010));
+    let (min_db, max_db, reference) = meter.meter_type.scale();
+    let clip_height = max_i32(4, width / 2) as f64;
+    let bar_height = size.y - clip_height - 2.0;
+    let bar_top = clip_height + 2.0;
+
+    let to_y = |db: f64| {
+        let t = clamp01_f64((db - min_db) / (max_db - min_db));
+        bar_top + (1.0 - t) * bar_height
+    };
+
+    let clip_color = if meter.clipped {
+        Color::from_u32(0xFFFF2020)
+    } else {
+        Color::from_u32(0xFF401010)
+    };
+    canvas.rect(
+        &Vector2::ORIGIN,
+        &Vector2::new(width as f64, clip_height),
+        clip_color,
+    );
+
+    let level_y = to_y(meter.level_db());
+    let reference_y = to_y(reference);
+    let full_scale_y = to_y(0.0);
+    let segments = [
+        (level_y, reference_y, bar_top + bar_height, 0xFF20C040),
+        (level_y, full_scale_y, reference_y, 0xFFE0B020),
+        (level_y, bar_top, full_scale_y, 0xFFFF3020),
+    ];
+    for &(level, top, bottom, color) in segments.iter() {
+        let start = max_f64(level, top);
+        if bottom > start {
+            canvas.rect(
+                &Vector2::new(1.0, start),
+                &Vector2::new((width - 2) as f64, bottom - start),
+                Color::from_u32(color),
+            );
+        }
+    }
+
+    let tick_color = Color::from_u32(0x60FFFFFF);
+    canvas.line(
+        &Vector2::new(0.0, reference_y),
+        &Vector2::new((width - 1) as f64, reference_y),
+        tick_color,
+    );
+
+    let hold_y = to_y(meter.hold_db());
+    if meter.hold_db() > min_db {
+        canvas.line(
+            &Vector2::new(0.0, hold_y),
+            &Vector2::new((width - 1) as f64, hold_y),
+            Color::from_u32(Colors::White as u32),
+        );
+    }
+}
+
+/// See `Image::meter_panel`, meters are as tall as the canvas
+pub fn plot_meter_panel(canvas: &mut Canvas, meters: &[Meter], meter_width: i32, gap: i32) {
+    let height = canvas.size().y;
+    for (index, meter) in meters.iter().enumerate() {
+        let offset = Vector2::new((index as i32 * (meter_width + gap)) as f64, 0.0);
+        let mut area = Translated::new(canvas, offset, meter_width as f64, height);
+        plot_meter(&mut area, meter);
+    }
+}
@@ -0,0 +1,490 @@
+#![allow(dead_code)]
+
+use audio::*;
+use canvas::*;
+use math::*;
+use render::*;
+
+/// Pitch class names starting at C
+pub const NOTE_NAMES: [&'static str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Clone, Copy)]
+pub struct PitchFrame {
+    /// Center of the analysis frame
+    pub position: usize,
+    /// Zero when the frame is unvoiced
+    pub frequency: f64,
+    pub confidence: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PitchMethod {
+    Yin,
+    /// Probabilistic YIN with Viterbi smoothing
+    PYin,
+}
+
+pub struct PitchDetector {
+    pub method: PitchMethod,
+    pub frame_size: usize,
+    pub hop: usize,
+    pub min_frequency: f64,
+    pub max_frequency: f64,
+    /// YIN absolute threshold on the normalized difference
+    pub threshold: f64,
+    /// pYIN pitch state resolution in cents
+    pub cents_per_state: f64,
+    /// pYIN largest pitch jump between frames in cents
+    pub max_jump_cents: f64,
+    /// pYIN probability of switching between voiced and unvoiced
+    pub voicing_switch: f64,
+}
+
+#[inline(always)]
+pub fn frequency_to_midi(frequency: f64) -> f64 {
+    69.0 + 12.0 * (frequency / 440.0).log2()
+}
+
+#[inline(always)]
+pub fn midi_to_frequency(note: f64) -> f64 {
+    440.0 * f64::powf(2.0, (note - 69.0) / 12.0)
+}
+
+/// Nearest note name with octave and the deviation in cents, "A4 +3"
+pub fn frequency_to_note(frequency: f64) -> String {
+    if frequency <= 0.0 {
+        return String::from("-");
+    }
+    let midi = frequency_to_midi(frequency);
+    let nearest = round_f64_i32(midi);
+    let cents = round_f64_i32((midi - nearest as f64) * 100.0);
+    let name = NOTE_NAMES[((nearest % 12 + 12) % 12) as usize];
+    let octave = nearest / 12 - 1;
+    if cents == 0 {
+        format!("{0}{1}", name, octave)
+    } else {
+        format!("{0}{1} {2:+}", name, octave, cents)
+    }
+}
+
+/// Cumulative mean normalized difference function of YIN, index is the lag
+pub fn yin_difference(frame: &[f64], max_lag: usize) -> Vec<f64> {
+    let window = frame.len() - max_lag;
+    let mut difference = vec![0.0; max_lag + 1];
+    for lag in 1..max_lag + 1 {
+        let mut sum = 0.0;
+        for i in 0..window {
+            let delta = frame[i] - frame[i + lag];
+            sum += delta * delta;
+        }
+        difference[lag] = sum;
+    }
+
+    let mut result = vec![1.0; max_lag + 1];
+    let mut running = 0.0;
+    for lag in 1..max_lag + 1 {
+        running += difference[lag];
+        result[lag] = if running > 0.0 {
+            difference[lag] * lag as f64 / running
+        } else {
+            1.0
+        };
+    }
+    result
+}
+
+/// Parabolic refinement of a minimum at `lag`
+fn refine_lag(values: &[f64], lag: usize) -> f64 {
+    if lag == 0 || lag + 1 >= values.len() {
+        return lag as f64;
+    }
+    let (a, b, c) = (values[lag - 1], values[lag], values[lag + 1]);
+    let denominator = a - 2.0 * b + c;
+    if abs_f64(denominator) < 1.0e-12 {
+        lag as f64
+    } else {
+        lag as f64 + 0.5 * (a - c) / denominator
+    }
+}
+
+/// First local minimum below `threshold` at or after `min_lag`
+fn first_dip(values: &[f64], min_lag: usize, threshold: f64) -> Option<usize> {
+    let mut lag = max_u32(min_lag as u32, 2) as usize;
+    while lag < values.len() {
+        if values[lag] < threshold {
+            while lag + 1 < values.len() && values[lag + 1] < values[lag] {
+                lag += 1;
+            }
+            return Some(lag);
+        }
+        lag += 1;
+    }
+    None
+}
+
+fn global_minimum(values: &[f64], min_lag: usize) -> usize {
+    let mut best = min_lag;
+    for lag in min_lag..values.len() {
+        if values[lag] < values[best] {
+            best = lag;
+        }
+    }
+    best
+}
+
+/// Closest candidate to the grid frequency of a state, the state itself when none is near
+fn refine_state(candidates: &[(f64, f64)], frequency: f64) -> f64 {
+    let mut best = frequency;
+    let mut best_distance = 0.5;
+    for &(candidate, _) in candidates.iter() {
+        let distance = abs_f64((candidate / frequency).log2() * 12.0);
+        if distance < best_distance {
+            best = candidate;
+            best_distance = distance;
+        }
+    }
+    best
+}
+
+/// Beta(2, 18) weights over thresholds 0.01..1.00, the prior pYIN uses
+fn threshold_prior() -> Vec<(f64, f64)> {
+    let mut result = Vec::with_capacity(100);
+    let mut total = 0.0;
+    for i in 1..101 {
+        let t = i as f64 / 100.0;
+        let weight = t * f64::powi(1.0 - t, 17);
+        result.push((t, weight));
+        total += weight;
+    }
+    for value in result.iter_mut() {
+        value.1 /= total;
+    }
+    result
+}
+
+impl PitchDetector {
+    pub fn new(sample_rate: f64) -> PitchDetector {
+        let frame_size = if sample_rate > 32000.0 { 2048 } else { 1024 };
+        PitchDetector {
+            method: PitchMethod::Yin,
+            frame_size: frame_size,
+            hop: frame_size / 4,
+            min_frequency: 50.0,
+            max_frequency: 1000.0,
+            threshold: 0.15,
+            cents_per_state: 10.0,
+            max_jump_cents: 250.0,
+            voicing_switch: 0.01,
+        }
+    }
+
+    fn lag_range(&self, sample_rate: f64) -> (usize, usize) {
+        let max_lag = min_u32(
+            (sample_rate / self.min_frequency) as u32,
+            (self.frame_size / 2) as u32,
+        ) as usize;
+        let min_lag = max_u32((sample_rate / self.max_frequency) as u32, 2) as usize;
+        (min_lag, max_lag)
+    }
+
+    fn frame(&self, wave: &Waveform, center: usize) -> Vec<f64> {
+        let half = self.frame_size / 2;
+        let mut result = vec![0.0; self.frame_size];
+        for n in 0..self.frame_size {
+            let index = center as isize + n as isize - half as isize;
+            if index >= 0 && (index as usize) < wave.sample_count {
+                result[n] = wave.samples[index as usize];
+            }
+        }
+        result
+    }
+
+    pub fn detect(&self, wave: &Waveform) -> Vec<PitchFrame> {
+        match self.method {
+            PitchMethod::Yin => self.detect_yin(wave),
+            PitchMethod::PYin => self.detect_pyin(wave),
+        }
+    }
+
+    pub fn detect_yin(&self, wave: &Waveform) -> Vec<PitchFrame> {
+        let (min_lag, max_lag) = self.lag_range(wave.sample_rate);
+        let mut result = Vec::new();
+
+        let mut center = 0;
+        while center < wave.sample_count {
+            let frame = self.frame(wave, center);
+            let values = yin_difference(&frame, max_lag);
+
+            let (frequency, confidence) = match first_dip(&values, min_lag, self.threshold) {
+                Some(lag) => {
+                    let refined = refine_lag(&values, lag);
+                    (wave.sample_rate / refined, clamp01_f64(1.0 - values[lag]))
+                }
+                None => {
+                    let lag = global_minimum(&values, min_lag);
+                    (0.0, clamp01_f64(1.0 - values[lag]))
+                }
+            };
+
+            result.push(PitchFrame {
+                position: center,
+                frequency: frequency,
+                confidence: confidence,
+            });
+            center += self.hop;
+        }
+        result
+    }
+
+    /// Pitch candidates of one frame as (frequency, probability)
+    fn pyin_candidates(
+        &self,
+        values: &[f64],
+        min_lag: usize,
+        sample_rate: f64,
+        prior: &[(f64, f64)],
+    ) -> Vec<(f64, f64)> {
+        let mut candidates: Vec<(usize, f64)> = Vec::new();
+        for &(threshold, weight) in prior.iter() {
+            let (lag, probability) = match first_dip(values, min_lag, threshold) {
+                Some(lag) => (lag, weight),
+                // Without a dip under the threshold the global minimum gets a small share.
+                None => (global_minimum(values, min_lag), weight * 0.01),
+            };
+            match candidates.iter().position(|c| c.0 == lag) {
+                Some(index) => candidates[index].1 += probability,
+                None => candidates.push((lag, probability)),
+            }
+        }
+
+        candidates
+            .iter()
+            .map(|&(lag, probability)| (sample_rate / refine_lag(values, lag), probability))
+            .collect()
+    }
+
+    pub fn detect_pyin(&self, wave: &Waveform) -> Vec<PitchFrame> {
+        let (min_lag, max_lag) = self.lag_range(wave.sample_rate);
+        let prior = threshold_prior();
+
+        let state_count = max_u32(
+            2,
+            (1200.0 * (self.max_frequency / self.min_frequency).log2() / self.cents_per_state)
+                as u32,
+        ) as usize;
+        let state_frequency = |state: usize| {
+            self.min_frequency * f64::powf(2.0, state as f64 * self.cents_per_state / 1200.0)
+        };
+        let jump = max_u32(1, (self.max_jump_cents / self.cents_per_state) as u32) as usize;
+
+        // Emission probabilities: voiced states first, then the unvoiced copies.
+        let mut observations: Vec<Vec<f64>> = Vec::new();
+        let mut positions = Vec::new();
+        let mut frame_candidates = Vec::new();
+        let mut center = 0;
+        while center < wave.sample_count {
+            let frame = self.frame(wave, center);
+            let values = yin_difference(&frame, max_lag);
+            let candidates = self.pyin_candidates(&values, min_lag, wave.sample_rate, &prior);
+
+            let mut emission = vec![0.0; 2 * state_count];
+            let mut voiced_total = 0.0;
+            for &(frequency, probability) in candidates.iter() {
+                if frequency < self.min_frequency || frequency > self.max_frequency {
+                    continue;
+                }
+                let state = round_f64_u32(
+                    1200.0 * (frequency / self.min_frequency).log2() / self.cents_per_state,
+                ) as usize;
+                if state < state_count {
+                    emission[state] += probability;
+                    voiced_total += probability;
+                }
+            }
+            let unvoiced = max_f64(0.0, 1.0 - voiced_total) / state_count as f64;
+            for state in 0..state_count {
+                emission[state_count + state] = unvoiced;
+            }
+
+            observations.push(emission);
+            positions.push(center);
+            frame_candidates.push(candidates);
+            center += self.hop;
+        }
+
+        let path = self.viterbi(&observations, state_count, jump);
+
+        let mut result = Vec::with_capacity(path.len());
+        for (index, state) in path.iter().enumerate() {
+            let voiced = *state < state_count;
+            let pitch_state = state % state_count;
+            let confidence = observations[index][pitch_state];
+            result.push(PitchFrame {
+                position: positions[index],
+                frequency: if voiced {
+                    refine_state(&frame_candidates[index], state_frequency(pitch_state))
+                } else {
+                    0.0
+                },
+                confidence: clamp01_f64(confidence),
+            });
+        }
+        result
+    }
+
+    /// Most likely state sequence in the log domain. Pitch can move up to `jump` states
+    /// per frame with a triangular weight, voicing flips with `voicing_switch`.
+    fn viterbi(&self, observations: &[Vec<f64>], state_count: usize, jump: usize) -> Vec<usize> {
+        let total = 2 * state_count;
+        let frame_count = observations.len();
+        if frame_count == 0 {
+            return Vec::new();
+        }
+
+        let floor = 1.0e-30;
+        let stay = (1.0 - self.voicing_switch).ln();
+        let switch = self.voicing_switch.ln();
+
+        let mut weights = Vec::with_capacity(2 * jump + 1);
+        let mut weight_sum = 0.0;
+        for offset in 0..2 * jump + 1 {
+            let distance = abs_i32(offset as i32 - jump as i32) as f64;
+            let weight = (jump as f64 + 1.0 - distance) / (jump as f64 + 1.0);
+            weights.push(weight);
+            weight_sum += weight;
+        }
+        let log_weights: Vec<f64> = weights.iter().map(|w| (w / weight_sum).ln()).collect();
+
+        let mut score: Vec<f64> = observations[0]
+            .iter()
+            .map(|p| (max_f64(*p, floor) / total as f64).ln())
+            .collect();
+        let mut backtrack: Vec<Vec<u32>> = Vec::with_capacity(frame_count);
+        backtrack.push(vec![0; total]);
+
+        for frame in 1..frame_count {
+            let mut next = vec![f64::NEG_INFINITY; total];
+            let mut from = vec![0u32; total];
+
+            for state in 0..total {
+                let voiced = state < state_count;
+                let pitch = state % state_count;
+                let low = if pitch > jump { pitch - jump } else { 0 };
+                let high = min_u32((pitch + jump) as u32, (state_count - 1) as u32) as usize;
+
+                for previous_pitch in low..high + 1 {
+                    let log_jump = log_weights[previous_pitch + jump - pitch];
+                    for &previous_voiced in [true, false].iter() {
+                        let previous = if previous_voiced {
+                            previous_pitch
+                        } else {
+                            state_count + previous_pitch
+                        };
+                        let log_voicing = if previous_voiced == voiced {
+                            stay
+                        } else {
+                            switch
+                        };
+                        let candidate = score[previous] + log_jump + log_voicing;
+                        if candidate > next[state] {
+                            next[state] = candidate;
+                            from[state] = previous as u32;
+                        }
+                    }
+                }
+                next[state] += max_f64(observations[frame][state], floor).ln();
+            }
+
+            score = next;
+            backtrack.push(from);
+        }
+
+        let mut best = 0;
+        for state in 1..total {
+            if score[state] > score[best] {
+                best = state;
+            }
+        }
+
+        let mut path = vec![0; frame_count];
+        path[frame_count - 1] = best;
+        for frame in (1..frame_count).rev() {
+            path[frame - 1] = backtrack[frame][path[frame]] as usize;
+        }
+        path
+    }
+}
+
+impl Image {
+    /// Draws the voiced parts of `track` over samples `start..start + range`, pitch on a
+    /// log scale between `min_frequency` (bottom) and `max_frequency` (top).
+    pub fn draw_pitch_contour(
+        &mut self,
+        track: &[PitchFrame],
+        start: u32,
+        range: u32,
+        min_frequency: f64,
+        max_frequency: f64,
+        color: Color,
+    ) {
+        plot_pitch_contour(
+            self,
+            track,
+            start,
+            range,
+            min_frequency,
+            max_frequency,
+            color,
+        );
+    }
+}
+
+/// See `Image::draw_pitch_contour`
+pub fn plot_pitch_contour(
+    canvas: &mut Canvas,
+    track: &[PitchFrame],
+    start: u32,
+    range: u32,
+    min_frequency: f64,
+    max_frequency: f64,
+    color: Color,
+) {
+    let size = canvas.size();
+    let (width, height) = (size.x, size.y);
+    let octaves = (max_frequency / min_frequency).log2();
+
+    let to_point = |frame: &PitchFrame| {
+        let x = (frame.position as f64 - start as f64) / range as f64 * width;
+        let t = (frame.frequency / min_frequency).log2() / octaves;
+        Vector2::new(x, height - 1.0 - clamp01_f64(t) * (height - 1.0))
+    };
+
+    for i in 1..track.len() {
+        let (a, b) = (&track[i - 1], &track[i]);
+        if a.frequency <= 0.0 || b.frequency <= 0.0 {
+            continue;
+        }
+        let end = start as usize + range as usize;
+        if b.position < start as usize || a.position > end {
+            continue;
+        }
+        let (p0, p1) = (to_point(a), to_point(b));
+        // Cut the segment at the view edges, keeping its slope
+        let y_at = |x: f64| {
+            if p1.x == p0.x {
+                p0.y
+            } else {
+                p0.y + (p1.y - p0.y) * (x - p0.x) / (p1.x - p0.x)
+            }
+        };
+        let x0 = clamp_f64(0.0, p0.x, width - 1.0);
+        let x1 = clamp_f64(0.0, p1.x, width - 1.0);
+        canvas.line(
+            &Vector2::new(x0, y_at(x0)),
+            &Vector2::new(x1, y_at(x1)),
+            color,
+        );
+    }
+}
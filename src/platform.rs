@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+use std::fmt;
+
+use math::*;
+use render::*;
+
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    pub fn as_usize(&self) -> usize {
+        let result = match *self {
+            MouseButton::Left => 0 as usize,
+            MouseButton::Right => 1 as usize,
+            MouseButton::Middle => 2 as usize,
+        };
+        result
+    }
+}
+
+impl fmt::Display for MouseButton {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match *self {
+            MouseButton::Left => "Left",
+            MouseButton::Right => "Right",
+            MouseButton::Middle => "Middle",
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+/// Key codes are below this, enough for every Win32 virtual key
+pub const KEY_COUNT: usize = 512;
+
+/// Key codes follow Win32 virtual keys on every platform, letters are uppercase ASCII.
+pub enum Message {
+    Quit,
+    Silence,
+    KeyDown(u32),
+    KeyUp(u32),
+    MouseUp(MouseButton, i32, i32),
+    MouseDown(MouseButton, i32, i32),
+    MouseMove(i32, i32),
+    Resize(i32, i32),
+}
+
+pub struct WindowBuffer {
+    pub image: Image,
+    pub resized: bool,
+}
+
+pub trait Platform {
+    fn create_window(&mut self, title: &str, width: i32, height: i32) -> bool;
+
+    /// Client area size in pixels
+    fn dimensions(&self) -> (i32, i32);
+
+    /// Next pending input, `Message::Silence` when there is none
+    fn poll_message(&mut self) -> Message;
+
+    /// Shows `image` in the window, top left aligned
+    fn present(&mut self, image: &Image);
+
+    /// Shows only `regions` of `image`, the rest of the window is left as presented before
+    fn present_regions(&mut self, image: &Image, regions: &[Rect2i]) {
+        if !regions.is_empty() {
+            self.present(image);
+        }
+    }
+
+    /// Seconds from an arbitrary start with the best available resolution
+    fn wall_clock(&self) -> f64;
+
+    fn refresh_rate(&self) -> f64 {
+        60.0
+    }
+
+    /// Sleeps until `target_seconds_per_frame` have passed since the previous call and
+    /// returns the actual frame time.
+    fn wait_for_frame(&mut self, target_seconds_per_frame: f64) -> f64;
+}
+
+/// Frame pacing shared by the platforms, coarse sleep then spin on the clock
+pub fn wait_until(
+    platform: &Platform,
+    last_counter: &mut f64,
+    target_seconds_per_frame: f64,
+    sleep_is_granular: bool,
+) -> f64 {
+    use std::thread;
+    use std::time::Duration;
+
+    let mut seconds_elapsed_for_frame = platform.wall_clock() - *last_counter;
+    if seconds_elapsed_for_frame < target_seconds_per_frame {
+        if sleep_is_granular {
+            let sleep_ms: u64 =
+                ((target_seconds_per_frame - seconds_elapsed_for_frame) * 1000.0) as u64;
+            if sleep_ms > 0 {
+                thread::sleep(Duration::from_millis(sleep_ms));
+            }
+        }
+
+        while seconds_elapsed_for_frame < target_seconds_per_frame {
+            seconds_elapsed_for_frame = platform.wall_clock() - *last_counter;
+        }
+    }
+
+    let end_counter = platform.wall_clock();
+    let delta_time = end_counter - *last_counter;
+    *last_counter = end_counter;
+    delta_time
+}
@@ -0,0 +1,432 @@
+#![allow(dead_code)]
+
+use std::io;
+
+use codec::*;
+use render::*;
+use zlib::*;
+
+pub const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Start column, start row, column step and row step of the seven Adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_RGB => 3,
+            COLOR_GRAY_ALPHA => 2,
+            COLOR_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    fn stride(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel() + 7) / 8
+    }
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> u32 {
+    (data[offset] as u32) << 24
+        | (data[offset + 1] as u32) << 16
+        | (data[offset + 2] as u32) << 8
+        | data[offset + 3] as u32
+}
+
+fn push_u32_be(output: &mut Vec<u8>, value: u32) {
+    output.push((value >> 24) as u8);
+    output.push((value >> 16) as u8);
+    output.push((value >> 8) as u8);
+    output.push(value as u8);
+}
+
+fn write_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    push_u32_be(output, data.len() as u32);
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(data);
+    let crc = crc32_update(crc32(chunk_type), data);
+    push_u32_be(output, crc);
+}
+
+#[inline(always)]
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i32 + b as i32 - c as i32;
+    let pa = (p - a as i32).abs();
+    let pb = (p - b as i32).abs();
+    let pc = (p - c as i32).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the filter of every row in place, `data` holds a filter byte before each row
+fn unfilter(data: &mut [u8], rows: usize, stride: usize, pixel_bytes: usize) -> io::Result<()> {
+    for row in 0..rows {
+        let start = row * (stride + 1);
+        let filter = data[start];
+        for i in 0..stride {
+            let at = start + 1 + i;
+            let left = if i >= pixel_bytes {
+                data[at - pixel_bytes]
+            } else {
+                0
+            };
+            let up = if row > 0 { data[at - stride - 1] } else { 0 };
+            let up_left = if row > 0 && i >= pixel_bytes {
+                data[at - stride - 1 - pixel_bytes]
+            } else {
+                0
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u32 + up as u32) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(invalid_image("Invalid PNG filter type")),
+            };
+            data[at] = data[at].wrapping_add(predictor);
+        }
+    }
+    Ok(())
+}
+
+/// Raw sample `channel` of pixel `x`, samples below 8 bits are packed from the high bit
+fn sample(row: &[u8], x: usize, channel: usize, header: &PngHeader) -> u32 {
+    let index = x * header.channels() + channel;
+    match header.bit_depth {
+        16 => (row[index * 2] as u32) << 8 | row[index * 2 + 1] as u32,
+        8 => row[index] as u32,
+        depth => {
+            let depth = depth as usize;
+            let bit = index * depth;
+            let shift = 8 - depth - bit % 8;
+            (row[bit / 8] as u32 >> shift) & ((1 << depth) - 1)
+        }
+    }
+}
+
+fn to_8_bit(value: u32, depth: u8) -> u32 {
+    match depth {
+        1 => value * 255,
+        2 => value * 85,
+        4 => value * 17,
+        16 => value >> 8,
+        _ => value,
+    }
+}
+
+fn decode_pixels(
+    image: &mut Image,
+    header: &PngHeader,
+    data: &[u8],
+    palette: &[(u32, u32, u32, u32)],
+    transparent: Option<(u32, u32, u32)>,
+    pass: (usize, usize, usize, usize),
+) -> io::Result<()> {
+    let (x0, y0, dx, dy) = pass;
+    let pass_width = (header.width - x0 + dx - 1) / dx;
+    let pass_height = (header.height - y0 + dy - 1) / dy;
+    let stride = header.stride(pass_width);
+    let depth = header.bit_depth;
+    for row in 0..pass_height {
+        let row_data = &data[row * (stride + 1) + 1..(row + 1) * (stride + 1)];
+        for column in 0..pass_width {
+            let s = |channel| sample(row_data, column, channel, header);
+            let (r, g, b, a) = match header.color_type {
+                COLOR_GRAY => {
+                    let value = s(0);
+                    let v = to_8_bit(value, depth);
+                    let a = if transparent == Some((value, value, value)) {
+                        0
+                    } else {
+                        255
+                    };
+                    (v, v, v, a)
+                }
+                COLOR_RGB => {
+                    let (r, g, b) = (s(0), s(1), s(2));
+                    let a = if transparent == Some((r, g, b)) {
+                        0
+                    } else {
+                        255
+                    };
+                    (
+                        to_8_bit(r, depth),
+                        to_8_bit(g, depth),
+                        to_8_bit(b, depth),
+                        a,
+                    )
+                }
+                COLOR_PALETTE => {
+                    let index = s(0) as usize;
+                    if index >= palette.len() {
+                        return Err(invalid_image("PNG palette index out of range"));
+                    }
+                    palette[index]
+                }
+                COLOR_GRAY_ALPHA => {
+                    let v = to_8_bit(s(0), depth);
+                    (v, v, v, to_8_bit(s(1), depth))
+                }
+                _ => (
+                    to_8_bit(s(0), depth),
+                    to_8_bit(s(1), depth),
+                    to_8_bit(s(2), depth),
+                    to_8_bit(s(3), depth),
+                ),
+            };
+            let x = x0 + column * dx;
+            let y = y0 + row * dy;
+            image.color_data[y * header.width + x] = Color::from_rgba(r, g, b, a);
+        }
+    }
+    Ok(())
+}
+
+impl Image {
+    /// Any valid PNG: all color types and bit depths, palettes, tRNS and Adam7 interlacing.
+    /// 16 bit samples are reduced to 8 bits.
+    pub fn decode_png(data: &[u8]) -> io::Result<Image> {
+        if data.len() < 8 || data[..8] != PNG_SIGNATURE {
+            return Err(invalid_image("Not a PNG file"));
+        }
+
+        let mut header: Option<PngHeader> = None;
+        let mut palette: Vec<(u32, u32, u32, u32)> = Vec::new();
+        let mut transparency: Vec<u8> = Vec::new();
+        let mut compressed: Vec<u8> = Vec::new();
+        let mut offset = 8;
+        loop {
+            if offset + 12 > data.len() {
+                return Err(invalid_image("Truncated PNG chunk"));
+            }
+            let length = read_u32_be(data, offset) as usize;
+            let chunk_type = &data[offset + 4..offset + 8];
+            if offset + 12 + length > data.len() {
+                return Err(invalid_image("Truncated PNG chunk"));
+            }
+            let chunk = &data[offset + 8..offset + 8 + length];
+            let crc = read_u32_be(data, offset + 8 + length);
+            if crc32_update(crc32(chunk_type), chunk) != crc {
+                return Err(invalid_image("PNG chunk checksum mismatch"));
+            }
+            offset += 12 + length;
+
+            match chunk_type {
+                b"IHDR" => {
+                    if length != 13 {
+                        return Err(invalid_image("Invalid PNG header"));
+                    }
+                    let png_header = PngHeader {
+                        width: read_u32_be(chunk, 0) as usize,
+                        height: read_u32_be(chunk, 4) as usize,
+                        bit_depth: chunk[8],
+                        color_type: chunk[9],
+                        interlaced: chunk[12] == 1,
+                    };
+                    let valid_depth = match png_header.color_type {
+                        COLOR_GRAY => [1, 2, 4, 8, 16].contains(&png_header.bit_depth),
+                        COLOR_PALETTE => [1, 2, 4, 8].contains(&png_header.bit_depth),
+                        COLOR_RGB | COLOR_GRAY_ALPHA | COLOR_RGBA => {
+                            [8, 16].contains(&png_header.bit_depth)
+                        }
+                        _ => false,
+                    };
+                    if !valid_depth || chunk[10] != 0 || chunk[11] != 0 || chunk[12] > 1 {
+                        return Err(invalid_image("Unsupported PNG format"));
+                    }
+                    check_dimensions(png_header.width as i64, png_header.height as i64)?;
+                    header = Some(png_header);
+                }
+                b"PLTE" => {
+                    palette = chunk
+                        .chunks(3)
+                        .filter(|entry| entry.len() == 3)
+                        .map(|entry| (entry[0] as u32, entry[1] as u32, entry[2] as u32, 255))
+                        .collect();
+                }
+                b"tRNS" => transparency = chunk.to_vec(),
+                b"IDAT" => compressed.extend_from_slice(chunk),
+                b"IEND" => break,
+                _ => {
+                    // Lowercase first letter marks chunks that are safe to ignore.
+                    if chunk_type[0] & 0x20 == 0 {
+                        return Err(invalid_image("Unknown critical PNG chunk"));
+                    }
+                }
+            }
+        }
+
+        let header = match header {
+            Some(header) => header,
+            None => return Err(invalid_image("PNG has no header")),
+        };
+        if header.color_type == COLOR_PALETTE && palette.is_empty() {
+            return Err(invalid_image("PNG has no palette"));
+        }
+
+        for (index, alpha) in transparency.iter().enumerate() {
+            if index < palette.len() {
+                palette[index].3 = *alpha as u32;
+            }
+        }
+        let transparent = match header.color_type {
+            COLOR_GRAY if transparency.len() >= 2 => {
+                let value = (transparency[0] as u32) << 8 | transparency[1] as u32;
+                Some((value, value, value))
+            }
+            COLOR_RGB if transparency.len() >= 6 => Some((
+                (transparency[0] as u32) << 8 | transparency[1] as u32,
+                (transparency[2] as u32) << 8 | transparency[3] as u32,
+                (transparency[4] as u32) << 8 | transparency[5] as u32,
+            )),
+            _ => None,
+        };
+
+        let mut pixels = decompress(&compressed)?;
+        let mut image = Image::new(header.width as i32, header.height as i32);
+        let pixel_bytes = (header.bits_per_pixel() + 7) / 8;
+        let passes: Vec<(usize, usize, usize, usize)> = if header.interlaced {
+            ADAM7.to_vec()
+        } else {
+            vec![(0, 0, 1, 1)]
+        };
+
+        let mut offset = 0;
+        for pass in passes.iter() {
+            let &(x0, y0, dx, dy) = pass;
+            if header.width <= x0 || header.height <= y0 {
+                continue;
+            }
+            let pass_width = (header.width - x0 + dx - 1) / dx;
+            let pass_height = (header.height - y0 + dy - 1) / dy;
+            let stride = header.stride(pass_width);
+            let size = (stride + 1) * pass_height;
+            if offset + size > pixels.len() {
+                return Err(invalid_image("PNG image data is too short"));
+            }
+            let pass_data = &mut pixels[offset..offset + size];
+            unfilter(pass_data, pass_height, stride, pixel_bytes)?;
+            decode_pixels(&mut image, &header, pass_data, &palette, transparent, *pass)?;
+            offset += size;
+        }
+        Ok(image)
+    }
+
+    /// 8 bit RGBA, or RGB when every pixel is opaque, with the smallest filter chosen per row
+    pub fn encode_png(&self) -> Vec<u8> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let opaque = self
+            .color_data
+            .iter()
+            .all(|color| color.separate().3 == 255);
+        let channels = if opaque { 3 } else { 4 };
+        let stride = width * channels;
+
+        let mut raw = vec![0u8; stride * height];
+        for (index, color) in self.color_data.iter().enumerate() {
+            let (r, g, b, a) = color.separate();
+            let at = index * channels;
+            raw[at] = r as u8;
+            raw[at + 1] = g as u8;
+            raw[at + 2] = b as u8;
+            if !opaque {
+                raw[at + 3] = a as u8;
+            }
+        }
+
+        let mut filtered = Vec::with_capacity((stride + 1) * height);
+        let mut candidate = vec![0u8; stride];
+        let mut best = vec![0u8; stride];
+        for row in 0..height {
+            let current = &raw[row * stride..(row + 1) * stride];
+            let previous = if row > 0 {
+                Some(&raw[(row - 1) * stride..row * stride])
+            } else {
+                None
+            };
+
+            // Smallest sum of absolute differences is a good guess at what compresses best.
+            let mut best_filter = 0;
+            let mut best_cost = u64::max_value();
+            for filter in 0..5u8 {
+                let mut cost = 0u64;
+                for i in 0..stride {
+                    let left = if i >= channels {
+                        current[i - channels]
+                    } else {
+                        0
+                    };
+                    let up = previous.map_or(0, |previous| previous[i]);
+                    let up_left = if i >= channels {
+                        previous.map_or(0, |previous| previous[i - channels])
+                    } else {
+                        0
+                    };
+                    let predictor = match filter {
+                        0 => 0,
+                        1 => left,
+                        2 => up,
+                        3 => ((left as u32 + up as u32) / 2) as u8,
+                        _ => paeth(left, up, up_left),
+                    };
+                    let value = current[i].wrapping_sub(predictor);
+                    candidate[i] = value;
+                    cost += (value as i8 as i32).abs() as u64;
+                }
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_filter = filter;
+                    best.copy_from_slice(&candidate);
+                }
+            }
+            filtered.push(best_filter);
+            filtered.extend_from_slice(&best);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        push_u32_be(&mut header, width as u32);
+        push_u32_be(&mut header, height as u32);
+        header.push(8);
+        header.push(if opaque { COLOR_RGB } else { COLOR_RGBA });
+        header.extend_from_slice(&[0, 0, 0]);
+
+        let mut output = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut output, b"IHDR", &header);
+        write_chunk(&mut output, b"IDAT", &compress(&filtered, 6));
+        write_chunk(&mut output, b"IEND", &[]);
+        output
+    }
+}
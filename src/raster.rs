@@ -0,0 +1,642 @@
+#![allow(dead_code)]
+
+use math::*;
+use render::*;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineJoin {
+    Miter,
+    Round,
+    Bevel,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineCap {
+    Butt,
+    Round,
+    Square,
+}
+
+/// How wide lines are drawn and how their corners and ends look
+#[derive(Clone, Copy)]
+pub struct Stroke {
+    pub width: f64,
+    pub join: LineJoin,
+    pub cap: LineCap,
+    /// Longest miter as a multiple of the line width before it falls back to a bevel
+    pub miter_limit: f64,
+}
+
+/// Signed area coverage over a rectangle of pixels. Every edge adds the area it
+/// covers to the cells it crosses and the rest of its height to the cell after, a
+/// running sum along each row then gives the coverage of everything added with
+/// non-zero winding. Overlapping shapes of the same orientation merge.
+pub struct Coverage {
+    pub left: i32,
+    pub top: i32,
+    pub width: usize,
+    pub height: usize,
+    stride: usize,
+    cells: Vec<f32>,
+}
+
+impl Stroke {
+    pub fn new(width: f64) -> Stroke {
+        Stroke {
+            width: width,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+            miter_limit: 4.0,
+        }
+    }
+}
+
+impl Coverage {
+    pub fn new(left: i32, top: i32, width: usize, height: usize) -> Coverage {
+        Coverage {
+            left: left,
+            top: top,
+            width: width,
+            height: height,
+            stride: width + 2,
+            cells: vec![0.0; (width + 2) * height],
+        }
+    }
+
+    /// Covers the bounding box of `polygons` clipped to `bounds`
+    pub fn around(polygons: &[Vec<Vector2>], bounds: &Rect) -> Coverage {
+        let (mut min_x, mut min_y) = (bounds.x + bounds.width, bounds.y + bounds.height);
+        let (mut max_x, mut max_y) = (bounds.x, bounds.y);
+        for point in polygons.iter().flat_map(|polygon| polygon.iter()) {
+            if !point.x.is_finite() || !point.y.is_finite() {
+                continue;
+            }
+            min_x = min_i32(min_x, floor_f64_i32(max_f64(point.x, -1.0e6)));
+            min_y = min_i32(min_y, floor_f64_i32(max_f64(point.y, -1.0e6)));
+            max_x = max_i32(max_x, ceil_f64_i32(min_f64(point.x, 1.0e6)));
+            max_y = max_i32(max_y, ceil_f64_i32(min_f64(point.y, 1.0e6)));
+        }
+        let area = Rect::new(min_x, min_y, max_x - min_x, max_y - min_y).intersect(bounds);
+        Coverage::new(area.x, area.y, area.width as usize, area.height as usize)
+    }
+
+    /// One edge, in image coordinates
+    pub fn line(&mut self, start: &Vector2, end: &Vector2) {
+        let start = Vector2::new(start.x - self.left as f64, start.y - self.top as f64);
+        let end = Vector2::new(end.x - self.left as f64, end.y - self.top as f64);
+        if start.y == end.y || !start.y.is_finite() || !end.y.is_finite() {
+            return;
+        }
+        let (direction, top, bottom) = if start.y < end.y {
+            (1.0, start, end)
+        } else {
+            (-1.0, end, start)
+        };
+        let dxdy = (bottom.x - top.x) / (bottom.y - top.y);
+        let mut x = top.x;
+        if top.y < 0.0 {
+            x -= top.y * dxdy;
+        }
+
+        let width = self.width as f64;
+        let first_row = clamp_f64(0.0, top.y.floor(), self.height as f64) as usize;
+        let last_row = clamp_f64(0.0, bottom.y.ceil(), self.height as f64) as usize;
+        for row in first_row..last_row {
+            let line_start = row * self.stride;
+            let dy = min_f64((row + 1) as f64, bottom.y) - max_f64(row as f64, top.y);
+            let x_next = x + dxdy * dy;
+            let d = dy * direction;
+            let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+            let x0 = clamp_f64(0.0, x0, width);
+            let x1 = clamp_f64(0.0, x1, width);
+            let x0_floor = x0.floor();
+            let x0_index = x0_floor as usize;
+            let x1_ceil = x1.ceil();
+            let x1_index = x1_ceil as usize;
+            let cells = &mut self.cells[line_start..line_start + self.stride];
+
+            if x1_index <= x0_index + 1 {
+                let middle = 0.5 * (x0 + x1) - x0_floor;
+                cells[x0_index] += (d - d * middle) as f32;
+                cells[x0_index + 1] += (d * middle) as f32;
+            } else {
+                let slope = 1.0 / (x1 - x0);
+                let x0_fraction = x0 - x0_floor;
+                let first_area = 0.5 * slope * (1.0 - x0_fraction) * (1.0 - x0_fraction);
+                let x1_fraction = x1 - x1_ceil + 1.0;
+                let last_area = 0.5 * slope * x1_fraction * x1_fraction;
+
+                cells[x0_index] += (d * first_area) as f32;
+                if x1_index == x0_index + 2 {
+                    cells[x0_index + 1] += (d * (1.0 - first_area - last_area)) as f32;
+                } else {
+                    let second_area = slope * (1.5 - x0_fraction);
+                    cells[x0_index + 1] += (d * (second_area - first_area)) as f32;
+                    for cell in cells[x0_index + 2..x1_index - 1].iter_mut() {
+                        *cell += (d * slope) as f32;
+                    }
+                    let covered = second_area + (x1_index - x0_index - 3) as f64 * slope;
+                    cells[x1_index - 1] += (d * (1.0 - covered - last_area)) as f32;
+                }
+                cells[x1_index] += (d * last_area) as f32;
+            }
+            x = x_next;
+        }
+    }
+
+    /// Closed polygon, the last point connects back to the first
+    pub fn polygon(&mut self, points: &[Vector2]) {
+        for i in 0..points.len() {
+            let next = if i + 1 < points.len() { i + 1 } else { 0 };
+            self.line(&points[i], &points[next]);
+        }
+    }
+
+    /// 0..255 per pixel, row by row
+    pub fn values(&self, rule: FillRule) -> Vec<u8> {
+        let mut values = vec![0u8; self.width * self.height];
+        for row in 0..self.height {
+            let mut sum = 0.0f32;
+            let cells = &self.cells[row * self.stride..(row + 1) * self.stride];
+            for (column, cell) in cells[..self.width].iter().enumerate() {
+                sum += *cell;
+                let winding = if sum < 0.0 { -sum } else { sum };
+                let value = match rule {
+                    FillRule::NonZero => winding.min(1.0),
+                    FillRule::EvenOdd => {
+                        let parity = winding % 2.0;
+                        if parity > 1.0 {
+                            2.0 - parity
+                        } else {
+                            parity
+                        }
+                    }
+                };
+                values[row * self.width + column] = (value * 255.0 + 0.5) as u8;
+            }
+        }
+        values
+    }
+}
+
+/// Twice the signed area, positive for clockwise polygons in image coordinates
+fn signed_area(points: &[Vector2]) -> f64 {
+    let mut area = 0.0;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        area += a.x * b.y - b.x * a.y;
+    }
+    area
+}
+
+/// Segments for a full turn, the error stays well under a tenth of a pixel
+fn curve_segments(radius: f64) -> usize {
+    clamp_f64(8.0, (radius * 4.0).ceil(), 256.0) as usize
+}
+
+/// Points from `start_angle` to `end_angle` (radians, clockwise on screen) including both
+pub fn arc_points(
+    center: &Vector2,
+    radii: &Vector2,
+    start_angle: f64,
+    end_angle: f64,
+) -> Vec<Vector2> {
+    let sweep = end_angle - start_angle;
+    let full = curve_segments(max_f64(abs_f64(radii.x), abs_f64(radii.y))) as f64;
+    let segments = max_f64(1.0, (abs_f64(sweep) / (2.0 * PI) * full).ceil()) as usize;
+    (0..segments + 1)
+        .map(|i| {
+            let angle = start_angle + sweep * i as f64 / segments as f64;
+            Vector2::new(
+                center.x + radii.x * angle.cos(),
+                center.y + radii.y * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+pub fn ellipse_points(center: &Vector2, radii: &Vector2) -> Vec<Vector2> {
+    let mut points = arc_points(center, radii, 0.0, 2.0 * PI);
+    points.pop();
+    points
+}
+
+/// Corners are quarter circles of `radius`, at most half the shorter side
+pub fn rounded_rect_points(position: &Vector2, size: &Vector2, radius: f64) -> Vec<Vector2> {
+    let radius = clamp_f64(0.0, radius, min_f64(size.x, size.y) * 0.5);
+    let (left, top) = (position.x, position.y);
+    let (right, bottom) = (position.x + size.x, position.y + size.y);
+    if radius <= 0.0 {
+        return vec![
+            Vector2::new(left, top),
+            Vector2::new(right, top),
+            Vector2::new(right, bottom),
+            Vector2::new(left, bottom),
+        ];
+    }
+    let radii = Vector2::new(radius, radius);
+    let corners = [
+        (Vector2::new(left + radius, top + radius), PI),
+        (Vector2::new(right - radius, top + radius), 1.5 * PI),
+        (Vector2::new(right - radius, bottom - radius), 0.0),
+        (Vector2::new(left + radius, bottom - radius), 0.5 * PI),
+    ];
+    let mut points = Vec::new();
+    for &(center, angle) in corners.iter() {
+        points.extend(arc_points(&center, &radii, angle, angle + 0.5 * PI));
+    }
+    points
+}
+
+/// Polygons that together cover `points` stroked with `stroke`. They all wind the
+/// same way so they merge in a `Coverage` instead of blending twice where they overlap.
+pub fn stroke_polygons(points: &[Vector2], stroke: &Stroke) -> Vec<Vec<Vector2>> {
+    outline(points, stroke, false)
+}
+
+/// Like `stroke_polygons` with the last point joined back to the first instead of capped
+pub fn closed_stroke_polygons(points: &[Vector2], stroke: &Stroke) -> Vec<Vec<Vector2>> {
+    outline(points, stroke, true)
+}
+
+fn outline(points: &[Vector2], stroke: &Stroke, closed: bool) -> Vec<Vec<Vector2>> {
+    let same =
+        |a: &Vector2, b: &Vector2| abs_f64(a.x - b.x) < 1.0e-9 && abs_f64(a.y - b.y) < 1.0e-9;
+    let mut path: Vec<Vector2> = Vec::with_capacity(points.len());
+    for point in points.iter() {
+        if !point.x.is_finite() || !point.y.is_finite() {
+            continue;
+        }
+        if !path.last().map_or(false, |last| same(last, point)) {
+            path.push(*point);
+        }
+    }
+    if closed && path.len() > 1 && same(&path[0], &path[path.len() - 1]) {
+        path.pop();
+    }
+    let closed = closed && path.len() > 2;
+
+    let half = stroke.width * 0.5;
+    let mut polygons = Vec::new();
+    if path.is_empty() || half <= 0.0 {
+        return polygons;
+    }
+    if path.len() == 1 {
+        let point = path[0];
+        match stroke.cap {
+            LineCap::Butt => {}
+            LineCap::Round => polygons.push(ellipse_points(&point, &Vector2::new(half, half))),
+            LineCap::Square => polygons.push(vec![
+                Vector2::new(point.x - half, point.y - half),
+                Vector2::new(point.x + half, point.y - half),
+                Vector2::new(point.x + half, point.y + half),
+                Vector2::new(point.x - half, point.y + half),
+            ]),
+        }
+        return polygons;
+    }
+
+    let count = path.len();
+    let segments = if closed { count } else { count - 1 };
+    let directions: Vec<Vector2> = (0..segments)
+        .map(|i| {
+            let delta = path[(i + 1) % count] - path[i];
+            delta * (1.0 / length_v2(delta))
+        })
+        .collect();
+    let normal = |direction: &Vector2| Vector2::new(-direction.y * half, direction.x * half);
+
+    for (i, direction) in directions.iter().enumerate() {
+        let (start, end) = (path[i], path[(i + 1) % count]);
+        let offset = normal(direction);
+        polygons.push(vec![
+            start + offset,
+            end + offset,
+            end - offset,
+            start - offset,
+        ]);
+    }
+
+    let corners = if closed { 0..count } else { 1..count - 1 };
+    for i in corners {
+        let (before, after) = (directions[(i + segments - 1) % segments], directions[i]);
+        let vertex = path[i];
+        let cross = before.x * after.y - before.y * after.x;
+        let dot = inner_v2(before, after);
+        if abs_f64(cross) < 1.0e-9 && dot > 0.0 {
+            continue;
+        }
+        // The outer side of the corner is opposite to the way the path turns
+        let side = if cross > 0.0 { -1.0 } else { 1.0 };
+        let outer_before = vertex + normal(&before) * side;
+        let outer_after = vertex + normal(&after) * side;
+        match stroke.join {
+            LineJoin::Round => polygons.push(ellipse_points(&vertex, &Vector2::new(half, half))),
+            LineJoin::Bevel => polygons.push(vec![vertex, outer_before, outer_after]),
+            LineJoin::Miter => {
+                let bisector = normal(&before) + normal(&after);
+                let bisector_length = length_v2(bisector);
+                // Half width over the cosine of half the turning angle
+                let cosine = bisector_length / (2.0 * half);
+                if cosine > 1.0e-9 && 1.0 / cosine <= stroke.miter_limit {
+                    let tip = vertex + bisector * (side * half / (cosine * bisector_length));
+                    polygons.push(vec![vertex, outer_before, tip, outer_after]);
+                } else {
+                    polygons.push(vec![vertex, outer_before, outer_after]);
+                }
+            }
+        }
+    }
+
+    if !closed {
+        let ends = [
+            (path[0], directions[0] * -1.0),
+            (path[count - 1], directions[segments - 1]),
+        ];
+        for &(point, outward) in ends.iter() {
+            let offset = normal(&outward);
+            match stroke.cap {
+                LineCap::Butt => {}
+                LineCap::Round => polygons.push(ellipse_points(&point, &Vector2::new(half, half))),
+                LineCap::Square => {
+                    let extension = outward * half;
+                    polygons.push(vec![
+                        point + offset,
+                        point + offset + extension,
+                        point - offset + extension,
+                        point - offset,
+                    ]);
+                }
+            }
+        }
+    }
+
+    for polygon in polygons.iter_mut() {
+        if signed_area(polygon) < 0.0 {
+            polygon.reverse();
+        }
+    }
+    polygons
+}
+
+impl Image {
+    /// Blends `color` with its alpha scaled by `coverage` (0..1), outside pixels are ignored
+    pub fn blend_point(&mut self, x: i32, y: i32, coverage: f64, color: Color) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height || coverage <= 0.0 {
+            return;
+        }
+        let (r, g, b, a) = color.separate();
+        let alpha = round_f64_u32(a as f64 * min_f64(coverage, 1.0));
+        if alpha == 0 {
+            return;
+        }
+        let width = self.width;
+        plot_point(
+            x,
+            y,
+            width,
+            Color::from_rgba(r, g, b, alpha),
+            &mut self.color_data,
+        );
+    }
+
+    /// Point at a sub pixel position spread over the four pixels around it
+    pub fn draw_point_aa(&mut self, position: &Vector2, color: Color) {
+        let x = position.x - 0.5;
+        let y = position.y - 0.5;
+        let (left, top) = (floor_f64_i32(x), floor_f64_i32(y));
+        let (fraction_x, fraction_y) = (x - left as f64, y - top as f64);
+        self.blend_point(left, top, (1.0 - fraction_x) * (1.0 - fraction_y), color);
+        self.blend_point(left + 1, top, fraction_x * (1.0 - fraction_y), color);
+        self.blend_point(left, top + 1, (1.0 - fraction_x) * fraction_y, color);
+        self.blend_point(left + 1, top + 1, fraction_x * fraction_y, color);
+    }
+
+    /// Xiaolin Wu's line, one pixel wide. Pixel centers are at half coordinates.
+    pub fn draw_line_aa(&mut self, start: &Vector2, end: &Vector2, color: Color) {
+        let (mut x0, mut y0) = (start.x - 0.5, start.y - 0.5);
+        let (mut x1, mut y1) = (end.x - 0.5, end.y - 0.5);
+        if !(x0.is_finite() && y0.is_finite() && x1.is_finite() && y1.is_finite()) {
+            return;
+        }
+        let steep = abs_f64(y1 - y0) > abs_f64(x1 - x0);
+        if steep {
+            ::std::mem::swap(&mut x0, &mut y0);
+            ::std::mem::swap(&mut x1, &mut y1);
+        }
+        if x0 > x1 {
+            ::std::mem::swap(&mut x0, &mut x1);
+            ::std::mem::swap(&mut y0, &mut y1);
+        }
+        let dx = x1 - x0;
+        let gradient = if dx < 1.0e-9 { 0.0 } else { (y1 - y0) / dx };
+
+        let plot = |image: &mut Image, x: i32, y: i32, coverage: f64| {
+            if steep {
+                image.blend_point(y, x, coverage, color);
+            } else {
+                image.blend_point(x, y, coverage, color);
+            }
+        };
+
+        // End points are weighted by how much of their pixel the line reaches into
+        let first_x = x0.round();
+        let first_y = y0 + gradient * (first_x - x0);
+        let first_gap = 1.0 - (x0 + 0.5 - (x0 + 0.5).floor());
+        let first_row = first_y.floor();
+        let first_fraction = first_y - first_row;
+        plot(
+            self,
+            first_x as i32,
+            first_row as i32,
+            (1.0 - first_fraction) * first_gap,
+        );
+        plot(
+            self,
+            first_x as i32,
+            first_row as i32 + 1,
+            first_fraction * first_gap,
+        );
+
+        let last_x = x1.round();
+        if last_x > first_x {
+            let last_y = y1 + gradient * (last_x - x1);
+            let last_gap = x1 + 0.5 - (x1 + 0.5).floor();
+            let last_row = last_y.floor();
+            let last_fraction = last_y - last_row;
+            plot(
+                self,
+                last_x as i32,
+                last_row as i32,
+                (1.0 - last_fraction) * last_gap,
+            );
+            plot(
+                self,
+                last_x as i32,
+                last_row as i32 + 1,
+                last_fraction * last_gap,
+            );
+        }
+
+        // Only the columns that can be on the image
+        let limit = if steep { self.height } else { self.width } as f64;
+        let from = max_f64(first_x + 1.0, -1.0);
+        let to = min_f64(last_x, limit + 1.0);
+        let mut column = from;
+        while column < to {
+            let y = first_y + gradient * (column - first_x);
+            let row = y.floor();
+            let fraction = y - row;
+            plot(self, column as i32, row as i32, 1.0 - fraction);
+            plot(self, column as i32, row as i32 + 1, fraction);
+            column += 1.0;
+        }
+    }
+
+    /// Blends `color` through the coverage, each pixel once
+    pub fn draw_coverage(&mut self, coverage: &Coverage, rule: FillRule, color: Color) {
+        let values = coverage.values(rule);
+        let opaque = color.separate().3 == 255;
+        for row in 0..coverage.height {
+            let y = coverage.top + row as i32;
+            for column in 0..coverage.width {
+                let x = coverage.left + column as i32;
+                let value = values[row * coverage.width + column];
+                // Fully covered opaque pixels are the bulk of large fills
+                if value == 255 && opaque && x >= 0 && y >= 0 && x < self.width && y < self.height {
+                    self.color_data[(y * self.width + x) as usize] = color;
+                } else if value > 0 {
+                    self.blend_point(x, y, value as f64 / 255.0, color);
+                }
+            }
+        }
+    }
+
+    /// Antialiased fill of closed polygons, holes and overlaps follow `rule`
+    pub fn fill_polygons(&mut self, polygons: &[Vec<Vector2>], rule: FillRule, color: Color) {
+        let bounds = Rect::new(0, 0, self.width, self.height);
+        let mut coverage = Coverage::around(polygons, &bounds);
+        if coverage.width == 0 || coverage.height == 0 {
+            return;
+        }
+        for polygon in polygons.iter() {
+            coverage.polygon(polygon);
+        }
+        self.draw_coverage(&coverage, rule, color);
+    }
+
+    pub fn fill_polygon(&mut self, points: &[Vector2], rule: FillRule, color: Color) {
+        let polygons = [points.to_vec()];
+        self.fill_polygons(&polygons, rule, color);
+    }
+
+    /// Wide line through `points` with the joins and caps of `stroke`
+    pub fn draw_stroke(&mut self, points: &[Vector2], stroke: &Stroke, color: Color) {
+        let polygons = stroke_polygons(points, stroke);
+        self.fill_polygons(&polygons, FillRule::NonZero, color);
+    }
+
+    /// Outline of a closed polygon, every corner joined and no caps
+    pub fn stroke_polygon(&mut self, points: &[Vector2], stroke: &Stroke, color: Color) {
+        let polygons = closed_stroke_polygons(points, stroke);
+        self.fill_polygons(&polygons, FillRule::NonZero, color);
+    }
+
+    pub fn fill_circle(&mut self, center: &Vector2, radius: f64, color: Color) {
+        self.fill_ellipse(center, &Vector2::new(radius, radius), color);
+    }
+
+    pub fn stroke_circle(&mut self, center: &Vector2, radius: f64, stroke: &Stroke, color: Color) {
+        self.stroke_ellipse(center, &Vector2::new(radius, radius), stroke, color);
+    }
+
+    pub fn fill_ellipse(&mut self, center: &Vector2, radii: &Vector2, color: Color) {
+        let points = ellipse_points(center, radii);
+        self.fill_polygon(&points, FillRule::NonZero, color);
+    }
+
+    pub fn stroke_ellipse(
+        &mut self,
+        center: &Vector2,
+        radii: &Vector2,
+        stroke: &Stroke,
+        color: Color,
+    ) {
+        let points = ellipse_points(center, radii);
+        self.stroke_polygon(&points, stroke, color);
+    }
+
+    /// Angles in radians from the positive x axis, clockwise on screen
+    pub fn stroke_arc(
+        &mut self,
+        center: &Vector2,
+        radius: f64,
+        start_angle: f64,
+        end_angle: f64,
+        stroke: &Stroke,
+        color: Color,
+    ) {
+        let radii = Vector2::new(radius, radius);
+        let points = arc_points(center, &radii, start_angle, end_angle);
+        self.draw_stroke(&points, stroke, color);
+    }
+
+    /// Filled slice between the arc and the center, see `stroke_arc`
+    pub fn fill_pie(
+        &mut self,
+        center: &Vector2,
+        radius: f64,
+        start_angle: f64,
+        end_angle: f64,
+        color: Color,
+    ) {
+        let radii = Vector2::new(radius, radius);
+        let mut points = arc_points(center, &radii, start_angle, end_angle);
+        points.push(*center);
+        self.fill_polygon(&points, FillRule::NonZero, color);
+    }
+
+    pub fn fill_rounded_rect(
+        &mut self,
+        position: &Vector2,
+        size: &Vector2,
+        radius: f64,
+        color: Color,
+    ) {
+        let points = rounded_rect_points(position, size, radius);
+        self.fill_polygon(&points, FillRule::NonZero, color);
+    }
+
+    pub fn stroke_rounded_rect(
+        &mut self,
+        position: &Vector2,
+        size: &Vector2,
+        radius: f64,
+        stroke: &Stroke,
+        color: Color,
+    ) {
+        let points = rounded_rect_points(position, size, radius);
+        self.stroke_polygon(&points, stroke, color);
+    }
+
+    /// One pixel wide antialiased polyline, blended once where segments meet
+    pub fn draw_polyline_aa(&mut self, points: &[Vector2], color: Color) {
+        self.draw_stroke(points, &Stroke::new(1.0), color);
+    }
+
+    /// Cardinal spline through `points`, see `create_curve`
+    pub fn draw_curve_aa(&mut self, points: &Vec<Vector2>, stroke: &Stroke, color: Color) {
+        if points.len() < 2 {
+            return;
+        }
+        let mut curve = create_curve(points);
+        curve.push(points[points.len() - 1]);
+        self.draw_stroke(&curve, stroke, color);
+    }
+}
@@ -10,6 +10,9 @@ use random::*;
 use audio::*;
 use tools::*;
 use math::*;
+use canvas::*;
+use raster::*;
+use waveview::*;
 
 pub enum Colors {
     Empty = 0x00000000,
@@ -26,10 +29,10 @@ pub struct Color {
 
 #[derive(Clone, Copy)]
 pub struct Rect {
-    x: i32,
-    y: i32,
-    width: i32,
-    height: i32,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
 }
 
 pub struct Image {
@@ -38,44 +41,45 @@ pub struct Image {
     pub color_data: Box<[Color]>,
 }
 
+/// Node of the scene graph. Position, scale, opacity and clip are relative to the
+/// parent, see `Image::draw_scene`.
 pub struct Sprite {
+    pub name: String,
     pub image: Image,
     pub position: Vector2,
+    pub scale: Vector2,
+    pub opacity: f64,
+    pub visible: bool,
+    /// Limits this sprite and its children, in its own unscaled coordinates
+    pub clip: Option<Rect>,
+    /// Repaint by the scene's painter, cleared before the painter runs
     pub need_update: bool,
+    /// Changed parts of `image` since the last frame, in image coordinates
+    pub damage: Vec<Rect2i>,
     pub layer: LayerID,
+    /// Order within the layer, higher is drawn later
+    pub z: i32,
     pub children: Vec<Box<Sprite>>,
 }
 
+/// Aliased lines are cheaper, antialiased ones are blended through their coverage
+#[derive(Clone, Copy, PartialEq)]
+pub enum Quality {
+    Fast,
+    AntiAliased,
+}
+
+/// Layers are drawn in this order wherever their sprites are in the tree
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LayerID {
     Base,
     Background,
     Wave,
+    Response,
     GUI,
     Last,
 }
 
-pub struct BitmapHeader {
-    file_t_ype: u16,
-    file_size: u32,
-    reserved1: u16,
-    reserved2: u16,
-    bitmap_offset: u32,
-    size: u32,
-    width: i32,
-    height: i32,
-    planes: u16,
-    bits_per_pixel: u16,
-    compression: u32,
-    size_of_bitmap: u32,
-    horz_resolution: i32,
-    vert_resolution: i32,
-    colors_used: u32,
-    colors_important: u32,
-    red_mask: u32,
-    green_mask: u32,
-    blue_mask: u32,
-}
-
 //#[inline(always)]
 //fn rgba_from_u32(color: u32) -> (u8, u8, u8, u8) {}
 
@@ -221,7 +225,7 @@ fn create_vectical_gradient(
     }
 }
 
-fn create_curve(points: &Vec<Vector2>) -> Vec<Vector2> {
+pub fn create_curve(points: &Vec<Vector2>) -> Vec<Vector2> {
     let array_len = points.len();
     let mut pts: Vec<Vector2> = Vec::with_capacity(array_len + 2);
     pts.push(points[0]);
@@ -319,26 +323,32 @@ fn create_horisontal_gradient(
     }
 }
 
+/// Blends a point at a sub pixel position into the four pixels around it, weighted
+/// by how close it is to each of them
 fn plot_aa(position: Vector2, width: i32, color: Color, data: &mut [Color]) {
     let (x, y) = (position.x, position.y);
     let floor_x = floor_f64_i32(x);
-    let ceil_x = ceil_f64_i32(x);
-    for rounded_x in floor_x..ceil_x {
-        let floor_y = floor_f64_i32(y);
-        let ceil_y = ceil_f64_i32(y);
-        for rounded_y in floor_y..ceil_y {
+    let floor_y = floor_f64_i32(y);
+    let alpha = color.separate().3 as f64 / 255.0;
+    for rounded_y in floor_y..floor_y + 2 {
+        for rounded_x in floor_x..floor_x + 2 {
+            if rounded_x < 0 || rounded_y < 0 || rounded_x >= width {
+                continue;
+            }
             let percent_x = 1.0 - abs_f64(x - rounded_x as f64);
             let percent_y = 1.0 - abs_f64(y - rounded_y as f64);
-            let percent = percent_x * percent_y;
+            let percent = percent_x * percent_y * alpha;
+            if percent <= 0.0 {
+                continue;
+            }
 
-            let pixel_index = get_index(rounded_x, rounded_y, width);
             let new_color = color_set_alpha_f64(color, percent);
-            data[pixel_index] = new_color;
+            plot_point(rounded_x, rounded_y, width, new_color, data);
         }
     }
 }
 
-fn plot_point(x: i32, y: i32, width: i32, color: Color, data: &mut [Color]) {
+pub fn plot_point(x: i32, y: i32, width: i32, color: Color, data: &mut [Color]) {
     let pixel_index = get_index(x, y, width);
 
     if pixel_index >= data.len() {
@@ -514,10 +524,15 @@ fn plot_line_fast_f64(
 
     let increment_val = if long_len < 0 { -1 } else { 1 };
 
-    let mult_diff = if long_len == 0 {
-        short_len as f64
+    let (short_delta, long_delta) = if y_longer {
+        (x2 - x1, y2 - y1)
     } else {
-        (short_len as f64) / (long_len as f64)
+        (y2 - y1, x2 - x1)
+    };
+    let mult_diff = if long_delta == 0.0 {
+        0.0
+    } else {
+        short_delta / long_delta
     };
 
     if y_longer == true {
@@ -579,16 +594,95 @@ fn plot_square(pos0: &Vector2, pos1: &Vector2, width: i32, color: Color, data: &
     );
 }
 
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn from_bounds(bounds: &Rect2i) -> Rect {
+        Rect::new(bounds.min_x, bounds.min_y, bounds.width(), bounds.height())
+    }
+
+    pub fn bounds(&self) -> Rect2i {
+        Rect2i::new(self.x, self.y, self.x + self.width, self.y + self.height)
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = max_i32(self.x, other.x);
+        let y = max_i32(self.y, other.y);
+        let right = min_i32(self.x + self.width, other.x + other.width);
+        let bottom = min_i32(self.y + self.height, other.y + other.height);
+        Rect::new(x, y, max_i32(0, right - x), max_i32(0, bottom - y))
+    }
+}
+
 impl Sprite {
     pub fn new(position: Vector2, width: i32, height: i32, layer: LayerID) -> Sprite {
+        let image = Image::from_color(width, height, Color::from_u32(Colors::White as u32));
+        Sprite::from_image(image, position, layer)
+    }
+
+    pub fn from_image(image: Image, position: Vector2, layer: LayerID) -> Sprite {
         Sprite {
-            image: Image::from_color(width, height, Color::from_u32(Colors::White as u32)),
+            name: String::new(),
+            image: image,
             position: position,
-            layer: layer,
+            scale: Vector2::new(1.0, 1.0),
+            opacity: 1.0,
+            visible: true,
+            clip: None,
             need_update: true,
+            damage: Vec::new(),
+            layer: layer,
+            z: 0,
             children: Vec::new(),
         }
     }
+
+    pub fn add_child(&mut self, child: Sprite) {
+        self.children.push(Box::new(child));
+    }
+
+    /// Depth first, this sprite included
+    pub fn find(&self, name: &str) -> Option<&Sprite> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children
+            .iter()
+            .filter_map(|child| child.find(name))
+            .next()
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Sprite> {
+        if self.name == name {
+            return Some(self);
+        }
+        for child in self.children.iter_mut() {
+            if let Some(found) = child.find_mut(name) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Reports a changed part of the image without a repaint
+    pub fn mark_damaged(&mut self, area: Rect2i) {
+        self.damage.push(area);
+    }
+
+    /// Marks this sprite and everything under it for repainting
+    pub fn invalidate(&mut self) {
+        self.need_update = true;
+        for child in self.children.iter_mut() {
+            child.invalidate();
+        }
+    }
 }
 
 impl Image {
@@ -612,37 +706,47 @@ impl Image {
         }
     }
 
+    /// See `plot_wave` for the display modes
     pub fn waveform(
         width: i32,
         height: i32,
         wave: &Waveform,
         start: u32,
         range: u32,
-        color: Color,
+        style: &WaveStyle,
     ) -> Image {
-        let center_y = height / 2;
-
-        let color_empty = Color::from_u32(Colors::Empty as u32);
-        let mut data = vec![color_empty; width as usize * height as usize].into_boxed_slice();
-
-        let half_height = height as f64 / 2.0;
-
-        let end = (start + range) as usize;
-
-        //let len = wave.points.len();
-        let mut last_position = Vector2::new(0.0, center_y as f64 + wave.points[0].y * half_height);
-
-        let mut index = 0;
-        for i in start as usize..end {
-            let position = Vector2::new(
-                index as f64 / range as f64 * width as f64,
-                center_y as f64 + wave.points[i].y * half_height,
-            );
-            plot_line(&last_position, &position, width, color, &mut data);
-            last_position = position;
-            index += 1;
+        let mut image = Image::new(width, height);
+        match style.quality {
+            Quality::Fast => plot_wave(&mut image, wave, start, range, style),
+            Quality::AntiAliased => {
+                let mut canvas = SmoothCanvas::new(&mut image, Stroke::new(1.0));
+                plot_wave(&mut canvas, wave, start, range, style);
+            }
         }
-        Image::from_data(width, height, data)
+        image
+    }
+
+    /// Magnitude in dB (+-range_db) and phase in radians (+-PI), one value per column.
+    /// Zero dB sits in the middle of the image.
+    pub fn response_curve(
+        width: i32,
+        height: i32,
+        magnitude_db: &[f64],
+        phase: &[f64],
+        range_db: f64,
+        magnitude_color: Color,
+        phase_color: Color,
+    ) -> Image {
+        let mut image = Image::new(width, height);
+        plot_response_curve(
+            &mut image,
+            magnitude_db,
+            phase,
+            range_db,
+            magnitude_color,
+            phase_color,
+        );
+        image
     }
 
     pub fn from_horisontal_gradient(
@@ -717,38 +821,97 @@ impl Image {
     }
 */
 
+    pub fn draw_point(&mut self, position: &Vector2, color: Color) {
+        let x = truncate_f64_i32(position.x);
+        let y = truncate_f64_i32(position.y);
+        if x >= 0 && x < self.width && y >= 0 && y < self.height {
+            let width = self.width;
+            plot_point(x, y, width, color, &mut self.color_data);
+        }
+    }
+
     pub fn draw_line(&mut self, start: &Vector2, end: &Vector2, color: Color) {
         let width = self.width;
         let data = &mut self.color_data;
         plot_line(start, end, width, color, data);
     }
 
+    /// The sprite's own image with its position, scale, opacity and clip, without children
     pub fn draw_bitmap(&mut self, sprite: &Sprite) {
-        let pos_x = round_f64_i32(sprite.position.x);
-        let pos_y = round_f64_i32(sprite.position.y);
+        let clip = match sprite.clip {
+            Some(clip) => Rect::new(
+                round_f64_i32(sprite.position.x + clip.x as f64 * sprite.scale.x),
+                round_f64_i32(sprite.position.y + clip.y as f64 * sprite.scale.y),
+                round_f64_i32(clip.width as f64 * sprite.scale.x),
+                round_f64_i32(clip.height as f64 * sprite.scale.y),
+            ),
+            None => Rect::new(0, 0, self.width, self.height),
+        };
+        self.draw_image(
+            &sprite.image,
+            &sprite.position,
+            &sprite.scale,
+            sprite.opacity,
+            &clip,
+        );
+    }
 
-        let image = &sprite.image;
+    /// Nearest neighbour scaled, alpha multiplied by `opacity` and limited to `clip`
+    pub fn draw_image(
+        &mut self,
+        image: &Image,
+        position: &Vector2,
+        scale: &Vector2,
+        opacity: f64,
+        clip: &Rect,
+    ) {
+        if !(scale.x > 0.0 && scale.y > 0.0 && opacity > 0.0) {
+            return;
+        }
+        let pos_x = round_f64_i32(position.x);
+        let pos_y = round_f64_i32(position.y);
+        let unscaled = scale.x == 1.0 && scale.y == 1.0;
 
         let source_width = image.width;
         let source_height = image.height;
+        let (width, height) = if unscaled {
+            (source_width, source_height)
+        } else {
+            (
+                round_f64_i32(source_width as f64 * scale.x),
+                round_f64_i32(source_height as f64 * scale.y),
+            )
+        };
 
         let self_width = self.width;
-        let self_height = self.height;
-
-        let start_x = max_i32(0, pos_x);
-        let end_x = min_i32(self_width, pos_x + source_width);
-
-        let start_y = max_i32(0, pos_y);
-        let end_y = min_i32(self_height, pos_y + source_height);
+        let area = Rect::new(pos_x, pos_y, width, height)
+            .intersect(clip)
+            .intersect(&Rect::new(0, 0, self_width, self.height));
+        let opacity = round_f64_u32(min_f64(opacity, 1.0) * 256.0);
 
         let dest_data = &mut self.color_data;
         let source_image_data = &image.color_data;
 
-        for y in start_y..end_y {
-            for x in start_x..end_x {
-                let source_offset = get_index(x - pos_x, y - pos_y, source_width);
-                let color = source_image_data[source_offset];
-                let fg_a = (color.value >> 24) & 0xFF;
+        for y in area.y..area.y + area.height {
+            let source_y = if unscaled {
+                y - pos_y
+            } else {
+                min_i32(((y - pos_y) as f64 / scale.y) as i32, source_height - 1)
+            };
+            for x in area.x..area.x + area.width {
+                let source_x = if unscaled {
+                    x - pos_x
+                } else {
+                    min_i32(((x - pos_x) as f64 / scale.x) as i32, source_width - 1)
+                };
+                let source_offset = get_index(source_x, source_y, source_width);
+                let mut color = source_image_data[source_offset];
+                let mut fg_a = (color.value >> 24) & 0xFF;
+                if opacity < 256 {
+                    fg_a = (fg_a * opacity) >> 8;
+                    let (r, g, b, _) = color.separate();
+                    color = Color::from_rgba(r, g, b, fg_a);
+                }
 
                 if fg_a == 0 {
                     continue;
@@ -772,7 +935,7 @@ impl Image {
         let width = round_f64_i32(size.x); // - x;
         let height = round_f64_i32(size.y); // - y;
 
-        let start_y = max_i32(0, min_i32(self_width, y));
+        let start_y = max_i32(0, min_i32(self_height, y));
         let start_x = max_i32(0, min_i32(self_width, x));
 
         let row_len = max_i32(start_x, min_i32(self_width, x + width)) - start_x;
@@ -801,3 +964,68 @@ impl Image {
         }
     }
 }
+
+/// `range` samples from `start` across the whole canvas, zero in the middle
+pub fn plot_waveform(canvas: &mut Canvas, wave: &Waveform, start: u32, range: u32, color: Color) {
+    let size = canvas.size();
+    let center_y = (size.y as i32 / 2) as f64;
+    let half_height = size.y / 2.0;
+
+    let end = (start + range) as usize;
+
+    let mut points = Vec::with_capacity(range as usize + 1);
+    points.push(Vector2::new(0.0, center_y + wave.points[0].y * half_height));
+    for (index, i) in (start as usize..end).enumerate() {
+        points.push(Vector2::new(
+            index as f64 / range as f64 * size.x,
+            center_y + wave.points[i].y * half_height,
+        ));
+    }
+    canvas.polyline(&points, color);
+}
+
+/// See `Image::response_curve`
+pub fn plot_response_curve(
+    canvas: &mut Canvas,
+    magnitude_db: &[f64],
+    phase: &[f64],
+    range_db: f64,
+    magnitude_color: Color,
+    phase_color: Color,
+) {
+    let size = canvas.size();
+    let center_y = size.y / 2.0;
+    let half_height = size.y / 2.0 - 1.0;
+    let grid_color = color_set_alpha(magnitude_color, 64);
+
+    canvas.line(
+        &Vector2::new(0.0, center_y),
+        &Vector2::new(size.x, center_y),
+        grid_color,
+    );
+
+    let count = magnitude_db.len();
+    if count < 2 {
+        return;
+    }
+    let step = (size.x - 1.0) / (count - 1) as f64;
+    let magnitude_y = |value: f64| center_y - clamp_f64(-1.0, value / range_db, 1.0) * half_height;
+    let phase_y = |value: f64| center_y - clamp_f64(-1.0, value / PI, 1.0) * half_height;
+
+    for i in 1..count {
+        let x0 = (i - 1) as f64 * step;
+        let x1 = i as f64 * step;
+        if i < phase.len() {
+            canvas.line(
+                &Vector2::new(x0, phase_y(phase[i - 1])),
+                &Vector2::new(x1, phase_y(phase[i])),
+                phase_color,
+            );
+        }
+        canvas.line(
+            &Vector2::new(x0, magnitude_y(magnitude_db[i - 1])),
+            &Vector2::new(x1, magnitude_y(magnitude_db[i])),
+            magnitude_color,
+        );
+    }
+}
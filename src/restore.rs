@@ -0,0 +1,388 @@
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+
+use audio::*;
+use filter::hann_window;
+use math::*;
+
+pub struct ClickDetector {
+    /// Order of the autoregressive model
+    pub order: usize,
+    /// Samples per model estimate
+    pub block_size: usize,
+    /// Residual threshold in multiples of the robust residual deviation
+    pub threshold: f64,
+    /// Detections closer than this are merged into one event
+    pub merge_distance: usize,
+    /// Samples added on both sides of each detected event
+    pub padding: usize,
+    /// Longer events are left alone, they are not clicks
+    pub max_length: usize,
+}
+
+pub struct ClipDetector {
+    /// Absolute level treated as clipped, None uses 99.9% of the file peak
+    pub level: Option<f64>,
+    /// Shortest run of samples at the clip level that counts as clipping
+    pub min_run: usize,
+}
+
+pub struct Declipper {
+    pub order: usize,
+    /// Samples of context on each side used for the model estimate
+    pub context: usize,
+    /// Active set iterations for the amplitude constraint
+    pub iterations: usize,
+    /// Longer runs are left clipped, the dense solve grows with the cube of the length
+    pub max_length: usize,
+}
+
+/// Prediction coefficients `a` with x[n] ~ sum(a[k] * x[n - 1 - k]), from the
+/// Hann windowed autocorrelation of `samples` (Levinson-Durbin).
+pub fn estimate_ar(samples: &[f64], order: usize) -> Vec<f64> {
+    let length = samples.len();
+    let mut windowed = Vec::with_capacity(length);
+    for i in 0..length {
+        windowed.push(samples[i] * hann_window(i, length));
+    }
+
+    let mut autocorrelation = vec![0.0; order + 1];
+    for lag in 0..order + 1 {
+        let mut sum = 0.0;
+        for i in lag..length {
+            sum += windowed[i] * windowed[i - lag];
+        }
+        autocorrelation[lag] = sum;
+    }
+    // Slight white noise correction keeps the recursion stable on silent blocks.
+    autocorrelation[0] *= 1.0 + 1.0e-9;
+    autocorrelation[0] += 1.0e-12;
+
+    let mut coefficients = vec![0.0; order];
+    let mut error = autocorrelation[0];
+    for i in 0..order {
+        let mut accumulator = autocorrelation[i + 1];
+        for j in 0..i {
+            accumulator -= coefficients[j] * autocorrelation[i - j];
+        }
+        let reflection = accumulator / error;
+
+        let previous = coefficients.clone();
+        coefficients[i] = reflection;
+        for j in 0..i {
+            coefficients[j] = previous[j] - reflection * previous[i - 1 - j];
+        }
+        error *= 1.0 - reflection * reflection;
+        if error <= 0.0 {
+            break;
+        }
+    }
+    coefficients
+}
+
+/// Prediction error for every sample of `samples[start..end]`, earlier samples are
+/// taken from before `start` when available.
+pub fn ar_residual(samples: &[f64], coefficients: &[f64], start: usize, end: usize) -> Vec<f64> {
+    let mut result = Vec::with_capacity(end - start);
+    for n in start..end {
+        let mut prediction = 0.0;
+        for k in 0..coefficients.len() {
+            if n > k {
+                prediction += coefficients[k] * samples[n - 1 - k];
+            }
+        }
+        result.push(samples[n] - prediction);
+    }
+    result
+}
+
+fn median(values: &mut Vec<f64>) -> f64 {
+    if values.len() == 0 {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    values[values.len() / 2]
+}
+
+/// Least squares AR interpolation. Replaces the samples at `unknown` (sorted, absolute
+/// indices) with the values minimizing the prediction error energy around them.
+pub fn lsar_interpolate(samples: &mut [f64], coefficients: &[f64], unknown: &[usize]) -> bool {
+    if unknown.len() == 0 {
+        return true;
+    }
+    let order = coefficients.len();
+    let first = unknown[0];
+    let last = unknown[unknown.len() - 1];
+
+    let segment_start = if first > order { first - order } else { 0 };
+    let segment_end = min_u32((last + order + 1) as u32, samples.len() as u32) as usize;
+
+    let count = unknown.len();
+    let mut position = vec![usize::max_value(); segment_end - segment_start];
+    for (index, sample) in unknown.iter().enumerate() {
+        position[sample - segment_start] = index;
+    }
+
+    // Prediction error filter b = [1, -a1, ... -ap], every row is e[n] = sum(b[k] * x[n - k]).
+    let mut normal = vec![0.0; count * count];
+    let mut rhs = vec![0.0; count];
+    let mut row_unknown: Vec<(usize, f64)> = Vec::with_capacity(order + 1);
+
+    for n in segment_start + order..segment_end {
+        row_unknown.clear();
+        let mut known = 0.0;
+        for k in 0..order + 1 {
+            let b = if k == 0 { 1.0 } else { -coefficients[k - 1] };
+            let index = n - k;
+            let slot = position[index - segment_start];
+            if slot != usize::max_value() {
+                row_unknown.push((slot, b));
+            } else {
+                known += b * samples[index];
+            }
+        }
+
+        for &(i, bi) in row_unknown.iter() {
+            rhs[i] -= bi * known;
+            for &(j, bj) in row_unknown.iter() {
+                normal[i * count + j] += bi * bj;
+            }
+        }
+    }
+
+    match solve_linear_system(&normal, &rhs) {
+        Some(values) => {
+            for (index, sample) in unknown.iter().enumerate() {
+                samples[*sample] = values[index];
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+fn merge_flags(
+    flags: &[bool],
+    merge_distance: usize,
+    padding: usize,
+    count: usize,
+) -> Vec<SampleRange> {
+    let mut events: Vec<SampleRange> = Vec::new();
+    let mut i = 0;
+    while i < flags.len() {
+        if !flags[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i + 1;
+        let mut gap = 0;
+        let mut j = i + 1;
+        while j < flags.len() && gap <= merge_distance {
+            if flags[j] {
+                end = j + 1;
+                gap = 0;
+            } else {
+                gap += 1;
+            }
+            j += 1;
+        }
+
+        let padded_start = if start > padding { start - padding } else { 0 };
+        let padded_end = min_u32((end + padding) as u32, count as u32) as usize;
+        events.push(SampleRange::new(padded_start, padded_end));
+        i = end;
+    }
+    events
+}
+
+impl ClickDetector {
+    pub fn new() -> ClickDetector {
+        ClickDetector {
+            order: 20,
+            block_size: 2048,
+            threshold: 6.0,
+            merge_distance: 8,
+            padding: 2,
+            max_length: 200,
+        }
+    }
+
+    pub fn detect(&self, wave: &Waveform) -> Vec<SampleRange> {
+        let count = wave.sample_count;
+        let mut flags = vec![false; count];
+
+        let mut start = 0;
+        while start < count {
+            let end = min_u32((start + self.block_size) as u32, count as u32) as usize;
+            let coefficients = estimate_ar(&wave.samples[start..end], self.order);
+            let residual = ar_residual(&wave.samples, &coefficients, start, end);
+
+            // Median absolute deviation is not pulled up by the clicks themselves.
+            let mut magnitudes: Vec<f64> = residual.iter().map(|value| abs_f64(*value)).collect();
+            let sigma = median(&mut magnitudes) / 0.6745;
+            let limit = self.threshold * max_f64(sigma, 1.0e-9);
+
+            for i in 0..residual.len() {
+                // The first samples of the file have no history to predict from.
+                if start + i >= self.order && abs_f64(residual[i]) > limit {
+                    flags[start + i] = true;
+                }
+            }
+            start = end;
+        }
+
+        let mut result = merge_flags(&flags, self.merge_distance, self.padding, count);
+        result.retain(|event| event.len() <= self.max_length);
+        result
+    }
+
+    /// Replaces every event by an LSAR interpolation from the surrounding signal
+    pub fn repair(&self, wave: &Waveform, events: &[SampleRange]) -> Waveform {
+        let mut samples = wave.samples.to_vec();
+        let context = max_u32(self.block_size as u32 / 2, (4 * self.order) as u32) as usize;
+
+        for event in events.iter() {
+            let context_start = if event.start > context {
+                event.start - context
+            } else {
+                0
+            };
+            let context_end = min_u32((event.end + context) as u32, samples.len() as u32) as usize;
+
+            // The model is estimated on the context with the damaged part left out.
+            let mut clean: Vec<f64> = Vec::with_capacity(context_end - context_start);
+            clean.extend_from_slice(&samples[context_start..event.start]);
+            clean.extend_from_slice(&samples[event.end..context_end]);
+            let coefficients = estimate_ar(&clean, self.order);
+
+            let unknown: Vec<usize> = (event.start..event.end).collect();
+            lsar_interpolate(&mut samples, &coefficients, &unknown);
+        }
+
+        Waveform::from_samples(samples, wave.sample_rate)
+    }
+}
+
+impl ClipDetector {
+    pub fn new() -> ClipDetector {
+        ClipDetector {
+            level: None,
+            min_run: 3,
+        }
+    }
+
+    pub fn clip_level(&self, wave: &Waveform) -> f64 {
+        match self.level {
+            Some(level) => level,
+            None => {
+                let mut peak = 0.0;
+                for i in 0..wave.sample_count {
+                    peak = max_f64(peak, abs_f64(wave.samples[i]));
+                }
+                peak * 0.999
+            }
+        }
+    }
+
+    pub fn detect(&self, wave: &Waveform) -> Vec<SampleRange> {
+        let level = self.clip_level(wave);
+        let mut result = Vec::new();
+        if level <= 0.0 {
+            return result;
+        }
+
+        let mut run_start = 0;
+        let mut in_run = false;
+        for i in 0..wave.sample_count + 1 {
+            let clipped = i < wave.sample_count && abs_f64(wave.samples[i]) >= level;
+            if clipped && !in_run {
+                run_start = i;
+                in_run = true;
+            } else if !clipped && in_run {
+                if i - run_start >= self.min_run {
+                    result.push(SampleRange::new(run_start, i));
+                }
+                in_run = false;
+            }
+        }
+        result
+    }
+}
+
+impl Declipper {
+    pub fn new() -> Declipper {
+        Declipper {
+            order: 24,
+            context: 1024,
+            iterations: 8,
+            max_length: 256,
+        }
+    }
+
+    /// Reconstructs every clipped run up to `max_length`, keeping each sample at least as
+    /// loud as the clip level and with the sign it was clipped at.
+    pub fn repair(&self, wave: &Waveform, events: &[SampleRange], level: f64) -> Waveform {
+        let mut samples = wave.samples.to_vec();
+
+        for event in events.iter().filter(|event| event.len() <= self.max_length) {
+            let context_start = if event.start > self.context {
+                event.start - self.context
+            } else {
+                0
+            };
+            let context_end =
+                min_u32((event.end + self.context) as u32, samples.len() as u32) as usize;
+
+            let mut clean: Vec<f64> = Vec::with_capacity(context_end - context_start);
+            for i in context_start..context_end {
+                if abs_f64(samples[i]) < level {
+                    clean.push(samples[i]);
+                }
+            }
+            let coefficients = estimate_ar(&clean, self.order);
+
+            let signs: Vec<f64> = (event.start..event.end)
+                .map(|i| sign_f64(samples[i]))
+                .collect();
+            let mut pinned = vec![false; event.len()];
+
+            // Active set: solve without constraints, pin every sample that came out below
+            // the clip level to the level and solve again for the rest.
+            for _ in 0..self.iterations {
+                let mut unknown = Vec::with_capacity(event.len());
+                for i in 0..event.len() {
+                    if pinned[i] {
+                        samples[event.start + i] = signs[i] * level;
+                    } else {
+                        unknown.push(event.start + i);
+                    }
+                }
+                if unknown.len() == 0 || !lsar_interpolate(&mut samples, &coefficients, &unknown) {
+                    break;
+                }
+
+                let mut violated = false;
+                for i in 0..event.len() {
+                    let value = samples[event.start + i];
+                    if !pinned[i] && value * signs[i] < level {
+                        pinned[i] = true;
+                        violated = true;
+                    }
+                }
+                if !violated {
+                    break;
+                }
+            }
+
+            for i in 0..event.len() {
+                if pinned[i] {
+                    samples[event.start + i] = signs[i] * level;
+                }
+            }
+        }
+
+        Waveform::from_samples(samples, wave.sample_rate)
+    }
+}
@@ -0,0 +1,322 @@
+#![allow(dead_code)]
+
+use audio::*;
+use fft::*;
+use math::*;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OnsetFunction {
+    /// Rectified increase of the compressed magnitude spectrum
+    SpectralFlux,
+    /// Rectified increase of the frequency weighted energy, suited to percussive material
+    HighFrequencyContent,
+}
+
+pub struct OnsetDetector {
+    pub function: OnsetFunction,
+    pub frame_size: usize,
+    pub hop: usize,
+    /// Frames before and after a candidate used for the adaptive threshold
+    pub window: usize,
+    /// Added to the local mean of the normalized detection function
+    pub delta: f64,
+    /// Shortest time between two onsets in seconds
+    pub min_interval: f64,
+}
+
+pub struct TempoEstimator {
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    /// Center of the log-normal tempo preference
+    pub preferred_bpm: f64,
+    /// Multiples of the period summed by each comb filter
+    pub comb_size: usize,
+}
+
+pub struct BeatTracker {
+    /// Penalty on beat intervals that differ from the tempo period
+    pub tightness: f64,
+}
+
+pub struct Rhythm {
+    pub onsets: Vec<usize>,
+    pub tempo: f64,
+    pub beats: Vec<usize>,
+}
+
+/// Normalized onset detection function with one value per hop
+pub struct OnsetStrength {
+    pub values: Vec<f64>,
+    pub hop: usize,
+    pub sample_rate: f64,
+}
+
+impl OnsetDetector {
+    pub fn new() -> OnsetDetector {
+        OnsetDetector {
+            function: OnsetFunction::SpectralFlux,
+            frame_size: 1024,
+            hop: 512,
+            window: 6,
+            delta: 0.07,
+            min_interval: 0.03,
+        }
+    }
+
+    pub fn strength(&self, wave: &Waveform) -> OnsetStrength {
+        let frames = stft(&wave.samples, self.frame_size, self.hop);
+        let bins = self.frame_size / 2 + 1;
+
+        let mut values = Vec::with_capacity(frames.len());
+        let mut previous = vec![0.0; bins];
+        let mut previous_hfc = 0.0;
+        for (index, spectrum) in frames.iter().enumerate() {
+            let value = match self.function {
+                OnsetFunction::SpectralFlux => {
+                    let mut flux = 0.0;
+                    for k in 0..bins {
+                        let magnitude = (1.0 + 100.0 * spectrum[k].norm()).ln();
+                        if index > 0 {
+                            flux += max_f64(0.0, magnitude - previous[k]);
+                        }
+                        previous[k] = magnitude;
+                    }
+                    flux
+                }
+                OnsetFunction::HighFrequencyContent => {
+                    let mut hfc = 0.0;
+                    for k in 0..bins {
+                        hfc += k as f64 * spectrum[k].norm_sq();
+                    }
+                    let hfc = (1.0 + hfc).ln();
+                    let rise = if index > 0 {
+                        max_f64(0.0, hfc - previous_hfc)
+                    } else {
+                        0.0
+                    };
+                    previous_hfc = hfc;
+                    rise
+                }
+            };
+            values.push(value);
+        }
+
+        let largest = values.iter().cloned().fold(0.0, max_f64);
+        if largest > 0.0 {
+            for value in values.iter_mut() {
+                *value /= largest;
+            }
+        }
+
+        OnsetStrength {
+            values: values,
+            hop: self.hop,
+            sample_rate: wave.sample_rate,
+        }
+    }
+
+    /// Frames that are the maximum of their neighbourhood and rise above its mean by `delta`
+    pub fn pick_peaks(&self, strength: &OnsetStrength) -> Vec<usize> {
+        let values = &strength.values;
+        let count = values.len();
+        let min_distance = max_u32(
+            1,
+            round_f64_u32(self.min_interval * strength.sample_rate / strength.hop as f64),
+        ) as usize;
+
+        let mut result: Vec<usize> = Vec::new();
+        for i in 0..count {
+            let start = if i > self.window { i - self.window } else { 0 };
+            let end = min_u32((i + self.window + 1) as u32, count as u32) as usize;
+
+            let mut local_max = 0.0;
+            let mut sum = 0.0;
+            for j in start..end {
+                local_max = max_f64(local_max, values[j]);
+                sum += values[j];
+            }
+            let mean = sum / (end - start) as f64;
+
+            if values[i] < local_max || values[i] < mean + self.delta {
+                continue;
+            }
+            match result.last() {
+                Some(&last) if i - last < min_distance => {}
+                _ => result.push(i),
+            }
+        }
+        result
+    }
+
+    /// Onset positions in samples
+    pub fn detect(&self, wave: &Waveform) -> Vec<usize> {
+        let strength = self.strength(wave);
+        self.pick_peaks(&strength)
+            .iter()
+            .map(|frame| frame * self.hop)
+            .collect()
+    }
+}
+
+impl TempoEstimator {
+    pub fn new() -> TempoEstimator {
+        TempoEstimator {
+            min_bpm: 60.0,
+            max_bpm: 200.0,
+            preferred_bpm: 120.0,
+            comb_size: 4,
+        }
+    }
+
+    /// Autocorrelation of the mean removed detection function up to `max_lag` frames
+    fn autocorrelation(values: &[f64], max_lag: usize) -> Vec<f64> {
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / max_u32(count as u32, 1) as f64;
+        let centered: Vec<f64> = values.iter().map(|value| value - mean).collect();
+
+        let mut result = vec![0.0; max_lag + 1];
+        for lag in 0..min_u32((max_lag + 1) as u32, count as u32) as usize {
+            let mut sum = 0.0;
+            for i in lag..count {
+                sum += centered[i] * centered[i - lag];
+            }
+            // Unbiased so longer lags are not penalized by the shrinking overlap.
+            result[lag] = sum / (count - lag) as f64;
+        }
+        result
+    }
+
+    /// Tempo in beats per minute. Each candidate period is scored by a comb over the
+    /// autocorrelation at its multiples, weighted toward `preferred_bpm`.
+    pub fn estimate(&self, strength: &OnsetStrength) -> f64 {
+        let frames_per_minute = 60.0 * strength.sample_rate / strength.hop as f64;
+        let min_lag = max_u32(1, (frames_per_minute / self.max_bpm) as u32) as usize;
+        let max_lag = max_u32(
+            (min_lag + 1) as u32,
+            (frames_per_minute / self.min_bpm) as u32 + 1,
+        ) as usize;
+
+        let acf = TempoEstimator::autocorrelation(&strength.values, max_lag * self.comb_size);
+        let score = |lag: usize| {
+            let mut sum = 0.0;
+            for multiple in 1..self.comb_size + 1 {
+                // Peaks at higher multiples land between frames, the tooth widens with them.
+                let center = lag * multiple;
+                let spread = multiple / 2;
+                let mut peak = f64::NEG_INFINITY;
+                for index in center - spread..center + spread + 1 {
+                    if index < acf.len() {
+                        peak = max_f64(peak, acf[index]);
+                    }
+                }
+                if peak > f64::NEG_INFINITY {
+                    sum += peak / multiple as f64;
+                }
+            }
+            let octaves = (frames_per_minute / lag as f64 / self.preferred_bpm).log2();
+            sum * (-0.5 * octaves * octaves).exp()
+        };
+
+        let scores: Vec<f64> = (0..max_lag + 2)
+            .map(|lag| if lag >= min_lag { score(lag) } else { 0.0 })
+            .collect();
+
+        let mut best = min_lag;
+        for lag in min_lag..max_lag + 1 {
+            if scores[lag] > scores[best] {
+                best = lag;
+            }
+        }
+
+        let mut period = best as f64;
+        if best > min_lag && best < max_lag {
+            let (a, b, c) = (scores[best - 1], scores[best], scores[best + 1]);
+            let denominator = a - 2.0 * b + c;
+            if abs_f64(denominator) > 1.0e-12 {
+                period += 0.5 * (a - c) / denominator;
+            }
+        }
+        frames_per_minute / period
+    }
+}
+
+impl BeatTracker {
+    pub fn new() -> BeatTracker {
+        BeatTracker { tightness: 100.0 }
+    }
+
+    /// Beat positions in samples. Dynamic programming over the detection function where
+    /// every frame links back to the best previous beat between half and twice the period.
+    pub fn track(&self, strength: &OnsetStrength, bpm: f64) -> Vec<usize> {
+        let values = &strength.values;
+        let count = values.len();
+        let period = 60.0 * strength.sample_rate / strength.hop as f64 / bpm;
+        if count == 0 || period < 1.0 {
+            return Vec::new();
+        }
+
+        let mut score = vec![0.0; count];
+        let mut backlink: Vec<Option<usize>> = vec![None; count];
+        for t in 0..count {
+            let low = t as f64 - 2.0 * period;
+            let high = t as f64 - 0.5 * period;
+
+            let mut best = 0.0;
+            if high >= 0.0 {
+                let start = max_f64(0.0, round_f64(low)) as usize;
+                let end = round_f64(high) as usize;
+                for previous in start..end + 1 {
+                    let ratio = (t - previous) as f64 / period;
+                    let penalty = self.tightness * ratio.ln() * ratio.ln();
+                    let candidate = score[previous] - penalty;
+                    if backlink[t].is_none() || candidate > best {
+                        best = candidate;
+                        backlink[t] = Some(previous);
+                    }
+                }
+            }
+            score[t] = values[t] + max_f64(best, 0.0);
+            if best <= 0.0 {
+                backlink[t] = None;
+            }
+        }
+
+        // The last beat is the best scoring frame within one period of the end.
+        let tail = max_f64(0.0, count as f64 - period) as usize;
+        let mut last = tail;
+        for t in tail..count {
+            if score[t] > score[last] {
+                last = t;
+            }
+        }
+
+        let mut beats = vec![last];
+        let mut current = last;
+        while let Some(previous) = backlink[current] {
+            beats.push(previous);
+            current = previous;
+        }
+        beats.reverse();
+        beats.iter().map(|frame| frame * strength.hop).collect()
+    }
+}
+
+impl Rhythm {
+    pub fn analyze(wave: &Waveform) -> Rhythm {
+        let detector = OnsetDetector::new();
+        let strength = detector.strength(wave);
+        let onsets = detector
+            .pick_peaks(&strength)
+            .iter()
+            .map(|frame| frame * detector.hop)
+            .collect();
+        let tempo = TempoEstimator::new().estimate(&strength);
+        let beats = BeatTracker::new().track(&strength, tempo);
+
+        Rhythm {
+            onsets: onsets,
+            tempo: tempo,
+            beats: beats,
+        }
+    }
+}
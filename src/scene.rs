@@ -0,0 +1,253 @@
+#![allow(dead_code)]
+
+use math::*;
+use render::*;
+
+/// Repaints a sprite from the state `T` for a target of the given size. It may set
+/// `need_update` again to be called the next frame too.
+pub type Painter<T> = fn(&T, &mut Sprite, &Vector2);
+
+/// Sprite tree with the painters that fill its named sprites
+pub struct Scene<T> {
+    pub root: Sprite,
+    painters: Vec<(String, Painter<T>)>,
+}
+
+/// Position, scale, opacity and clip of a sprite resolved to target coordinates
+#[derive(Clone, Copy)]
+struct Transform {
+    position: Vector2,
+    scale: Vector2,
+    opacity: f64,
+    clip: Rect,
+}
+
+struct Placement<'a> {
+    sprite: &'a Sprite,
+    transform: Transform,
+}
+
+impl<T> Scene<T> {
+    pub fn new(root: Sprite) -> Scene<T> {
+        Scene {
+            root: root,
+            painters: Vec::new(),
+        }
+    }
+
+    /// Adds `sprite` under the sprite called `parent`, or under the root when there is none
+    pub fn add(&mut self, parent: &str, sprite: Sprite, painter: Option<Painter<T>>) {
+        if let Some(painter) = painter {
+            self.painters.push((sprite.name.clone(), painter));
+        }
+        match self.root.find_mut(parent) {
+            Some(parent) => parent.add_child(sprite),
+            None => self.root.add_child(sprite),
+        }
+    }
+
+    /// Runs the painters of the sprites that need an update and returns the parts of a
+    /// target of `size` that changed, the old and new bounds of every repainted sprite
+    /// and its children plus the damage the sprites reported.
+    pub fn update(&mut self, state: &T, size: &Vector2) -> Vec<Rect2i> {
+        let target = Transform::target(&Rect::new(0, 0, size.x as i32, size.y as i32));
+        let mut damage = Vec::new();
+        update_sprite(
+            &mut self.root,
+            &target,
+            state,
+            size,
+            &self.painters,
+            &mut damage,
+        );
+        damage
+    }
+
+    pub fn invalidate(&mut self) {
+        self.root.invalidate();
+    }
+
+    /// Repaints only the sprite called `name` and its children on the next update
+    pub fn invalidate_node(&mut self, name: &str) {
+        if let Some(sprite) = self.root.find_mut(name) {
+            sprite.invalidate();
+        }
+    }
+
+    pub fn render(&self, target: &mut Image) {
+        target.draw_scene(&self.root);
+    }
+
+    /// Composites only `regions` of the target, everything else stays as it is
+    pub fn render_regions(&self, target: &mut Image, regions: &[Rect2i]) {
+        for region in regions.iter() {
+            target.draw_scene_region(&self.root, region);
+        }
+    }
+}
+
+impl Transform {
+    fn target(clip: &Rect) -> Transform {
+        Transform {
+            position: Vector2::ORIGIN,
+            scale: Vector2::new(1.0, 1.0),
+            opacity: 1.0,
+            clip: *clip,
+        }
+    }
+
+    /// The transform of `sprite` with this one as its parent
+    fn child(&self, sprite: &Sprite) -> Transform {
+        let position = Vector2::new(
+            self.position.x + sprite.position.x * self.scale.x,
+            self.position.y + sprite.position.y * self.scale.y,
+        );
+        let scale = Vector2::new(self.scale.x * sprite.scale.x, self.scale.y * sprite.scale.y);
+        let mut transform = Transform {
+            position: position,
+            scale: scale,
+            opacity: self.opacity * sprite.opacity,
+            clip: self.clip,
+        };
+        if let Some(clip) = sprite.clip {
+            transform.clip = self.clip.intersect(&transform.rect(&clip));
+        }
+        transform
+    }
+
+    fn rect(&self, local: &Rect) -> Rect {
+        Rect::new(
+            round_f64_i32(self.position.x + local.x as f64 * self.scale.x),
+            round_f64_i32(self.position.y + local.y as f64 * self.scale.y),
+            round_f64_i32(local.width as f64 * self.scale.x),
+            round_f64_i32(local.height as f64 * self.scale.y),
+        )
+    }
+
+    /// Where `area` of the sprite's image ends up, clipped
+    fn damage(&self, area: &Rect2i) -> Rect2i {
+        let rect = self.rect(&Rect::from_bounds(area));
+        // One pixel more to cover the rounding of the scaled image edges
+        let grow = if self.scale.x == 1.0 && self.scale.y == 1.0 {
+            0
+        } else {
+            1
+        };
+        rect.bounds().grow(grow).intersect(&self.clip.bounds())
+    }
+}
+
+fn place<'a>(sprite: &'a Sprite, parent: &Transform, placements: &mut Vec<Placement<'a>>) {
+    if !sprite.visible {
+        return;
+    }
+    let transform = parent.child(sprite);
+    placements.push(Placement {
+        sprite: sprite,
+        transform: transform,
+    });
+    for child in sprite.children.iter() {
+        place(child, &transform, placements);
+    }
+}
+
+/// Target area the visible part of `sprite` and its children cover
+fn covered_area(sprite: &Sprite, parent: &Transform) -> Rect2i {
+    let mut placements = Vec::new();
+    place(sprite, parent, &mut placements);
+    placements
+        .iter()
+        .fold(Rect2i::new(0, 0, 0, 0), |area, placement| {
+            let image = &placement.sprite.image;
+            let bounds = Rect2i::new(0, 0, image.width, image.height);
+            area.union(&placement.transform.damage(&bounds))
+        })
+}
+
+fn update_sprite<T>(
+    sprite: &mut Sprite,
+    parent: &Transform,
+    state: &T,
+    size: &Vector2,
+    painters: &[(String, Painter<T>)],
+    damage: &mut Vec<Rect2i>,
+) {
+    if sprite.need_update {
+        damage.push(covered_area(sprite, parent));
+        sprite.need_update = false;
+        let painter = painters.iter().find(|painter| painter.0 == sprite.name);
+        if let Some(&(_, painter)) = painter {
+            painter(state, sprite, size);
+        }
+        damage.push(covered_area(sprite, parent));
+    }
+
+    let transform = parent.child(sprite);
+    if sprite.visible {
+        for area in sprite.damage.iter() {
+            damage.push(transform.damage(area));
+        }
+    }
+    sprite.damage.clear();
+
+    for child in sprite.children.iter_mut() {
+        update_sprite(child, &transform, state, size, painters, damage);
+    }
+}
+
+/// Merges overlapping rectangles and drops empty ones. Past `limit` rectangles, or when they
+/// cover most of their bounds, only the bounds are left.
+pub fn simplify_damage(damage: &[Rect2i], limit: usize) -> Vec<Rect2i> {
+    let mut merged: Vec<Rect2i> = Vec::new();
+    for rect in damage.iter().filter(|rect| !rect.is_empty()) {
+        let mut rect = *rect;
+        // A grown rectangle can overlap ones that were apart before
+        while let Some(index) = merged.iter().position(|other| other.overlaps(&rect)) {
+            rect = rect.union(&merged.swap_remove(index));
+        }
+        merged.push(rect);
+    }
+
+    let bounds = merged
+        .iter()
+        .fold(Rect2i::new(0, 0, 0, 0), |bounds, rect| bounds.union(rect));
+    let covered: i64 = merged.iter().map(|rect| rect.area()).sum();
+    if merged.len() > limit || covered * 4 > bounds.area() * 3 {
+        merged.clear();
+        if !bounds.is_empty() {
+            merged.push(bounds);
+        }
+    }
+    merged
+}
+
+impl Image {
+    /// Composites `root` and its visible descendants, by layer and then z. Sprites with
+    /// the same layer and z keep the tree order, parents before their children.
+    pub fn draw_scene(&mut self, root: &Sprite) {
+        let everything = Rect2i::new(0, 0, self.width, self.height);
+        self.draw_scene_region(root, &everything);
+    }
+
+    /// `draw_scene` limited to `region`
+    pub fn draw_scene_region(&mut self, root: &Sprite, region: &Rect2i) {
+        let clip = Rect::new(0, 0, self.width, self.height).intersect(&Rect::from_bounds(region));
+        if clip.width == 0 || clip.height == 0 {
+            return;
+        }
+        let mut placements = Vec::new();
+        place(root, &Transform::target(&clip), &mut placements);
+        placements.sort_by_key(|placement| (placement.sprite.layer, placement.sprite.z));
+
+        for placement in placements.iter() {
+            let transform = &placement.transform;
+            self.draw_image(
+                &placement.sprite.image,
+                &transform.position,
+                &transform.scale,
+                transform.opacity,
+                &transform.clip,
+            );
+        }
+    }
+}
@@ -0,0 +1,184 @@
+#![allow(dead_code)]
+
+use std::io;
+
+use audio::*;
+use math::*;
+use tools::*;
+
+pub struct SilenceDetector {
+    /// RMS level in dBFS below which a window counts as silent
+    pub threshold_db: f64,
+    /// Shorter quiet stretches are pauses, not silence, in seconds
+    pub min_duration: f64,
+    /// Time the signal stays active after dropping below the threshold, keeps decay tails
+    pub hangover: f64,
+    /// Length of the RMS measurement windows in seconds
+    pub window: f64,
+}
+
+impl SilenceDetector {
+    pub fn new() -> SilenceDetector {
+        SilenceDetector {
+            threshold_db: -50.0,
+            min_duration: 0.25,
+            hangover: 0.05,
+            window: 0.01,
+        }
+    }
+
+    /// Silent ranges in order, the first and last may touch the ends of the file
+    pub fn detect(&self, wave: &Waveform) -> Vec<SampleRange> {
+        let count = wave.sample_count;
+        let window = max_u32(1, (self.window * wave.sample_rate) as u32) as usize;
+        let hangover = (self.hangover * wave.sample_rate) as usize;
+        let min_length = (self.min_duration * wave.sample_rate) as usize;
+        let threshold = db_to_volume(self.threshold_db);
+
+        // Nothing rings into silence at the very start of the file.
+        let close = |run: usize, run_end: usize, result: &mut Vec<SampleRange>| {
+            let silence_start = if run == 0 { 0 } else { run + hangover };
+            if run_end > silence_start && run_end - silence_start >= min_length {
+                result.push(SampleRange::new(silence_start, run_end));
+            }
+        };
+
+        let mut result = Vec::new();
+        let mut run_start = None;
+        let mut start = 0;
+        while start < count {
+            let end = min_u32((start + window) as u32, count as u32) as usize;
+            let silent = rms(&wave.samples[start..end]) < threshold;
+
+            match run_start {
+                None if silent => run_start = Some(start),
+                Some(run) if !silent => {
+                    close(run, start, &mut result);
+                    run_start = None;
+                }
+                _ => {}
+            }
+            start += window;
+        }
+        // A run still open at the end is silence up to the end of the file
+        if let Some(run) = run_start {
+            close(run, count, &mut result);
+        }
+        result
+    }
+
+    /// Range left after removing leading and trailing silence
+    pub fn trim_range(&self, wave: &Waveform) -> SampleRange {
+        let silences = self.detect(wave);
+        let mut range = SampleRange::new(0, wave.sample_count);
+        if let Some(first) = silences.first() {
+            if first.start == 0 {
+                range.start = first.end;
+            }
+        }
+        if let Some(last) = silences.last() {
+            if last.end == wave.sample_count && last.start >= range.start {
+                range.end = last.start;
+            }
+        }
+        if range.end < range.start {
+            range.end = range.start;
+        }
+        range
+    }
+
+    pub fn trim(&self, wave: &Waveform) -> Waveform {
+        extract(wave, self.trim_range(wave))
+    }
+
+    /// Non-silent regions between the detected silences
+    pub fn regions(&self, wave: &Waveform) -> Vec<SampleRange> {
+        let mut result = Vec::new();
+        let mut start = 0;
+        for silence in self.detect(wave).iter() {
+            if silence.start > start {
+                result.push(SampleRange::new(start, silence.start));
+            }
+            start = silence.end;
+        }
+        if wave.sample_count > start {
+            result.push(SampleRange::new(start, wave.sample_count));
+        }
+        result
+    }
+
+    pub fn split(&self, wave: &Waveform) -> Vec<Waveform> {
+        self.regions(wave)
+            .iter()
+            .map(|region| extract(wave, *region))
+            .collect()
+    }
+}
+
+/// Copy of the samples in `range`
+pub fn extract(wave: &Waveform, range: SampleRange) -> Waveform {
+    let end = min_u32(range.end as u32, wave.sample_count as u32) as usize;
+    let start = min_u32(range.start as u32, end as u32) as usize;
+    Waveform::from_samples(wave.samples[start..end].to_vec(), wave.sample_rate)
+}
+
+/// Writes every region as `<prefix>_001.wav`, `<prefix>_002.wav`, ... and returns the paths
+pub fn export_regions(
+    wave: &Waveform,
+    regions: &[SampleRange],
+    prefix: &str,
+) -> io::Result<Vec<String>> {
+    let mut paths = Vec::with_capacity(regions.len());
+    for (index, region) in regions.iter().enumerate() {
+        let path = format!("{0}_{1:03}.wav", prefix, index + 1);
+        extract(wave, *region).write_wav(&path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One second of tone between silences, 44100 Hz gives 441 sample windows so the
+    /// lengths below are not multiples of the window.
+    fn tone_between_silences(before: usize, after: usize) -> Waveform {
+        let mut samples = vec![0.0; before];
+        samples.extend((0..44100).map(|i| 0.5 * f64::sin(i as f64 * 0.05)));
+        samples.extend(vec![0.0; after]);
+        Waveform::from_samples(samples, 44100.0)
+    }
+
+    #[test]
+    fn trailing_silence_is_closed_at_the_end() {
+        let wave = tone_between_silences(22050, 30000);
+        assert!(wave.sample_count % 441 != 0);
+        let silences = SilenceDetector::new().detect(&wave);
+        assert_eq!(silences.len(), 2);
+        assert_eq!(silences[0].start, 0);
+        assert_eq!(silences[1].end, wave.sample_count);
+    }
+
+    #[test]
+    fn trim_removes_both_ends() {
+        let wave = tone_between_silences(22050, 30000);
+        let range = SilenceDetector::new().trim_range(&wave);
+        assert!(range.start > 21000 && range.start <= 22050);
+        assert!(range.end >= 22050 + 44100 && range.end < 22050 + 44100 + 3000);
+    }
+
+    #[test]
+    fn regions_end_before_trailing_silence() {
+        let wave = tone_between_silences(22050, 30000);
+        let regions = SilenceDetector::new().regions(&wave);
+        assert_eq!(regions.len(), 1);
+        assert!(regions[0].end < wave.sample_count);
+    }
+
+    #[test]
+    fn short_trailing_pause_is_not_silence() {
+        let wave = tone_between_silences(0, 5000);
+        assert!(SilenceDetector::new().detect(&wave).is_empty());
+    }
+}
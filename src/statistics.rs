@@ -0,0 +1,216 @@
+#![allow(dead_code)]
+
+use std::fmt;
+
+use audio::*;
+use font::*;
+use math::*;
+use render::*;
+use tools::*;
+
+pub const HISTOGRAM_BINS: usize = 64;
+pub const CLIP_THRESHOLD: f64 = 0.999;
+
+pub struct WaveformStatistics {
+    pub range: SampleRange,
+    pub sample_rate: f64,
+    pub min: f64,
+    pub max: f64,
+    pub peak: f64,
+    pub rms: f64,
+    pub crest_factor: f64,
+    pub dc_offset: f64,
+    pub clipped_samples: usize,
+    /// Crossings per second
+    pub zero_crossing_rate: f64,
+    /// Sample counts over [-1.0,1.0] in `HISTOGRAM_BINS` equal bins
+    pub histogram: Vec<usize>,
+}
+
+impl WaveformStatistics {
+    pub fn analyze(wave: &Waveform, range: SampleRange) -> WaveformStatistics {
+        let end = min_u32(range.end as u32, wave.sample_count as u32) as usize;
+        let start = min_u32(range.start as u32, end as u32) as usize;
+        let count = end - start;
+
+        let mut min = 0.0;
+        let mut max = 0.0;
+        let mut sum = 0.0;
+        let mut sum_sq = 0.0;
+        let mut clipped = 0;
+        let mut crossings = 0;
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+
+        for i in start..end {
+            let value = wave.samples[i];
+            if i == start || value < min {
+                min = value;
+            }
+            if i == start || value > max {
+                max = value;
+            }
+            sum += value;
+            sum_sq += value * value;
+
+            if abs_f64(value) >= CLIP_THRESHOLD {
+                clipped += 1;
+            }
+            if i > start && (value >= 0.0) != (wave.samples[i - 1] >= 0.0) {
+                crossings += 1;
+            }
+
+            let bin =
+                floor_f64_i32((clamp_f64(-1.0, value, 1.0) + 1.0) * 0.5 * HISTOGRAM_BINS as f64);
+            histogram[min_i32(bin, HISTOGRAM_BINS as i32 - 1) as usize] += 1;
+        }
+
+        let peak = max_f64(abs_f64(min), abs_f64(max));
+        let rms = if count > 0 {
+            square_root(sum_sq / count as f64)
+        } else {
+            0.0
+        };
+        let duration = count as f64 / wave.sample_rate;
+
+        WaveformStatistics {
+            range: SampleRange::new(start, end),
+            sample_rate: wave.sample_rate,
+            min: min,
+            max: max,
+            peak: peak,
+            rms: rms,
+            crest_factor: if rms > 0.0 { peak / rms } else { 0.0 },
+            dc_offset: if count > 0 { sum / count as f64 } else { 0.0 },
+            clipped_samples: clipped,
+            zero_crossing_rate: if duration > 0.0 {
+                crossings as f64 / duration
+            } else {
+                0.0
+            },
+            histogram: histogram,
+        }
+    }
+
+    pub fn analyze_all(wave: &Waveform) -> WaveformStatistics {
+        WaveformStatistics::analyze(wave, SampleRange::new(0, wave.sample_count))
+    }
+
+    /// Statistics of one channel of a multichannel recording
+    pub fn analyze_channel(
+        channels: &[&Waveform],
+        channel: usize,
+        range: SampleRange,
+    ) -> Option<WaveformStatistics> {
+        if channel < channels.len() {
+            Some(WaveformStatistics::analyze(channels[channel], range))
+        } else {
+            None
+        }
+    }
+
+    pub fn peak_db(&self) -> f64 {
+        to_db(self.peak)
+    }
+
+    pub fn rms_db(&self) -> f64 {
+        to_db(self.rms)
+    }
+
+    pub fn crest_factor_db(&self) -> f64 {
+        to_db(self.crest_factor)
+    }
+}
+
+fn to_db(value: f64) -> f64 {
+    if value > 0.0 {
+        volume_to_db(value)
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+impl fmt::Display for WaveformStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let duration = self.range.len() as f64 / self.sample_rate;
+        writeln!(
+            f,
+            "Samples {0}..{1} ({2:0.3} s)",
+            self.range.start, self.range.end, duration
+        )?;
+        writeln!(
+            f,
+            "Peak          {0:0.2} dBFS ({1:0.5})",
+            self.peak_db(),
+            self.peak
+        )?;
+        writeln!(
+            f,
+            "RMS           {0:0.2} dBFS ({1:0.5})",
+            self.rms_db(),
+            self.rms
+        )?;
+        writeln!(
+            f,
+            "Crest factor  {0:0.2} dB ({1:0.3})",
+            self.crest_factor_db(),
+            self.crest_factor
+        )?;
+        writeln!(f, "DC offset     {0:0.6}", self.dc_offset)?;
+        writeln!(f, "Min / Max     {0:0.5} / {1:0.5}", self.min, self.max)?;
+        writeln!(f, "Clipped       {0}", self.clipped_samples)?;
+        write!(f, "Zero crossing {0:0.1} /s", self.zero_crossing_rate)
+    }
+}
+
+impl Image {
+    /// Histogram bars with min/max (solid) and RMS (faded) level lines, amplitudes
+    /// land on the same rows as in `Image::waveform`. The summary text is drawn in the
+    /// top left corner.
+    pub fn statistics_overlay(
+        width: i32,
+        height: i32,
+        stats: &WaveformStatistics,
+        color: Color,
+    ) -> Image {
+        let mut image = Image::new(width, height);
+        let (r, g, b, _) = color.separate();
+        let bar_color = Color::from_rgba(r, g, b, 96);
+        let rms_color = Color::from_rgba(r, g, b, 160);
+
+        let largest = stats.histogram.iter().cloned().max().unwrap_or(0);
+        if largest > 0 {
+            let bin_height = height as f64 / HISTOGRAM_BINS as f64;
+            for (bin, count) in stats.histogram.iter().enumerate() {
+                let bar_width = *count as f64 / largest as f64 * width as f64;
+                let y = bin as f64 * bin_height;
+                image.draw_rect(
+                    &Vector2::new(0.0, y),
+                    &Vector2::new(bar_width, max_f64(1.0, bin_height - 1.0)),
+                    bar_color,
+                );
+            }
+        }
+
+        let center_y = height as f64 / 2.0;
+        let half_height = height as f64 / 2.0;
+        let levels = [
+            (stats.max, color),
+            (stats.min, color),
+            (stats.rms, rms_color),
+            (-stats.rms, rms_color),
+        ];
+        for &(level, line_color) in levels.iter() {
+            let y = center_y + clamp_f64(-1.0, level, 1.0) * (half_height - 1.0);
+            image.draw_line(
+                &Vector2::new(0.0, y),
+                &Vector2::new(width as f64, y),
+                line_color,
+            );
+        }
+
+        let summary = format!("{0}", stats);
+        image.draw_text(&Font::builtin(), &summary, &Vector2::new(4.0, 4.0), color);
+
+        image
+    }
+}
@@ -0,0 +1,253 @@
+#![allow(dead_code)]
+
+use audio::*;
+use math::*;
+use render::*;
+
+/// Running phase correlation, +1 mono, 0 uncorrelated, -1 out of phase
+pub struct CorrelationMeter {
+    sample_rate: f64,
+    /// Averaging time constant in seconds, see `set_integration`
+    integration: f64,
+    coefficient: f64,
+    left_right: f64,
+    left_left: f64,
+    right_right: f64,
+}
+
+pub struct StereoStatistics {
+    pub range: SampleRange,
+    pub correlation: f64,
+    /// Side share of the total energy, 0 mono, 0.5 uncorrelated, 1 out of phase
+    pub width: f64,
+    /// Energy balance, -1 left only, 1 right only
+    pub balance: f64,
+    pub left_rms: f64,
+    pub right_rms: f64,
+    pub mid_rms: f64,
+    pub side_rms: f64,
+}
+
+/// Mid/side Lissajous display, mono is vertical and left only leans to the upper left.
+/// Every frame fades what was drawn before so traces decay like phosphor.
+pub struct Vectorscope {
+    pub image: Image,
+    pub color: Color,
+    pub background: Color,
+    /// Alpha of the background blended over the image every frame, higher fades faster
+    pub decay: u32,
+    /// Alpha of every plotted sample, overlapping samples build up brightness
+    pub intensity: u32,
+    pub gain: f64,
+}
+
+/// Correlation of the two channels without mean removal, as phase meters show it
+pub fn phase_correlation(left: &[f64], right: &[f64]) -> f64 {
+    let count = min_u32(left.len() as u32, right.len() as u32) as usize;
+    let mut left_right = 0.0;
+    let mut left_left = 0.0;
+    let mut right_right = 0.0;
+    for i in 0..count {
+        left_right += left[i] * right[i];
+        left_left += left[i] * left[i];
+        right_right += right[i] * right[i];
+    }
+    if left_left > 0.0 && right_right > 0.0 {
+        left_right / (left_left * right_right).sqrt()
+    } else {
+        0.0
+    }
+}
+
+impl CorrelationMeter {
+    pub fn new(sample_rate: f64, integration: f64) -> CorrelationMeter {
+        let mut meter = CorrelationMeter {
+            sample_rate: sample_rate,
+            integration: 0.0,
+            coefficient: 0.0,
+            left_right: 0.0,
+            left_left: 0.0,
+            right_right: 0.0,
+        };
+        meter.set_integration(integration);
+        meter
+    }
+
+    pub fn integration(&self) -> f64 {
+        self.integration
+    }
+
+    pub fn set_integration(&mut self, integration: f64) {
+        self.integration = integration;
+        self.coefficient = 1.0 - (-1.0 / (integration * self.sample_rate)).exp();
+    }
+
+    pub fn reset(&mut self) {
+        self.left_right = 0.0;
+        self.left_left = 0.0;
+        self.right_right = 0.0;
+    }
+
+    #[inline(always)]
+    pub fn push(&mut self, left: f64, right: f64) {
+        let c = self.coefficient;
+        self.left_right += c * (left * right - self.left_right);
+        self.left_left += c * (left * left - self.left_left);
+        self.right_right += c * (right * right - self.right_right);
+    }
+
+    pub fn value(&self) -> f64 {
+        let energy = self.left_left * self.right_right;
+        if energy > 1.0e-20 {
+            clamp_f64(-1.0, self.left_right / energy.sqrt(), 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl StereoStatistics {
+    pub fn analyze(left: &Waveform, right: &Waveform, range: SampleRange) -> StereoStatistics {
+        let count = min_u32(left.sample_count as u32, right.sample_count as u32) as usize;
+        let end = min_u32(range.end as u32, count as u32) as usize;
+        let start = min_u32(range.start as u32, end as u32) as usize;
+        let left_samples = &left.samples[start..end];
+        let right_samples = &right.samples[start..end];
+
+        let mut left_energy = 0.0;
+        let mut right_energy = 0.0;
+        let mut mid_energy = 0.0;
+        let mut side_energy = 0.0;
+        for i in 0..end - start {
+            let (l, r) = (left_samples[i], right_samples[i]);
+            let mid = (l + r) * 0.5;
+            let side = (l - r) * 0.5;
+            left_energy += l * l;
+            right_energy += r * r;
+            mid_energy += mid * mid;
+            side_energy += side * side;
+        }
+
+        let length = max_u32((end - start) as u32, 1) as f64;
+        let total = left_energy + right_energy;
+        StereoStatistics {
+            range: SampleRange::new(start, end),
+            correlation: phase_correlation(left_samples, right_samples),
+            width: if mid_energy + side_energy > 0.0 {
+                side_energy / (mid_energy + side_energy)
+            } else {
+                0.0
+            },
+            balance: if total > 0.0 {
+                (right_energy - left_energy) / total
+            } else {
+                0.0
+            },
+            left_rms: (left_energy / length).sqrt(),
+            right_rms: (right_energy / length).sqrt(),
+            mid_rms: (mid_energy / length).sqrt(),
+            side_rms: (side_energy / length).sqrt(),
+        }
+    }
+
+    /// Statistics for consecutive windows of `window` samples
+    pub fn per_window(left: &Waveform, right: &Waveform, window: usize) -> Vec<StereoStatistics> {
+        let count = min_u32(left.sample_count as u32, right.sample_count as u32) as usize;
+        let window = max_u32(window as u32, 1) as usize;
+        let mut result = Vec::new();
+        let mut start = 0;
+        while start < count {
+            let end = min_u32((start + window) as u32, count as u32) as usize;
+            result.push(StereoStatistics::analyze(
+                left,
+                right,
+                SampleRange::new(start, end),
+            ));
+            start = end;
+        }
+        result
+    }
+}
+
+impl Vectorscope {
+    pub fn new(size: i32) -> Vectorscope {
+        let background = Color::from_u32(Colors::Black as u32);
+        Vectorscope {
+            image: Image::from_color(size, size, background),
+            color: Color::from_u32(0xFF40FF60),
+            background: background,
+            decay: 48,
+            intensity: 96,
+            gain: 1.0,
+        }
+    }
+
+    fn graticule(&mut self) {
+        let size = self.image.width as f64;
+        let (r, g, b, _) = self.color.separate();
+        let axis_color = Color::from_rgba(r, g, b, 40);
+        let center = size / 2.0;
+
+        // Mono, side, left and right axes
+        let axes = [
+            (Vector2::new(center, 0.0), Vector2::new(center, size - 1.0)),
+            (Vector2::new(0.0, center), Vector2::new(size - 1.0, center)),
+            (Vector2::new(0.0, 0.0), Vector2::new(size - 1.0, size - 1.0)),
+            (Vector2::new(size - 1.0, 0.0), Vector2::new(0.0, size - 1.0)),
+        ];
+        for &(ref start, ref end) in axes.iter() {
+            self.image.draw_line(start, end, axis_color);
+        }
+    }
+
+    /// Fades the previous frame and plots `range` of the channel pair on top
+    pub fn update(&mut self, left: &Waveform, right: &Waveform, range: SampleRange) {
+        let size = self.image.width as f64;
+        let (r, g, b, _) = self.background.separate();
+        let fade = Color::from_rgba(r, g, b, self.decay);
+        self.image
+            .draw_rect(&Vector2::ORIGIN, &Vector2::new(size, size), fade);
+        self.graticule();
+
+        let (r, g, b, _) = self.color.separate();
+        let point_color = Color::from_rgba(r, g, b, self.intensity);
+        let count = min_u32(left.sample_count as u32, right.sample_count as u32) as usize;
+        let end = min_u32(range.end as u32, count as u32) as usize;
+        let center = size / 2.0;
+        let scale = self.gain * (size / 2.0 - 1.0) * 0.5;
+
+        for i in min_u32(range.start as u32, end as u32) as usize..end {
+            let (l, r) = (left.samples[i], right.samples[i]);
+            let point = Vector2::new(center + (r - l) * scale, center - (l + r) * scale);
+            self.image.draw_point(&point, point_color);
+        }
+    }
+}
+
+impl Image {
+    /// Horizontal -1..+1 bar growing from the center toward the correlation value
+    pub fn correlation_meter(width: i32, height: i32, value: f64, color: Color) -> Image {
+        let mut image = Image::new(width, height);
+        let (r, g, b, _) = color.separate();
+        let center = width as f64 / 2.0;
+        let x = center + clamp_f64(-1.0, value, 1.0) * (center - 1.0);
+
+        image.draw_rect(
+            &Vector2::ORIGIN,
+            &Vector2::new(width as f64, height as f64),
+            Color::from_rgba(r, g, b, 40),
+        );
+        let (start, end) = if x < center { (x, center) } else { (center, x) };
+        image.draw_rect(
+            &Vector2::new(start, 0.0),
+            &Vector2::new(max_f64(1.0, end - start), height as f64),
+            color,
+        );
+        image.draw_line(
+            &Vector2::new(center, 0.0),
+            &Vector2::new(center, (height - 1) as f64),
+            Color::from_u32(Colors::White as u32),
+        );
+        image
+    }
+}
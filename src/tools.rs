@@ -1,14 +1,17 @@
 #![allow(dead_code)]
 
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 
+#[cfg(windows)]
 use std::os::windows::ffi::OsStrExt;
+#[cfg(windows)]
 use std::ffi::OsStr;
 
 pub struct BitScanResult {
-    found: bool,
-    index: u32,
+    pub found: bool,
+    pub index: u32,
 }
 
 #[inline]
@@ -79,6 +82,7 @@ pub fn to_terabytes(bytes: u64) -> u64 {
     to_gigabytes(bytes) / 1024
 }
 
+#[cfg(windows)]
 #[inline(always)]
 pub fn to_wstring(str: &str) -> Vec<u16> {
     OsStr::new(str)
@@ -113,15 +117,15 @@ pub fn fast_set32(dst: *mut u32, src: u32, len: usize) {
     }
 }
 
-pub fn read_file() -> Vec<u8> {
+pub fn read_file(path: &str) -> io::Result<Vec<u8>> {
     let mut data = Vec::new();
-    let mut f = File::open("../../images/logo.bmp").expect("Unable to open file");
-    f.read_to_end(&mut data).expect("Unable to read data");
-    data
+    let mut f = File::open(path)?;
+    f.read_to_end(&mut data)?;
+    Ok(data)
 }
 
 pub fn db_to_volume(db: f64) -> f64 {
-    (0.05 * db).powf(10.0)
+    10.0f64.powf(0.05 * db)
 }
 
 pub fn volume_to_db(volume: f64) -> f64 {
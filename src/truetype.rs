@@ -0,0 +1,611 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::f64;
+use std::io;
+
+use font::*;
+use math::*;
+use raster::*;
+
+/// Composite glyphs deeper than this are taken as malformed
+const MAX_COMPONENT_DEPTH: usize = 8;
+
+#[derive(Clone, Copy)]
+struct OutlinePoint {
+    x: f64,
+    y: f64,
+    on_curve: bool,
+}
+
+/// Glyph outlines, metrics and kerning read straight from the TrueType tables.
+/// Only `glyf` outlines are supported, CFF (OpenType) fonts are rejected.
+pub struct TrueType<'a> {
+    data: &'a [u8],
+    pub units_per_em: f64,
+    pub glyph_count: usize,
+    pub ascent: i16,
+    pub descent: i16,
+    pub line_gap: i16,
+    long_offsets: bool,
+    metrics_count: usize,
+    hmtx: usize,
+    loca: usize,
+    glyf: usize,
+    cmap: usize,
+    kern: Option<usize>,
+}
+
+fn truncated() -> io::Error {
+    invalid_font("Truncated TrueType data")
+}
+
+fn read_u8(data: &[u8], offset: usize) -> io::Result<u8> {
+    data.get(offset).cloned().ok_or_else(truncated)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> io::Result<u16> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok((bytes[0] as u16) << 8 | bytes[1] as u16),
+        None => Err(truncated()),
+    }
+}
+
+fn read_i16(data: &[u8], offset: usize) -> io::Result<i16> {
+    read_u16(data, offset).map(|value| value as i16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    Ok((read_u16(data, offset)? as u32) << 16 | read_u16(data, offset + 2)? as u32)
+}
+
+/// Signed 2.14 fixed point
+fn read_f2dot14(data: &[u8], offset: usize) -> io::Result<f64> {
+    read_i16(data, offset).map(|value| value as f64 / 16384.0)
+}
+
+impl<'a> TrueType<'a> {
+    pub fn parse(data: &'a [u8]) -> io::Result<TrueType<'a>> {
+        let version = read_u32(data, 0)?;
+        if version == 0x4F54544F {
+            return Err(invalid_font("CFF outlines are not supported"));
+        }
+        if version != 0x00010000 && version != 0x74727565 {
+            return Err(invalid_font("Not a TrueType font"));
+        }
+
+        let table_count = read_u16(data, 4)? as usize;
+        let mut tables = HashMap::new();
+        for i in 0..table_count {
+            let record = 12 + i * 16;
+            let tag = data.get(record..record + 4).ok_or_else(truncated)?;
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            if offset
+                .checked_add(length)
+                .map_or(true, |end| end > data.len())
+            {
+                return Err(truncated());
+            }
+            tables.insert([tag[0], tag[1], tag[2], tag[3]], offset);
+        }
+        let table = |tag: &[u8; 4]| -> io::Result<usize> {
+            tables
+                .get(tag)
+                .cloned()
+                .ok_or_else(|| invalid_font("TrueType font is missing a required table"))
+        };
+
+        let head = table(b"head")?;
+        let maxp = table(b"maxp")?;
+        let hhea = table(b"hhea")?;
+
+        let units_per_em = read_u16(data, head + 18)?;
+        if units_per_em == 0 {
+            return Err(invalid_font("TrueType font has no units per em"));
+        }
+
+        let mut font = TrueType {
+            data: data,
+            units_per_em: units_per_em as f64,
+            glyph_count: read_u16(data, maxp + 4)? as usize,
+            ascent: read_i16(data, hhea + 4)?,
+            descent: read_i16(data, hhea + 6)?,
+            line_gap: read_i16(data, hhea + 8)?,
+            long_offsets: read_i16(data, head + 50)? != 0,
+            metrics_count: read_u16(data, hhea + 34)? as usize,
+            hmtx: table(b"hmtx")?,
+            loca: table(b"loca")?,
+            glyf: table(b"glyf")?,
+            cmap: 0,
+            kern: tables.get(b"kern").cloned(),
+        };
+        if font.metrics_count == 0 {
+            return Err(invalid_font("TrueType font has no horizontal metrics"));
+        }
+        font.cmap = font.find_cmap(table(b"cmap")?)?;
+        Ok(font)
+    }
+
+    /// Prefers the full Unicode subtable (format 12) over the basic plane one (format 4)
+    fn find_cmap(&self, cmap: usize) -> io::Result<usize> {
+        let data = self.data;
+        let count = read_u16(data, cmap + 2)? as usize;
+        let mut best = None;
+        for i in 0..count {
+            let record = cmap + 4 + i * 8;
+            let platform = read_u16(data, record)?;
+            let encoding = read_u16(data, record + 2)?;
+            let subtable = cmap + read_u32(data, record + 4)? as usize;
+            let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+            if !unicode {
+                continue;
+            }
+            match read_u16(data, subtable)? {
+                12 => return Ok(subtable),
+                4 => best = Some(subtable),
+                _ => {}
+            }
+        }
+        best.ok_or_else(|| invalid_font("TrueType font has no Unicode character map"))
+    }
+
+    /// Zero, the missing glyph, when the font does not have the character
+    pub fn glyph_index(&self, character: char) -> usize {
+        self.lookup(character as u32).unwrap_or(0)
+    }
+
+    fn lookup(&self, code: u32) -> io::Result<usize> {
+        let data = self.data;
+        let table = self.cmap;
+        if read_u16(data, table)? == 12 {
+            let groups = read_u32(data, table + 12)? as usize;
+            for i in 0..groups {
+                let group = table + 16 + i * 12;
+                let start = read_u32(data, group)?;
+                let end = read_u32(data, group + 4)?;
+                if code >= start && code <= end {
+                    return Ok((read_u32(data, group + 8)? + code - start) as usize);
+                }
+            }
+            return Ok(0);
+        }
+
+        if code > 0xFFFF {
+            return Ok(0);
+        }
+        let segments = read_u16(data, table + 6)? as usize / 2;
+        let ends = table + 14;
+        let starts = ends + segments * 2 + 2;
+        let deltas = starts + segments * 2;
+        let range_offsets = deltas + segments * 2;
+        for i in 0..segments {
+            if code > read_u16(data, ends + i * 2)? as u32 {
+                continue;
+            }
+            let start = read_u16(data, starts + i * 2)? as u32;
+            if code < start {
+                return Ok(0);
+            }
+            let delta = read_u16(data, deltas + i * 2)? as u32;
+            let range_offset = read_u16(data, range_offsets + i * 2)? as usize;
+            if range_offset == 0 {
+                return Ok(((code + delta) & 0xFFFF) as usize);
+            }
+            let address = range_offsets + i * 2 + range_offset + (code - start) as usize * 2;
+            let glyph = read_u16(data, address)? as u32;
+            if glyph == 0 {
+                return Ok(0);
+            }
+            return Ok(((glyph + delta) & 0xFFFF) as usize);
+        }
+        Ok(0)
+    }
+
+    /// Advance width in font units
+    pub fn advance(&self, glyph: usize) -> i32 {
+        let metric = if glyph < self.metrics_count {
+            glyph
+        } else {
+            self.metrics_count - 1
+        };
+        read_u16(self.data, self.hmtx + metric * 4).unwrap_or(0) as i32
+    }
+
+    fn glyph_range(&self, glyph: usize) -> io::Result<(usize, usize)> {
+        if glyph >= self.glyph_count {
+            return Err(invalid_font("Glyph index out of range"));
+        }
+        let (start, end) = if self.long_offsets {
+            (
+                read_u32(self.data, self.loca + glyph * 4)? as usize,
+                read_u32(self.data, self.loca + glyph * 4 + 4)? as usize,
+            )
+        } else {
+            (
+                read_u16(self.data, self.loca + glyph * 2)? as usize * 2,
+                read_u16(self.data, self.loca + glyph * 2 + 2)? as usize * 2,
+            )
+        };
+        if end < start || self.glyf + end > self.data.len() {
+            return Err(invalid_font("Bad glyph location"));
+        }
+        Ok((self.glyf + start, self.glyf + end))
+    }
+
+    /// Contours in font units, y up
+    fn outline(&self, glyph: usize, depth: usize) -> io::Result<Vec<Vec<OutlinePoint>>> {
+        let (start, end) = self.glyph_range(glyph)?;
+        if start == end {
+            return Ok(Vec::new());
+        }
+        let contour_count = read_i16(self.data, start)?;
+        if contour_count >= 0 {
+            self.simple_outline(start, contour_count as usize)
+        } else if depth < MAX_COMPONENT_DEPTH {
+            self.composite_outline(start, depth)
+        } else {
+            Err(invalid_font("Composite glyph nested too deeply"))
+        }
+    }
+
+    fn simple_outline(
+        &self,
+        start: usize,
+        contour_count: usize,
+    ) -> io::Result<Vec<Vec<OutlinePoint>>> {
+        let data = self.data;
+        let mut ends = Vec::with_capacity(contour_count);
+        for i in 0..contour_count {
+            ends.push(read_u16(data, start + 10 + i * 2)? as usize);
+        }
+        let point_count = match ends.last() {
+            Some(last) => last + 1,
+            None => return Ok(Vec::new()),
+        };
+
+        let instructions = start + 10 + contour_count * 2;
+        let mut offset = instructions + 2 + read_u16(data, instructions)? as usize;
+
+        let mut flags = Vec::with_capacity(point_count);
+        while flags.len() < point_count {
+            let flag = read_u8(data, offset)?;
+            offset += 1;
+            flags.push(flag);
+            if flag & 0x08 != 0 {
+                let repeat = read_u8(data, offset)?;
+                offset += 1;
+                for _ in 0..repeat {
+                    flags.push(flag);
+                }
+            }
+        }
+        flags.truncate(point_count);
+
+        // Short coordinates are a byte with the sign in the flags, long ones a
+        // signed delta. Without either the coordinate repeats.
+        let mut read_coordinates = |short: u8, same: u8| -> io::Result<Vec<f64>> {
+            let mut values = Vec::with_capacity(point_count);
+            let mut value = 0i32;
+            for flag in flags.iter() {
+                if flag & short != 0 {
+                    let delta = read_u8(data, offset)? as i32;
+                    offset += 1;
+                    value += if flag & same != 0 { delta } else { -delta };
+                } else if flag & same == 0 {
+                    value += read_i16(data, offset)? as i32;
+                    offset += 2;
+                }
+                values.push(value as f64);
+            }
+            Ok(values)
+        };
+        let xs = read_coordinates(0x02, 0x10)?;
+        let ys = read_coordinates(0x04, 0x20)?;
+
+        let mut contours = Vec::with_capacity(contour_count);
+        let mut first = 0;
+        for end in ends {
+            if end < first || end >= point_count {
+                return Err(invalid_font("Bad contour end point"));
+            }
+            let contour = (first..end + 1)
+                .map(|i| OutlinePoint {
+                    x: xs[i],
+                    y: ys[i],
+                    on_curve: flags[i] & 0x01 != 0,
+                })
+                .collect();
+            contours.push(contour);
+            first = end + 1;
+        }
+        Ok(contours)
+    }
+
+    /// Components placed by offset and optional scale, point matching is not supported
+    fn composite_outline(&self, start: usize, depth: usize) -> io::Result<Vec<Vec<OutlinePoint>>> {
+        let data = self.data;
+        let mut contours = Vec::new();
+        let mut offset = start + 10;
+        loop {
+            let flags = read_u16(data, offset)?;
+            let component = read_u16(data, offset + 2)? as usize;
+            offset += 4;
+
+            let (dx, dy) = if flags & 0x0001 != 0 {
+                let values = (read_i16(data, offset)?, read_i16(data, offset + 2)?);
+                offset += 4;
+                (values.0 as f64, values.1 as f64)
+            } else {
+                let values = (
+                    read_u8(data, offset)? as i8,
+                    read_u8(data, offset + 1)? as i8,
+                );
+                offset += 2;
+                (values.0 as f64, values.1 as f64)
+            };
+            let (dx, dy) = if flags & 0x0002 != 0 {
+                (dx, dy)
+            } else {
+                (0.0, 0.0)
+            };
+
+            let mut matrix = [1.0, 0.0, 0.0, 1.0];
+            if flags & 0x0008 != 0 {
+                let scale = read_f2dot14(data, offset)?;
+                matrix = [scale, 0.0, 0.0, scale];
+                offset += 2;
+            } else if flags & 0x0040 != 0 {
+                matrix[0] = read_f2dot14(data, offset)?;
+                matrix[3] = read_f2dot14(data, offset + 2)?;
+                offset += 4;
+            } else if flags & 0x0080 != 0 {
+                for (i, value) in matrix.iter_mut().enumerate() {
+                    *value = read_f2dot14(data, offset + i * 2)?;
+                }
+                offset += 8;
+            }
+
+            for contour in self.outline(component, depth + 1)? {
+                let placed = contour
+                    .iter()
+                    .map(|point| OutlinePoint {
+                        x: point.x * matrix[0] + point.y * matrix[2] + dx,
+                        y: point.x * matrix[1] + point.y * matrix[3] + dy,
+                        on_curve: point.on_curve,
+                    })
+                    .collect();
+                contours.push(placed);
+            }
+
+            if flags & 0x0020 == 0 {
+                return Ok(contours);
+            }
+        }
+    }
+
+    /// Horizontal pairs from a format 0 `kern` table, in font units
+    pub fn kerning_pairs(&self) -> Vec<(usize, usize, i32)> {
+        let mut pairs = Vec::new();
+        if let Some(kern) = self.kern {
+            let _ = self.read_kerning(kern, &mut pairs);
+        }
+        pairs
+    }
+
+    fn read_kerning(&self, kern: usize, pairs: &mut Vec<(usize, usize, i32)>) -> io::Result<()> {
+        let data = self.data;
+        if read_u16(data, kern)? != 0 {
+            return Ok(());
+        }
+        let table_count = read_u16(data, kern + 2)? as usize;
+        let mut subtable = kern + 4;
+        for _ in 0..table_count {
+            let length = read_u16(data, subtable + 2)? as usize;
+            let coverage = read_u16(data, subtable + 4)?;
+            let horizontal = coverage & 0x0001 != 0;
+            let minimum = coverage & 0x0002 != 0;
+            let cross_stream = coverage & 0x0004 != 0;
+            if coverage >> 8 == 0 && horizontal && !minimum && !cross_stream {
+                let count = read_u16(data, subtable + 6)? as usize;
+                for i in 0..count {
+                    let pair = subtable + 14 + i * 6;
+                    pairs.push((
+                        read_u16(data, pair)? as usize,
+                        read_u16(data, pair + 2)? as usize,
+                        read_i16(data, pair + 4)? as i32,
+                    ));
+                }
+            }
+            if length == 0 {
+                break;
+            }
+            subtable += length;
+        }
+        Ok(())
+    }
+
+    /// Antialiased coverage of the glyph at `scale` pixels per font unit
+    pub fn rasterize(&self, glyph: usize, scale: f64) -> io::Result<Glyph> {
+        let advance = round_f64_i32(self.advance(glyph) as f64 * scale);
+        let contours = self.outline(glyph, 0)?;
+
+        let mut lines = Vec::new();
+        for contour in contours.iter() {
+            let points: Vec<OutlinePoint> = contour
+                .iter()
+                .map(|point| OutlinePoint {
+                    x: point.x * scale,
+                    y: -point.y * scale,
+                    on_curve: point.on_curve,
+                })
+                .collect();
+            flatten_contour(&points, &mut lines);
+        }
+        if lines.is_empty() {
+            return Ok(Glyph::empty(advance));
+        }
+
+        let (mut min_x, mut min_y) = (f64::MAX, f64::MAX);
+        let (mut max_x, mut max_y) = (f64::MIN, f64::MIN);
+        for &(start, end) in lines.iter() {
+            for point in [start, end].iter() {
+                min_x = min_f64(min_x, point.x);
+                min_y = min_f64(min_y, point.y);
+                max_x = max_f64(max_x, point.x);
+                max_y = max_f64(max_y, point.y);
+            }
+        }
+        let left = floor_f64_i32(min_x);
+        let top = floor_f64_i32(min_y);
+        let width = ceil_f64_i32(max_x) - left;
+        let height = ceil_f64_i32(max_y) - top;
+        if width <= 0 || height <= 0 || width > 4096 || height > 4096 {
+            return Ok(Glyph::empty(advance));
+        }
+
+        let mut coverage = Coverage::new(left, top, width as usize, height as usize);
+        for &(start, end) in lines.iter() {
+            coverage.line(&start, &end);
+        }
+
+        Ok(Glyph {
+            width: width,
+            height: height,
+            left: left,
+            top: top,
+            advance: advance,
+            coverage: coverage.values(FillRule::NonZero),
+        })
+    }
+
+    /// `characters` rendered at `pixel_size` pixels per em, kerning rounded to pixels.
+    /// Characters the font does not have are left to the fallback.
+    pub fn font(&self, pixel_size: f64, characters: &str) -> Font {
+        let scale = pixel_size / self.units_per_em;
+        let ascent = ceil_f64_i32(self.ascent as f64 * scale);
+        let descent = ceil_f64_i32(-self.descent as f64 * scale);
+        let line_gap = round_f64_i32(self.line_gap as f64 * scale);
+        let mut font = Font::new(ascent, descent, line_gap);
+
+        let mut characters_of_glyph: HashMap<usize, Vec<char>> = HashMap::new();
+        for character in characters.chars() {
+            let index = self.glyph_index(character);
+            if index == 0 {
+                continue;
+            }
+            if let Ok(glyph) = self.rasterize(index, scale) {
+                font.glyphs.insert(character, glyph);
+                characters_of_glyph
+                    .entry(index)
+                    .or_insert_with(Vec::new)
+                    .push(character);
+            }
+        }
+
+        for (left, right, value) in self.kerning_pairs() {
+            let amount = round_f64_i32(value as f64 * scale);
+            if amount == 0 {
+                continue;
+            }
+            if let (Some(lefts), Some(rights)) = (
+                characters_of_glyph.get(&left),
+                characters_of_glyph.get(&right),
+            ) {
+                for a in lefts.iter() {
+                    for b in rights.iter() {
+                        font.kerning.insert((*a, *b), amount);
+                    }
+                }
+            }
+        }
+        font
+    }
+}
+
+/// Lines of a quadratic B-spline contour. Two off curve points in a row have an
+/// implied on curve point halfway between them.
+fn flatten_contour(points: &[OutlinePoint], lines: &mut Vec<(Vector2, Vector2)>) {
+    let count = points.len();
+    if count < 2 {
+        return;
+    }
+    let midpoint =
+        |a: &OutlinePoint, b: &OutlinePoint| Vector2::new((a.x + b.x) * 0.5, (a.y + b.y) * 0.5);
+    let position = |point: &OutlinePoint| Vector2::new(point.x, point.y);
+
+    let first_on = match points.iter().position(|point| point.on_curve) {
+        Some(index) => index,
+        None => {
+            // All control points, every midpoint is on the curve
+            let start = midpoint(&points[count - 1], &points[0]);
+            let mut current = start;
+            for i in 0..count {
+                let next = midpoint(&points[i], &points[(i + 1) % count]);
+                flatten_quadratic(&current, &position(&points[i]), &next, lines);
+                current = next;
+            }
+            return;
+        }
+    };
+
+    let start = position(&points[first_on]);
+    let mut current = start;
+    let mut control: Option<Vector2> = None;
+    for step in 1..count + 1 {
+        let point = &points[(first_on + step) % count];
+        let target = position(point);
+        match (point.on_curve, control) {
+            (true, None) => {
+                lines.push((current, target));
+                current = target;
+            }
+            (true, Some(control_point)) => {
+                flatten_quadratic(&current, &control_point, &target, lines);
+                current = target;
+                control = None;
+            }
+            (false, None) => control = Some(target),
+            (false, Some(control_point)) => {
+                let middle = Vector2::new(
+                    (control_point.x + target.x) * 0.5,
+                    (control_point.y + target.y) * 0.5,
+                );
+                flatten_quadratic(&current, &control_point, &middle, lines);
+                current = middle;
+                control = Some(target);
+            }
+        }
+    }
+    if let Some(control_point) = control {
+        flatten_quadratic(&current, &control_point, &start, lines);
+    }
+}
+
+/// Enough segments to stay within about a third of a pixel of the curve
+fn flatten_quadratic(
+    start: &Vector2,
+    control: &Vector2,
+    end: &Vector2,
+    lines: &mut Vec<(Vector2, Vector2)>,
+) {
+    let deviation_x = start.x - 2.0 * control.x + end.x;
+    let deviation_y = start.y - 2.0 * control.y + end.y;
+    let deviation = deviation_x * deviation_x + deviation_y * deviation_y;
+    if deviation < 0.333 {
+        lines.push((*start, *end));
+        return;
+    }
+    let segments = 1 + (3.0 * deviation).sqrt().sqrt().floor() as usize;
+    let mut previous = *start;
+    for i in 1..segments + 1 {
+        let t = i as f64 / segments as f64;
+        let u = 1.0 - t;
+        let point = Vector2::new(
+            u * u * start.x + 2.0 * u * t * control.x + t * t * end.x,
+            u * u * start.y + 2.0 * u * t * control.y + t * t * end.y,
+        );
+        lines.push((previous, point));
+        previous = point;
+    }
+}
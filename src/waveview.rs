@@ -0,0 +1,267 @@
+#![allow(dead_code)]
+
+use audio::*;
+use canvas::*;
+use filter::*;
+use math::*;
+use raster::*;
+use render::*;
+use tools::*;
+
+/// Pixels per sample from which single samples are drawn as dots on the reconstruction
+pub const SAMPLE_VIEW_ZOOM: f64 = 6.0;
+/// Samples on each side of the windowed sinc used between the dots
+const RECONSTRUCTION_TAPS: i64 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WaveMode {
+    /// One line through the samples
+    Line,
+    /// Filled minimum to maximum of every column with the RMS band inside
+    Envelope,
+    /// Envelope of the absolute values mirrored around the center line
+    Rectified,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AmplitudeScale {
+    Linear,
+    /// Decibels from full scale at the edges down to the floor (negative) at the center line
+    Decibel(f64),
+}
+
+/// How `Image::waveform` and `plot_wave` draw the samples
+#[derive(Clone, Copy)]
+pub struct WaveStyle {
+    pub mode: WaveMode,
+    pub scale: AmplitudeScale,
+    pub quality: Quality,
+    pub color: Color,
+    pub rms_color: Color,
+}
+
+impl WaveMode {
+    pub fn next(self) -> WaveMode {
+        match self {
+            WaveMode::Line => WaveMode::Envelope,
+            WaveMode::Envelope => WaveMode::Rectified,
+            WaveMode::Rectified => WaveMode::Line,
+        }
+    }
+}
+
+impl AmplitudeScale {
+    /// Display position of a sample value, -1..1 from the center line to the edges
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            AmplitudeScale::Linear => value,
+            AmplitudeScale::Decibel(floor) => {
+                let magnitude = abs_f64(value);
+                if magnitude <= 0.0 || floor >= 0.0 {
+                    return 0.0;
+                }
+                let level = clamp01_f64(1.0 - volume_to_db(magnitude) / floor);
+                if value < 0.0 {
+                    -level
+                } else {
+                    level
+                }
+            }
+        }
+    }
+}
+
+impl WaveStyle {
+    pub fn new(color: Color, rms_color: Color) -> WaveStyle {
+        WaveStyle {
+            mode: WaveMode::Line,
+            scale: AmplitudeScale::Linear,
+            quality: Quality::AntiAliased,
+            color: color,
+            rms_color: rms_color,
+        }
+    }
+}
+
+/// Band limited value between samples, a Lanczos windowed sinc over the neighbours.
+/// Whole positions give the samples themselves.
+pub fn reconstruct(samples: &[f64], position: f64) -> f64 {
+    let center = position.floor() as i64;
+    let mut sum = 0.0;
+    for index in center - RECONSTRUCTION_TAPS + 1..center + RECONSTRUCTION_TAPS + 1 {
+        if index < 0 || index >= samples.len() as i64 {
+            continue;
+        }
+        let distance = position - index as f64;
+        sum +=
+            samples[index as usize] * sinc(distance) * sinc(distance / RECONSTRUCTION_TAPS as f64);
+    }
+    sum
+}
+
+/// Minimum, maximum and RMS of the samples one column covers, including the samples on
+/// both sides so neighbouring columns connect when there are fewer samples than columns.
+/// Samples from `end` on are left out.
+fn column_statistics(samples: &[f64], from: f64, to: f64, end: usize) -> (f64, f64, f64) {
+    let last_index = min_f64(samples.len() as f64, end as f64) - 1.0;
+    let first = clamp_f64(0.0, from.floor(), last_index) as usize;
+    let last = clamp_f64(0.0, to.ceil(), last_index) as usize;
+    let (mut minimum, mut maximum) = (samples[first], samples[first]);
+    for value in samples[first..last + 1].iter() {
+        minimum = min_f64(minimum, *value);
+        maximum = max_f64(maximum, *value);
+    }
+    (minimum, maximum, rms(&samples[first..last + 1]))
+}
+
+/// Filled area between two edges sampled at the same x positions
+fn plot_band(canvas: &mut Canvas, xs: &[f64], top: &[f64], bottom: &[f64], color: Color) {
+    let mut points = Vec::with_capacity(xs.len() * 2);
+    for (x, y) in xs.iter().zip(top.iter()) {
+        points.push(Vector2::new(*x, *y));
+    }
+    for (x, y) in xs.iter().zip(bottom.iter()).rev() {
+        points.push(Vector2::new(*x, *y));
+    }
+    canvas.polygon(&points, color);
+}
+
+/// `range` samples from `start` across the whole canvas in the mode and amplitude scale
+/// of `style`. Zoomed in past `SAMPLE_VIEW_ZOOM` every mode shows the samples as dots on
+/// their reconstruction.
+pub fn plot_wave(canvas: &mut Canvas, wave: &Waveform, start: u32, range: u32, style: &WaveStyle) {
+    let size = canvas.size();
+    if range == 0 || wave.sample_count == 0 || size.x < 1.0 {
+        return;
+    }
+    let center_y = (size.y as i32 / 2) as f64;
+    let half_height = size.y / 2.0;
+    let scale = style.scale;
+    let to_y = |value: f64| center_y + scale.apply(value) * half_height;
+    let samples = &wave.samples[..];
+
+    let pixels_per_sample = size.x / range as f64;
+    if pixels_per_sample >= SAMPLE_VIEW_ZOOM {
+        plot_samples(canvas, samples, start, range, style, &to_y);
+        return;
+    }
+
+    if style.mode == WaveMode::Line {
+        if scale == AmplitudeScale::Linear {
+            plot_waveform(canvas, wave, start, range, style.color);
+        } else {
+            let end = ((start + range) as usize).min(wave.sample_count);
+            let points: Vec<Vector2> = (start as usize..end)
+                .enumerate()
+                .map(|(index, i)| Vector2::new(index as f64 * pixels_per_sample, to_y(samples[i])))
+                .collect();
+            canvas.polyline(&points, style.color);
+        }
+        return;
+    }
+
+    let columns = size.x.ceil() as usize;
+    let samples_per_column = range as f64 / size.x;
+    let end = (start + range) as usize;
+    let mut edges = [
+        Vec::with_capacity(columns + 2),
+        Vec::with_capacity(columns + 2),
+        Vec::with_capacity(columns + 2),
+        Vec::with_capacity(columns + 2),
+    ];
+    for column in 0..columns {
+        let from = start as f64 + column as f64 * samples_per_column;
+        let to = from + samples_per_column;
+        let (minimum, maximum, rms) = column_statistics(samples, from, to, end);
+        let (low, high, rms_low, rms_high) = match style.mode {
+            WaveMode::Rectified => {
+                let peak = max_f64(abs_f64(minimum), abs_f64(maximum));
+                (-peak, peak, -rms, rms)
+            }
+            _ => (
+                minimum,
+                maximum,
+                max_f64(minimum, -rms),
+                min_f64(maximum, rms),
+            ),
+        };
+        // The bottom edge one pixel lower so a flat signal is as thick as the line
+        for (k, &(a, b)) in [(low, high), (rms_low, rms_high)].iter().enumerate() {
+            let (a, b) = (to_y(a), to_y(b));
+            edges[k * 2].push(min_f64(a, b));
+            edges[k * 2 + 1].push(max_f64(a, b) + 1.0);
+        }
+    }
+
+    // Through the column centers and out to both sides
+    let mut xs = vec![0.0];
+    xs.extend((0..columns).map(|column| column as f64 + 0.5));
+    xs.push(size.x);
+    for edge in edges.iter_mut() {
+        let (first, last) = (edge[0], edge[edge.len() - 1]);
+        edge.insert(0, first);
+        edge.push(last);
+    }
+
+    plot_band(canvas, &xs, &edges[0], &edges[1], style.color);
+    plot_band(canvas, &xs, &edges[2], &edges[3], style.rms_color);
+}
+
+/// Windowed sinc reconstruction with a dot on every sample, mirrored when rectified
+fn plot_samples(
+    canvas: &mut Canvas,
+    samples: &[f64],
+    start: u32,
+    range: u32,
+    style: &WaveStyle,
+    to_y: &Fn(f64) -> f64,
+) {
+    let size = canvas.size();
+    let pixels_per_sample = size.x / range as f64;
+    let rectified = style.mode == WaveMode::Rectified;
+    let limit = (samples.len() - 1) as f64;
+
+    let columns = size.x.ceil() as usize;
+    let mut xs = Vec::with_capacity(columns + 1);
+    let mut values = Vec::with_capacity(columns + 1);
+    for column in 0..columns + 1 {
+        let position = start as f64 + column as f64 / pixels_per_sample;
+        if position > limit {
+            break;
+        }
+        xs.push(column as f64);
+        values.push(reconstruct(samples, position));
+    }
+
+    if rectified {
+        let top: Vec<f64> = values.iter().map(|value| to_y(-abs_f64(*value))).collect();
+        let bottom: Vec<f64> = values
+            .iter()
+            .map(|value| to_y(abs_f64(*value)) + 1.0)
+            .collect();
+        plot_band(canvas, &xs, &top, &bottom, style.color);
+    } else {
+        let points: Vec<Vector2> = xs
+            .iter()
+            .zip(values.iter())
+            .map(|(x, value)| Vector2::new(*x, to_y(*value)))
+            .collect();
+        canvas.polyline(&points, style.color);
+    }
+
+    let radius = clamp_f64(1.5, pixels_per_sample * 0.15, 4.0);
+    let end = ((start + range) as usize + 1).min(samples.len());
+    for (i, sample) in samples.iter().enumerate().take(end).skip(start as usize) {
+        let x = (i - start as usize) as f64 * pixels_per_sample + 0.5;
+        let value = if rectified { abs_f64(*sample) } else { *sample };
+        let mut dots = vec![value];
+        if rectified {
+            dots.push(-value);
+        }
+        for value in dots.iter() {
+            let center = Vector2::new(x, to_y(*value) + 0.5);
+            let dot = ellipse_points(&center, &Vector2::new(radius, radius));
+            canvas.polygon(&dot, style.rms_color);
+        }
+    }
+}
@@ -7,55 +7,30 @@ extern crate winapi;
 
 use std::mem;
 use std::ptr;
-use std::fmt;
-use std::thread;
-use std::time::Duration;
 
+use math::*;
+use platform::*;
 use render::*;
 use tools::*;
 
-pub enum MouseButton {
-    Left,
-    Right,
-    Middle,
-}
-
-impl MouseButton {
-    pub fn as_usize(&self) -> usize {
-        let result = match *self {
-            MouseButton::Left => 0 as usize,
-            MouseButton::Right => 1 as usize,
-            MouseButton::Middle => 2 as usize,
-        };
-        result
-    }
-}
-
-impl fmt::Display for MouseButton {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let printable = match *self {
-            MouseButton::Left => "Left",
-            MouseButton::Right => "Right",
-            MouseButton::Middle => "Middle",
-        };
-        write!(f, "{}", printable)
-    }
-}
-
-pub enum Message {
-    Quit,
-    Silence,
-    KeyDown(u32),
-    KeyUp(u32),
-    MouseUp(MouseButton, i32, i32),
-    MouseDown(MouseButton, i32, i32),
-    MouseMove(i32, i32),
+pub struct Win32Platform {
+    window: winapi::HWND,
+    message: winapi::MSG,
+    info: winapi::BITMAPINFO,
+    /// Boxed so `window_proc` can hold on to it through GWLP_USERDATA
+    state: Box<WindowState>,
+    perf_count_frequency: i64,
+    sleep_is_granular: bool,
+    last_counter: f64,
 }
 
-pub struct WindowBuffer {
-    pub info: winapi::BITMAPINFO,
-    pub image: Image,
+/// Client size as last reported by WM_SIZE
+pub struct WindowState {
+    pub width: i32,
+    pub height: i32,
     pub resized: bool,
+    /// Part of the window was uncovered and needs all of it presented again
+    pub exposed: bool,
 }
 
 pub unsafe extern "system" fn window_proc(
@@ -72,7 +47,7 @@ pub unsafe extern "system" fn window_proc(
         return DefWindowProcW(window, msg, w_param, l_param);
     }
 
-    let buffer: &mut WindowBuffer = mem::transmute(user_data);
+    let state: &mut WindowState = mem::transmute(user_data);
 
     match msg {
         WM_DESTROY => {
@@ -88,10 +63,13 @@ pub unsafe extern "system" fn window_proc(
             let width = (l_param as i32) & 0xffff;
             let height = ((l_param as i32) >> 16) & 0xffff;
             println!("WM_SIZE");
-            resize_dib_section(buffer, width, height);
+            state.width = width;
+            state.height = height;
+            state.resized = true;
         }
 
         WM_PAINT => {
+            state.exposed = true;
             //let mut paint: PAINTSTRUCT = mem::zeroed();
             //let device_context = BeginPaint(window, &mut paint);
             //win32_display_buffer_in_window(&mut app.buffer, &window);
@@ -148,21 +126,17 @@ unsafe fn hide_console_window() {
     }
 }
 
-pub fn resize_dib_section(buffer: &mut WindowBuffer, width: i32, height: i32) {
-    println!("RESIZE {0}, {1}", width, height);
+fn bitmap_info(width: i32, height: i32) -> winapi::BITMAPINFO {
     use winapi::{BITMAPINFOHEADER, BI_RGB};
 
-    buffer.resized = true;
-
-    buffer.image.width = width;
-    buffer.image.height = height;
-
-    buffer.info.bmiHeader.biSize = mem::size_of::<BITMAPINFOHEADER>() as u32;
-    buffer.info.bmiHeader.biWidth = buffer.image.width;
-    buffer.info.bmiHeader.biHeight = -buffer.image.height;
-    buffer.info.bmiHeader.biPlanes = 1;
-    buffer.info.bmiHeader.biBitCount = 32;
-    buffer.info.bmiHeader.biCompression = BI_RGB;
+    let mut info: winapi::BITMAPINFO = unsafe { mem::zeroed() };
+    info.bmiHeader.biSize = mem::size_of::<BITMAPINFOHEADER>() as u32;
+    info.bmiHeader.biWidth = width;
+    info.bmiHeader.biHeight = -height;
+    info.bmiHeader.biPlanes = 1;
+    info.bmiHeader.biBitCount = 32;
+    info.bmiHeader.biCompression = BI_RGB;
+    info
 }
 
 pub fn get_window_dimension(window: winapi::HWND) -> (i32, i32) {
@@ -176,42 +150,6 @@ pub fn get_window_dimension(window: winapi::HWND) -> (i32, i32) {
     }
 }
 
-pub fn sleep(
-    last_counter: &mut i64,
-    target_seconds_per_frame: f64,
-    global_perf_count_frequency: i64,
-    sleep_is_granular: bool,
-) -> f64 {
-    let work_counter = unsafe { get_wall_clock() };
-    let work_seconds_elapsed =
-        get_seconds_elapsed(global_perf_count_frequency, *last_counter, work_counter);
-
-    let mut seconds_elapsed_for_frame = work_seconds_elapsed;
-
-    if seconds_elapsed_for_frame < target_seconds_per_frame {
-        if sleep_is_granular {
-            let sleep_ms: u64 =
-                ((target_seconds_per_frame - seconds_elapsed_for_frame) * 1000.0) as u64;
-            if sleep_ms > 0 {
-                thread::sleep(Duration::from_millis(sleep_ms));
-            }
-        }
-
-        while seconds_elapsed_for_frame < target_seconds_per_frame {
-            seconds_elapsed_for_frame = unsafe {
-                get_seconds_elapsed(global_perf_count_frequency, *last_counter, get_wall_clock())
-            };
-        }
-    }
-
-    let end_counter = unsafe { get_wall_clock() };
-    let delta_time = get_seconds_elapsed(global_perf_count_frequency, *last_counter, end_counter);
-
-    *last_counter = end_counter;
-
-    delta_time
-}
-
 pub fn get_monitor_refresh_rate(window: &winapi::HWND) -> f64 {
     use winapi::VREFRESH;
     use user32::{GetDC, ReleaseDC};
@@ -356,13 +294,11 @@ fn parse_mouse_click(msg: u32, l_param: winapi::LPARAM) -> Message {
     };
 }
 
-pub fn display_buffer_in_window(buffer: &WindowBuffer, window: &winapi::HWND) {
+pub fn display_buffer_in_window(image: &Image, info: &winapi::BITMAPINFO, window: &winapi::HWND) {
     use gdi32::StretchDIBits;
     use winapi::{DIB_RGB_COLORS, SRCCOPY};
     use user32::{GetDC, ReleaseDC};
 
-    let image = &buffer.image;
-
     unsafe {
         let device_context = GetDC(*window);
 
@@ -377,7 +313,7 @@ pub fn display_buffer_in_window(buffer: &WindowBuffer, window: &winapi::HWND) {
             image.width,
             image.height,
             mem::transmute(image.color_data.as_ptr()),
-            &buffer.info,
+            info,
             DIB_RGB_COLORS,
             SRCCOPY,
         );
@@ -386,6 +322,48 @@ pub fn display_buffer_in_window(buffer: &WindowBuffer, window: &winapi::HWND) {
     }
 }
 
+/// Copies only `regions` of `image` to the same place in the window
+pub fn display_regions_in_window(
+    image: &Image,
+    info: &winapi::BITMAPINFO,
+    window: &winapi::HWND,
+    regions: &[Rect2i],
+) {
+    use gdi32::StretchDIBits;
+    use winapi::{DIB_RGB_COLORS, SRCCOPY};
+    use user32::{GetDC, ReleaseDC};
+
+    let everything = Rect2i::new(0, 0, image.width, image.height);
+    unsafe {
+        let device_context = GetDC(*window);
+
+        for region in regions.iter() {
+            let region = region.intersect(&everything);
+            if region.is_empty() {
+                continue;
+            }
+            // The source rectangle of a top down DIB still counts rows from the bottom.
+            StretchDIBits(
+                device_context,
+                region.min_x,
+                region.min_y,
+                region.width(),
+                region.height(),
+                region.min_x,
+                image.height - region.max_y,
+                region.width(),
+                region.height(),
+                mem::transmute(image.color_data.as_ptr()),
+                info,
+                DIB_RGB_COLORS,
+                SRCCOPY,
+            );
+        }
+
+        ReleaseDC(*window, device_context);
+    }
+}
+
 #[inline]
 pub unsafe fn get_wall_clock() -> i64 {
     use kernel32::QueryPerformanceCounter;
@@ -403,3 +381,105 @@ pub fn get_seconds_elapsed(global_perf_count_frequency: i64, start: i64, end: i6
     let result = (end - start) as f64 / global_perf_count_frequency as f64;
     return result;
 }
+
+impl Win32Platform {
+    pub fn new() -> Win32Platform {
+        use kernel32::QueryPerformanceFrequency;
+        use winapi::TIMERR_NOERROR;
+        use winmm::timeBeginPeriod;
+
+        let mut perf_count_frequency: i64 = 60;
+        if unsafe { QueryPerformanceFrequency(&mut perf_count_frequency) } == 0 {
+            perf_count_frequency = 60;
+        }
+
+        let desired_scheduler_ms: u32 = 1;
+        let sleep_is_granular = unsafe { timeBeginPeriod(desired_scheduler_ms) == TIMERR_NOERROR };
+
+        Win32Platform {
+            window: ptr::null_mut(),
+            message: unsafe { mem::zeroed() },
+            info: unsafe { mem::zeroed() },
+            state: Box::new(WindowState {
+                width: 0,
+                height: 0,
+                resized: false,
+                exposed: true,
+            }),
+            perf_count_frequency: perf_count_frequency,
+            sleep_is_granular: sleep_is_granular,
+            last_counter: 0.0,
+        }
+    }
+}
+
+impl Platform for Win32Platform {
+    fn create_window(&mut self, title: &str, _width: i32, _height: i32) -> bool {
+        use winapi::GWLP_USERDATA;
+        use user32::SetWindowLongPtrW;
+
+        self.window = unsafe { create_window(String::from("ShadeClass"), String::from(title)) };
+        if self.window == ptr::null_mut() {
+            return false;
+        }
+
+        let (width, height) = get_window_dimension(self.window);
+        self.state.width = width;
+        self.state.height = height;
+        unsafe {
+            let state: &mut WindowState = &mut self.state;
+            SetWindowLongPtrW(self.window, GWLP_USERDATA, mem::transmute(state));
+        }
+        self.last_counter = self.wall_clock();
+        true
+    }
+
+    fn dimensions(&self) -> (i32, i32) {
+        (self.state.width, self.state.height)
+    }
+
+    fn poll_message(&mut self) -> Message {
+        if self.state.resized {
+            self.state.resized = false;
+            return Message::Resize(self.state.width, self.state.height);
+        }
+        process_pending_messages(&mut self.message)
+    }
+
+    fn present(&mut self, image: &Image) {
+        self.info = bitmap_info(image.width, image.height);
+        display_buffer_in_window(image, &self.info, &self.window);
+        self.state.exposed = false;
+    }
+
+    fn present_regions(&mut self, image: &Image, regions: &[Rect2i]) {
+        if self.state.exposed {
+            self.present(image);
+        } else if !regions.is_empty() {
+            self.info = bitmap_info(image.width, image.height);
+            display_regions_in_window(image, &self.info, &self.window, regions);
+        }
+    }
+
+    fn wall_clock(&self) -> f64 {
+        let counter = unsafe { get_wall_clock() };
+        get_seconds_elapsed(self.perf_count_frequency, 0, counter)
+    }
+
+    fn refresh_rate(&self) -> f64 {
+        get_monitor_refresh_rate(&self.window)
+    }
+
+    fn wait_for_frame(&mut self, target_seconds_per_frame: f64) -> f64 {
+        let mut last_counter = self.last_counter;
+        let sleep_is_granular = self.sleep_is_granular;
+        let delta_time = wait_until(
+            self,
+            &mut last_counter,
+            target_seconds_per_frame,
+            sleep_is_granular,
+        );
+        self.last_counter = last_counter;
+        delta_time
+    }
+}
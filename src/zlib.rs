@@ -0,0 +1,927 @@
+#![allow(dead_code)]
+
+use std::io;
+
+const MAX_BITS: usize = 15;
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+const BLOCK_TOKENS: usize = 16384;
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // 5552 bytes is the most that can be summed before b overflows.
+    for chunk in data.chunks(5552) {
+        for byte in chunk.iter() {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for n in 0..256 {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        table[n] = c;
+    }
+    table
+}
+
+/// Continues a CRC-32 over more data, start with 0
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let table = crc_table();
+    let mut c = !crc;
+    for byte in data.iter() {
+        c = table[((c ^ *byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// zlib stream: two byte header, deflate data and the Adler-32 of the input
+pub fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut output = vec![0x78, 0x9C];
+    output.extend(deflate(data, level));
+    let checksum = adler32(data);
+    output.push((checksum >> 24) as u8);
+    output.push((checksum >> 16) as u8);
+    output.push((checksum >> 8) as u8);
+    output.push(checksum as u8);
+    output
+}
+
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(invalid_data("zlib stream too short"));
+    }
+    let (cmf, flg) = (data[0] as u32, data[1] as u32);
+    if cmf & 0x0F != 8 || cmf >> 4 > 7 || (cmf * 256 + flg) % 31 != 0 {
+        return Err(invalid_data("Invalid zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(invalid_data("zlib preset dictionaries are not supported"));
+    }
+
+    let (output, used) = inflate_with_length(&data[2..])?;
+    let end = 2 + used;
+    if end + 4 > data.len() {
+        return Err(invalid_data("zlib stream is missing its checksum"));
+    }
+    let expected = ((data[end] as u32) << 24)
+        | ((data[end + 1] as u32) << 16)
+        | ((data[end + 2] as u32) << 8)
+        | data[end + 3] as u32;
+    if adler32(&output) != expected {
+        return Err(invalid_data("zlib checksum mismatch"));
+    }
+    Ok(output)
+}
+
+//
+// Inflate
+//
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data: data,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.bit_count < count {
+            if self.position >= self.data.len() {
+                return Err(invalid_data("Unexpected end of deflate data"));
+            }
+            self.bit_buffer |= (self.data[self.position] as u32) << self.bit_count;
+            self.position += 1;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// Canonical Huffman decoding table, symbols sorted by code
+struct HuffmanDecoder {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl HuffmanDecoder {
+    fn new(lengths: &[u8]) -> io::Result<HuffmanDecoder> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for length in lengths.iter() {
+            counts[*length as usize] += 1;
+        }
+
+        let mut left: i32 = 1;
+        for length in 1..MAX_BITS + 1 {
+            left <<= 1;
+            left -= counts[length] as i32;
+            if left < 0 {
+                return Err(invalid_data("Over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..MAX_BITS + 1 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Ok(HuffmanDecoder {
+            counts: counts,
+            symbols: symbols,
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<usize> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..MAX_BITS + 1 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid_data("Invalid Huffman code"))
+    }
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literal = vec![0u8; 288];
+    for symbol in 0..288 {
+        literal[symbol] = match symbol {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8,
+        };
+    }
+    (literal, vec![5u8; 30])
+}
+
+/// Raw deflate data, without a zlib header
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    inflate_with_length(data).map(|(output, _)| output)
+}
+
+/// Decompressed data and the number of input bytes it used
+fn inflate_with_length(data: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+    let mut reader = BitReader::new(data);
+    let mut output = Vec::with_capacity(data.len() * 4);
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let position = reader.position;
+                if position + 4 > data.len() {
+                    return Err(invalid_data("Unexpected end of stored block"));
+                }
+                let length = data[position] as usize | (data[position + 1] as usize) << 8;
+                let complement = data[position + 2] as usize | (data[position + 3] as usize) << 8;
+                if length != !complement & 0xFFFF {
+                    return Err(invalid_data("Stored block length mismatch"));
+                }
+                let start = position + 4;
+                if start + length > data.len() {
+                    return Err(invalid_data("Unexpected end of stored block"));
+                }
+                output.extend_from_slice(&data[start..start + length]);
+                reader.position = start + length;
+            }
+            1 => {
+                let (literal, distance) = fixed_lengths();
+                inflate_block(
+                    &mut reader,
+                    &mut output,
+                    &HuffmanDecoder::new(&literal)?,
+                    &HuffmanDecoder::new(&distance)?,
+                )?;
+            }
+            2 => {
+                let (literal, distance) = read_dynamic_lengths(&mut reader)?;
+                inflate_block(
+                    &mut reader,
+                    &mut output,
+                    &HuffmanDecoder::new(&literal)?,
+                    &HuffmanDecoder::new(&distance)?,
+                )?;
+            }
+            _ => return Err(invalid_data("Invalid deflate block type")),
+        }
+        if last {
+            break;
+        }
+    }
+    Ok((output, reader.position))
+}
+
+fn read_dynamic_lengths(reader: &mut BitReader) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid_data("Too many deflate codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for i in 0..code_length_count {
+        code_lengths[CODE_LENGTH_ORDER[i]] = reader.bits(3)? as u8;
+    }
+    let code_length_decoder = HuffmanDecoder::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_decoder.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0...15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(invalid_data("Repeated length without a previous one"));
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(invalid_data("Too many code lengths"));
+        }
+        for _ in 0..repeat {
+            lengths[index] = value;
+            index += 1;
+        }
+    }
+
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(invalid_data("Missing end of block code"));
+    }
+    let distance = lengths.split_off(literal_count);
+    Ok((lengths, distance))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literal: &HuffmanDecoder,
+    distance: &HuffmanDecoder,
+) -> io::Result<()> {
+    loop {
+        let symbol = literal.decode(reader)?;
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == END_OF_BLOCK {
+            return Ok(());
+        } else {
+            let index = symbol - 257;
+            if index >= 29 {
+                return Err(invalid_data("Invalid length code"));
+            }
+            let length =
+                LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+            let index = distance.decode(reader)?;
+            if index >= 30 {
+                return Err(invalid_data("Invalid distance code"));
+            }
+            let offset =
+                DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+            if offset > output.len() {
+                return Err(invalid_data("Distance too far back"));
+            }
+
+            // Matches may overlap what they copy, go byte by byte.
+            let start = output.len() - offset;
+            for i in 0..length {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        }
+    }
+}
+
+//
+// Deflate
+//
+
+struct BitWriter {
+    output: Vec<u8>,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            output: Vec::new(),
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.output.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.bit_count > 0 {
+            self.output.push(self.bit_buffer as u8);
+        }
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match(u16, u16),
+}
+
+fn length_symbol(length: usize) -> usize {
+    let mut index = 28;
+    while LENGTH_BASE[index] as usize > length {
+        index -= 1;
+    }
+    index
+}
+
+fn distance_symbol(distance: usize) -> usize {
+    let mut index = 29;
+    while DISTANCE_BASE[index] as usize > distance {
+        index -= 1;
+    }
+    index
+}
+
+/// Huffman code lengths no longer than `limit` for the given symbol frequencies.
+/// At least two symbols always get a code so decoders see a complete tree.
+fn huffman_lengths(frequencies: &[u32], limit: usize) -> Vec<u8> {
+    let mut used: Vec<usize> = (0..frequencies.len())
+        .filter(|&symbol| frequencies[symbol] > 0)
+        .collect();
+    let mut lengths = vec![0u8; frequencies.len()];
+    for symbol in 0..frequencies.len() {
+        if used.len() >= 2 {
+            break;
+        }
+        if frequencies[symbol] == 0 {
+            used.push(symbol);
+        }
+    }
+    if used.len() < 2 {
+        for symbol in used.iter() {
+            lengths[*symbol] = 1;
+        }
+        return lengths;
+    }
+
+    // Two queue Huffman construction over symbols sorted by frequency.
+    used.sort_by_key(|&symbol| (frequencies[symbol], symbol));
+    let count = used.len();
+    let mut weights: Vec<u64> = used
+        .iter()
+        .map(|&symbol| frequencies[symbol] as u64)
+        .collect();
+    let mut parents = vec![0usize; 2 * count - 1];
+    let mut leaf = 0;
+    let mut node = count;
+    let mut next_internal = count;
+    weights.resize(2 * count - 1, 0);
+    while node < 2 * count - 1 {
+        let mut children = [0usize; 2];
+        for child in children.iter_mut() {
+            if leaf < count && (next_internal >= node || weights[leaf] <= weights[next_internal]) {
+                *child = leaf;
+                leaf += 1;
+            } else {
+                *child = next_internal;
+                next_internal += 1;
+            }
+        }
+        weights[node] = weights[children[0]] + weights[children[1]];
+        parents[children[0]] = node;
+        parents[children[1]] = node;
+        node += 1;
+    }
+
+    let root = 2 * count - 2;
+    let mut depths = vec![0usize; 2 * count - 1];
+    for n in (0..root).rev() {
+        depths[n] = depths[parents[n]] + 1;
+    }
+
+    // Fold codes longer than the limit back in while keeping the Kraft sum at one.
+    let mut length_counts = vec![0usize; count + 1];
+    for n in 0..count {
+        length_counts[depths[n]] += 1;
+    }
+    let mut longest = count;
+    while longest > limit {
+        while length_counts[longest] > 0 {
+            let mut shorter = longest - 2;
+            while length_counts[shorter] == 0 {
+                shorter -= 1;
+            }
+            length_counts[longest] -= 2;
+            length_counts[longest - 1] += 1;
+            length_counts[shorter + 1] += 2;
+            length_counts[shorter] -= 1;
+        }
+        longest -= 1;
+    }
+
+    // The most frequent symbols take the shortest codes.
+    let mut n = count;
+    for length in 1..longest + 1 {
+        for _ in 0..length_counts[length] {
+            n -= 1;
+            lengths[used[n]] = length as u8;
+        }
+    }
+    lengths
+}
+
+/// Canonical codes for the lengths, bit reversed for the LSB first writer
+fn huffman_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut counts = [0u32; MAX_BITS + 1];
+    for length in lengths.iter() {
+        counts[*length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next = [0u32; MAX_BITS + 2];
+    let mut code = 0;
+    for length in 1..MAX_BITS + 1 {
+        code = (code + counts[length - 1]) << 1;
+        next[length] = code;
+    }
+
+    let mut codes = vec![0u32; lengths.len()];
+    for (symbol, length) in lengths.iter().enumerate() {
+        let length = *length as usize;
+        if length != 0 {
+            let code = next[length];
+            next[length] += 1;
+            let mut reversed = 0;
+            for bit in 0..length {
+                reversed |= ((code >> bit) & 1) << (length - 1 - bit);
+            }
+            codes[symbol] = reversed;
+        }
+    }
+    codes
+}
+
+/// Finds matches with hash chains over the last 32 KB and one step lazy evaluation
+fn find_tokens(data: &[u8], max_chain: usize) -> Vec<Token> {
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+    let hash_size = 1 << HASH_BITS;
+    let mut head = vec![-1i32; hash_size];
+    let mut previous = vec![-1i32; WINDOW_SIZE];
+
+    let hash = |position: usize| {
+        let value = (data[position] as usize) << 16
+            | (data[position + 1] as usize) << 8
+            | data[position + 2] as usize;
+        (value.wrapping_mul(2654435761) >> 8) & (hash_size - 1)
+    };
+
+    let longest_match = |head: &Vec<i32>, previous: &Vec<i32>, position: usize| {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if position + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let max_length = if data.len() - position < MAX_MATCH {
+            data.len() - position
+        } else {
+            MAX_MATCH
+        };
+        let mut candidate = head[hash(position)];
+        let mut chain = max_chain;
+        while candidate >= 0 && chain > 0 {
+            let start = candidate as usize;
+            if position - start > WINDOW_SIZE {
+                break;
+            }
+            if data[start + best_length] == data[position + best_length] {
+                let mut length = 0;
+                while length < max_length && data[start + length] == data[position + length] {
+                    length += 1;
+                }
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - start;
+                    if length == max_length {
+                        break;
+                    }
+                }
+            }
+            candidate = previous[start % WINDOW_SIZE];
+            chain -= 1;
+        }
+        if best_length >= MIN_MATCH {
+            (best_length, best_distance)
+        } else {
+            (0, 0)
+        }
+    };
+
+    let insert = |head: &mut Vec<i32>, previous: &mut Vec<i32>, position: usize| {
+        if position + MIN_MATCH <= data.len() {
+            let h = hash(position);
+            previous[position % WINDOW_SIZE] = head[h];
+            head[h] = position as i32;
+        }
+    };
+
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = longest_match(&head, &previous, position);
+        insert(&mut head, &mut previous, position);
+        if length == 0 {
+            tokens.push(Token::Literal(data[position]));
+            position += 1;
+            continue;
+        }
+
+        // A longer match one byte later is worth a literal.
+        let (next_length, _) = longest_match(&head, &previous, position + 1);
+        if next_length > length {
+            tokens.push(Token::Literal(data[position]));
+            position += 1;
+            continue;
+        }
+
+        tokens.push(Token::Match(length as u16, distance as u16));
+        for i in 1..length {
+            insert(&mut head, &mut previous, position + i);
+        }
+        position += length;
+    }
+    tokens
+}
+
+fn token_frequencies(tokens: &[Token]) -> (Vec<u32>, Vec<u32>) {
+    let mut literal = vec![0u32; 286];
+    let mut distance = vec![0u32; 30];
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(byte) => literal[byte as usize] += 1,
+            Token::Match(length, offset) => {
+                literal[257 + length_symbol(length as usize)] += 1;
+                distance[distance_symbol(offset as usize)] += 1;
+            }
+        }
+    }
+    literal[END_OF_BLOCK] += 1;
+    (literal, distance)
+}
+
+/// Bits the tokens take with the given code lengths
+fn encoded_size(
+    literal: &[u32],
+    distance: &[u32],
+    literal_lengths: &[u8],
+    distance_lengths: &[u8],
+) -> usize {
+    let mut bits = 0;
+    for symbol in 0..literal.len() {
+        bits += literal[symbol] as usize * literal_lengths[symbol] as usize;
+        if symbol > END_OF_BLOCK {
+            bits += literal[symbol] as usize * LENGTH_EXTRA[symbol - 257] as usize;
+        }
+    }
+    for symbol in 0..distance.len() {
+        bits += distance[symbol] as usize
+            * (distance_lengths[symbol] as usize + DISTANCE_EXTRA[symbol] as usize);
+    }
+    bits
+}
+
+/// Run length coded code lengths as (symbol, extra bits value)
+fn run_length_code(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == value {
+            run += 1;
+        }
+
+        if value == 0 && run >= 3 {
+            let run = if run > 138 { 138 } else { run };
+            if run >= 11 {
+                result.push((18, (run - 11) as u8));
+            } else {
+                result.push((17, (run - 3) as u8));
+            }
+            i += run;
+        } else if value != 0 && run >= 4 {
+            result.push((value, 0));
+            let run = if run - 1 > 6 { 6 } else { run - 1 };
+            result.push((16, (run - 3) as u8));
+            i += run + 1;
+        } else {
+            result.push((value, 0));
+            i += 1;
+        }
+    }
+    result
+}
+
+fn write_tokens(
+    writer: &mut BitWriter,
+    tokens: &[Token],
+    literal_lengths: &[u8],
+    distance_lengths: &[u8],
+) {
+    let literal_codes = huffman_codes(literal_lengths);
+    let distance_codes = huffman_codes(distance_lengths);
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(byte) => writer.write(
+                literal_codes[byte as usize],
+                literal_lengths[byte as usize] as u32,
+            ),
+            Token::Match(length, offset) => {
+                let index = length_symbol(length as usize);
+                writer.write(
+                    literal_codes[257 + index],
+                    literal_lengths[257 + index] as u32,
+                );
+                writer.write(
+                    length as u32 - LENGTH_BASE[index] as u32,
+                    LENGTH_EXTRA[index] as u32,
+                );
+                let index = distance_symbol(offset as usize);
+                writer.write(distance_codes[index], distance_lengths[index] as u32);
+                writer.write(
+                    offset as u32 - DISTANCE_BASE[index] as u32,
+                    DISTANCE_EXTRA[index] as u32,
+                );
+            }
+        }
+    }
+    writer.write(
+        literal_codes[END_OF_BLOCK],
+        literal_lengths[END_OF_BLOCK] as u32,
+    );
+}
+
+/// Writes one block as stored, fixed or dynamic Huffman, whichever is smallest
+fn write_block(writer: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let (literal, distance) = token_frequencies(tokens);
+
+    let literal_lengths = huffman_lengths(&literal, MAX_BITS);
+    let distance_lengths = huffman_lengths(&distance, MAX_BITS);
+    let mut literal_count = 286;
+    while literal_count > 257 && literal_lengths[literal_count - 1] == 0 {
+        literal_count -= 1;
+    }
+    let mut distance_count = 30;
+    while distance_count > 1 && distance_lengths[distance_count - 1] == 0 {
+        distance_count -= 1;
+    }
+
+    let mut all_lengths = literal_lengths[..literal_count].to_vec();
+    all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+    let runs = run_length_code(&all_lengths);
+    let mut code_length_frequencies = vec![0u32; 19];
+    for &(symbol, _) in runs.iter() {
+        code_length_frequencies[symbol as usize] += 1;
+    }
+    let code_length_lengths = huffman_lengths(&code_length_frequencies, 7);
+    let mut code_length_count = 19;
+    while code_length_count > 4
+        && code_length_lengths[CODE_LENGTH_ORDER[code_length_count - 1]] == 0
+    {
+        code_length_count -= 1;
+    }
+
+    let extra_bits = |symbol: u8| match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    };
+    let mut dynamic_bits = 5 + 5 + 4 + 3 * code_length_count;
+    for &(symbol, _) in runs.iter() {
+        dynamic_bits += code_length_lengths[symbol as usize] as usize + extra_bits(symbol);
+    }
+    dynamic_bits += encoded_size(&literal, &distance, &literal_lengths, &distance_lengths);
+
+    let (fixed_literal, fixed_distance) = fixed_lengths();
+    let fixed_bits = encoded_size(&literal, &distance, &fixed_literal, &fixed_distance);
+    let stored_bits = (raw.len() + 4 * (raw.len() / 65535 + 1)) * 8;
+
+    if stored_bits <= fixed_bits && stored_bits <= dynamic_bits {
+        write_stored(writer, raw, last);
+    } else if fixed_bits <= dynamic_bits {
+        writer.write(last as u32, 1);
+        writer.write(1, 2);
+        write_tokens(writer, tokens, &fixed_literal, &fixed_distance);
+    } else {
+        writer.write(last as u32, 1);
+        writer.write(2, 2);
+        writer.write((literal_count - 257) as u32, 5);
+        writer.write((distance_count - 1) as u32, 5);
+        writer.write((code_length_count - 4) as u32, 4);
+        for i in 0..code_length_count {
+            writer.write(code_length_lengths[CODE_LENGTH_ORDER[i]] as u32, 3);
+        }
+        let code_length_codes = huffman_codes(&code_length_lengths);
+        for &(symbol, extra) in runs.iter() {
+            writer.write(
+                code_length_codes[symbol as usize],
+                code_length_lengths[symbol as usize] as u32,
+            );
+            writer.write(extra as u32, extra_bits(symbol) as u32);
+        }
+        write_tokens(writer, tokens, &literal_lengths, &distance_lengths);
+    }
+}
+
+fn write_stored(writer: &mut BitWriter, raw: &[u8], last: bool) {
+    let chunks: Vec<&[u8]> = if raw.is_empty() {
+        vec![raw]
+    } else {
+        raw.chunks(65535).collect()
+    };
+    for (index, chunk) in chunks.iter().enumerate() {
+        writer.write((last && index == chunks.len() - 1) as u32, 1);
+        writer.write(0, 2);
+        writer.flush();
+        let length = chunk.len() as u32;
+        writer.write(length, 16);
+        writer.write(!length & 0xFFFF, 16);
+        writer.output.extend_from_slice(chunk);
+    }
+}
+
+/// Raw deflate data. Level 0 only stores, 1 to 9 search longer match chains.
+pub fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    if level == 0 {
+        write_stored(&mut writer, data, true);
+        writer.flush();
+        return writer.output;
+    }
+
+    let level = if level > 9 { 9 } else { level };
+    let tokens = find_tokens(data, 4 << level);
+    if tokens.is_empty() {
+        write_block(&mut writer, &tokens, data, true);
+    }
+
+    let mut start = 0;
+    let mut raw_start = 0;
+    while start < tokens.len() {
+        let end = if tokens.len() - start > BLOCK_TOKENS {
+            start + BLOCK_TOKENS
+        } else {
+            tokens.len()
+        };
+        let mut raw_end = raw_start;
+        for token in tokens[start..end].iter() {
+            raw_end += match *token {
+                Token::Literal(_) => 1,
+                Token::Match(length, _) => length as usize,
+            };
+        }
+        write_block(
+            &mut writer,
+            &tokens[start..end],
+            &data[raw_start..raw_end],
+            end == tokens.len(),
+        );
+        start = end;
+        raw_start = raw_end;
+    }
+    writer.flush();
+    writer.output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32_update(crc32(b"12345"), b"6789"), 0xCBF43926);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        let mut text = Vec::new();
+        for i in 0..20000u32 {
+            text.extend_from_slice(format!("{0} ", i % 97).as_bytes());
+        }
+        // A bit of noise that does not repeat
+        let mut state = 12345u32;
+        for _ in 0..5000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            text.push((state >> 24) as u8);
+        }
+        for level in 0..10 {
+            let compressed = compress(&text, level);
+            assert!(decompress(&compressed).unwrap() == text);
+        }
+        assert!(decompress(&compress(b"", 6)).unwrap().is_empty());
+        assert!(decompress(&compress(b"a", 6)).unwrap() == b"a");
+    }
+
+    #[test]
+    fn corrupt_stream_is_an_error() {
+        let mut compressed = compress(b"hello hello hello", 6);
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(decompress(&compressed).is_err());
+    }
+}